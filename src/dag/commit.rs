@@ -1,5 +1,6 @@
 //! Commit data structures and operations
 
+use crate::util::codec::TextCodec;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
@@ -33,6 +34,10 @@ pub struct Commit {
     /// Size of content in bytes
     pub size: usize,
 
+    /// Text codec the content was posted with, `None` for plain text
+    #[serde(default)]
+    pub encoding: Option<TextCodec>,
+
     /// Whether this is a head commit
     pub is_head: bool,
 }
@@ -55,6 +60,7 @@ impl Commit {
             author,
             mime,
             size,
+            encoding: None,
             is_head: false,
         }
     }
@@ -72,3 +78,19 @@ pub struct ContentRef {
     /// Total size
     pub size: usize,
 }
+
+/// A single chunk in a commit's content manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// Tweet ID holding this chunk
+    pub id: TweetId,
+
+    /// Position of the chunk within the content
+    pub index: usize,
+
+    /// Size of the chunk tweet in bytes, as posted
+    pub size: usize,
+
+    /// Hash of the chunk tweet as posted (blake3)
+    pub hash: Hash,
+}
@@ -1,11 +1,23 @@
 //! Diff operations between commits
+//!
+//! Text content (by MIME type) is diffed line by line, everything else byte
+//! by byte. Both use the Myers O(ND) algorithm, and the resulting ops can be
+//! replayed exactly with [`apply_diff`].
 
 use crate::dag::commit::Commit;
-use crate::error::Result;
+use crate::error::{Result, XFilesError};
+use crate::util::encoding::{is_text_mime, parse_delta_mime};
+use crate::util::hash::{compute_hash, verify_hash};
+
+/// Edit distance beyond which the diff gives up and replaces the whole
+/// changed region, bounding time on unrelated inputs
+const MAX_EDIT_DISTANCE: usize = 4096;
 
 /// Represents a difference between two commits
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DiffOp {
+    /// Content is unchanged
+    Equal(Vec<u8>),
     /// Content was added
     Add(Vec<u8>),
     /// Content was removed
@@ -14,12 +26,569 @@ pub enum DiffOp {
     Modify { old: Vec<u8>, new: Vec<u8> },
 }
 
+/// Compute the diff between two versions of content
+///
+/// Text MIME types are diffed by line, all others by byte.
+pub fn diff_content(old: &[u8], new: &[u8], mime: &str) -> Vec<DiffOp> {
+    if is_text_mime(mime) {
+        let old_lines = split_lines(old);
+        let new_lines = split_lines(new);
+        build_ops(&old_lines, &new_lines)
+    } else {
+        let old_bytes: Vec<&[u8]> = old.chunks(1).collect();
+        let new_bytes: Vec<&[u8]> = new.chunks(1).collect();
+        build_ops(&old_bytes, &new_bytes)
+    }
+}
+
 /// Compute the diff between two commits
-pub fn diff_commits(_old: &Commit, _new: &Commit) -> Result<Vec<DiffOp>> {
-    todo!("Implement commit diff")
+///
+/// Both contents are verified against their commit hashes first.
+pub fn diff_commits(
+    old: &Commit,
+    old_content: &[u8],
+    new: &Commit,
+    new_content: &[u8],
+) -> Result<Vec<DiffOp>> {
+    for (commit, content) in [(old, old_content), (new, new_content)] {
+        if !verify_hash(content, &commit.hash) {
+            return Err(XFilesError::HashMismatch {
+                expected: commit.hash.clone(),
+                actual: compute_hash(content),
+            });
+        }
+    }
+
+    // Delta commits carry the content type as a parameter
+    let mime = parse_delta_mime(&new.mime).unwrap_or(&new.mime);
+
+    Ok(diff_content(old_content, new_content, mime))
 }
 
 /// Apply a diff to content
-pub fn apply_diff(_content: &[u8], _diff: &[DiffOp]) -> Result<Vec<u8>> {
-    todo!("Implement diff application")
+///
+/// Fails if the unchanged or removed content recorded in the diff does not
+/// match `content`.
+pub fn apply_diff(content: &[u8], diff: &[DiffOp]) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(content.len());
+    let mut pos = 0;
+
+    let consume = |expected: &[u8], pos: &mut usize| -> Result<()> {
+        let end = *pos + expected.len();
+        if content.get(*pos..end) != Some(expected) {
+            return Err(XFilesError::InvalidDiff(format!(
+                "content does not match diff at offset {}",
+                pos
+            )));
+        }
+        *pos = end;
+        Ok(())
+    };
+
+    for op in diff {
+        match op {
+            DiffOp::Equal(data) => {
+                consume(data, &mut pos)?;
+                output.extend_from_slice(data);
+            }
+            DiffOp::Add(data) => output.extend_from_slice(data),
+            DiffOp::Remove(data) => consume(data, &mut pos)?,
+            DiffOp::Modify { old, new } => {
+                consume(old, &mut pos)?;
+                output.extend_from_slice(new);
+            }
+        }
+    }
+
+    if pos != content.len() {
+        return Err(XFilesError::InvalidDiff(format!(
+            "diff ends at offset {} but content has {} bytes",
+            pos,
+            content.len()
+        )));
+    }
+
+    Ok(output)
+}
+
+/// Encode a diff as a compact patch against its base content
+///
+/// Unchanged and removed content is stored as a length only, so the patch
+/// can only be decoded against the exact base it was computed from. Ops are
+/// written as `=<len>;` (keep), `-<len>;` (drop) and `+<len>:<bytes>` (add).
+pub fn encode_patch(diff: &[DiffOp]) -> Vec<u8> {
+    let mut patch = Vec::new();
+
+    for op in diff {
+        match op {
+            DiffOp::Equal(data) => patch.extend_from_slice(format!("={};", data.len()).as_bytes()),
+            DiffOp::Remove(data) => patch.extend_from_slice(format!("-{};", data.len()).as_bytes()),
+            DiffOp::Add(data) => {
+                patch.extend_from_slice(format!("+{}:", data.len()).as_bytes());
+                patch.extend_from_slice(data);
+            }
+            DiffOp::Modify { old, new } => {
+                patch.extend_from_slice(format!("-{};+{}:", old.len(), new.len()).as_bytes());
+                patch.extend_from_slice(new);
+            }
+        }
+    }
+
+    patch
+}
+
+/// Decode a patch produced by [`encode_patch`] against its base content
+pub fn decode_patch(base: &[u8], patch: &[u8]) -> Result<Vec<DiffOp>> {
+    let invalid = |reason: &str| XFilesError::InvalidDiff(format!("malformed patch: {}", reason));
+
+    let mut ops = Vec::new();
+    let mut pos: usize = 0;
+    let mut base_pos: usize = 0;
+
+    while pos < patch.len() {
+        let kind = patch[pos];
+        let digits = patch[pos + 1..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count();
+        let len: usize = std::str::from_utf8(&patch[pos + 1..pos + 1 + digits])
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| invalid("missing length"))?;
+        let terminator = patch.get(pos + 1 + digits).copied();
+        pos += digits + 2;
+
+        match (kind, terminator) {
+            (b'=' | b'-', Some(b';')) => {
+                let end = base_pos
+                    .checked_add(len)
+                    .ok_or_else(|| invalid("range exceeds base content"))?;
+                let data = base
+                    .get(base_pos..end)
+                    .ok_or_else(|| invalid("range exceeds base content"))?
+                    .to_vec();
+                base_pos += len;
+                ops.push(if kind == b'=' { DiffOp::Equal(data) } else { DiffOp::Remove(data) });
+            }
+            (b'+', Some(b':')) => {
+                let end = pos.checked_add(len).ok_or_else(|| invalid("truncated insertion"))?;
+                let data = patch
+                    .get(pos..end)
+                    .ok_or_else(|| invalid("truncated insertion"))?
+                    .to_vec();
+                pos += len;
+                ops.push(DiffOp::Add(data));
+            }
+            _ => return Err(invalid("unknown op")),
+        }
+    }
+
+    Ok(ops)
+}
+
+/// Render a diff in unified format
+///
+/// `context` is the number of unchanged lines shown around each change.
+pub fn unified_diff(diff: &[DiffOp], old_label: &str, new_label: &str, context: usize) -> String {
+    // Flatten into tagged lines
+    let mut lines: Vec<(char, &[u8])> = Vec::new();
+    for op in diff {
+        match op {
+            DiffOp::Equal(data) => lines.extend(split_lines(data).into_iter().map(|l| (' ', l))),
+            DiffOp::Add(data) => lines.extend(split_lines(data).into_iter().map(|l| ('+', l))),
+            DiffOp::Remove(data) => lines.extend(split_lines(data).into_iter().map(|l| ('-', l))),
+            DiffOp::Modify { old, new } => {
+                lines.extend(split_lines(old).into_iter().map(|l| ('-', l)));
+                lines.extend(split_lines(new).into_iter().map(|l| ('+', l)));
+            }
+        }
+    }
+
+    let mut output = String::new();
+    let changes: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].0 != ' ').collect();
+    if changes.is_empty() {
+        return output;
+    }
+
+    output.push_str(&format!("--- {}\n+++ {}\n", old_label, new_label));
+
+    // Group changes into hunks whose context windows overlap
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(lines.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    // Line numbers at the start of each flattened line
+    let mut old_line = 1;
+    let mut new_line = 1;
+    let mut positions = Vec::with_capacity(lines.len());
+    for (tag, _) in &lines {
+        positions.push((old_line, new_line));
+        match tag {
+            ' ' => {
+                old_line += 1;
+                new_line += 1;
+            }
+            '-' => old_line += 1,
+            _ => new_line += 1,
+        }
+    }
+
+    for (start, end) in hunks {
+        let hunk = &lines[start..end];
+        let old_count = hunk.iter().filter(|(t, _)| *t != '+').count();
+        let new_count = hunk.iter().filter(|(t, _)| *t != '-').count();
+        let (old_start, new_start) = positions[start];
+
+        output.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_count),
+            hunk_range(new_start, new_count)
+        ));
+
+        for (tag, line) in hunk {
+            output.push(*tag);
+            output.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                output.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    output
+}
+
+/// Format a hunk range, following the GNU convention for empty ranges
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start - 1),
+        1 => start.to_string(),
+        _ => format!("{},{}", start, count),
+    }
+}
+
+/// Split content into lines, keeping line terminators
+fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Single-token edit produced by the Myers backtrack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// Diff two token sequences and group the edits into ops
+fn build_ops(old: &[&[u8]], new: &[&[u8]]) -> Vec<DiffOp> {
+    // Trim the common prefix and suffix before running Myers
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut edits = vec![Edit::Equal; prefix];
+    match myers(old_mid, new_mid) {
+        Some(middle) => edits.extend(middle),
+        None => {
+            edits.extend(std::iter::repeat_n(Edit::Delete, old_mid.len()));
+            edits.extend(std::iter::repeat_n(Edit::Insert, new_mid.len()));
+        }
+    }
+    edits.extend(std::iter::repeat_n(Edit::Equal, suffix));
+
+    // Group runs of the same edit kind
+    let mut runs: Vec<DiffOp> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for edit in edits {
+        let token = match edit {
+            Edit::Equal | Edit::Delete => old[i],
+            Edit::Insert => new[j],
+        };
+        match (edit, runs.last_mut()) {
+            (Edit::Equal, Some(DiffOp::Equal(data)))
+            | (Edit::Delete, Some(DiffOp::Remove(data)))
+            | (Edit::Insert, Some(DiffOp::Add(data))) => data.extend_from_slice(token),
+            (Edit::Equal, _) => runs.push(DiffOp::Equal(token.to_vec())),
+            (Edit::Delete, _) => runs.push(DiffOp::Remove(token.to_vec())),
+            (Edit::Insert, _) => runs.push(DiffOp::Add(token.to_vec())),
+        }
+        match edit {
+            Edit::Equal => {
+                i += 1;
+                j += 1;
+            }
+            Edit::Delete => i += 1,
+            Edit::Insert => j += 1,
+        }
+    }
+
+    // A removal directly followed by an addition is a modification
+    let mut ops = Vec::with_capacity(runs.len());
+    let mut runs = runs.into_iter().peekable();
+    while let Some(op) = runs.next() {
+        match (op, runs.peek()) {
+            (DiffOp::Remove(old), Some(DiffOp::Add(_))) => {
+                if let Some(DiffOp::Add(new)) = runs.next() {
+                    ops.push(DiffOp::Modify { old, new });
+                }
+            }
+            (op, _) => ops.push(op),
+        }
+    }
+
+    ops
+}
+
+/// Myers O(ND) shortest edit script, in linear space
+///
+/// Each step finds the middle snake of the edit path by searching from both
+/// ends at once, then solves the halves on either side of it, so only two
+/// frontiers are kept at any time. Returns `None` if the edit distance
+/// exceeds [`MAX_EDIT_DISTANCE`].
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Option<Vec<Edit>> {
+    let mut edits = Vec::with_capacity(a.len() + b.len());
+    if a.is_empty() || b.is_empty() {
+        edit_script(a, b, &mut edits);
+        return Some(edits);
+    }
+
+    // The halves of the path have shorter scripts, so only the full
+    // search needs the cap
+    let snake = middle_snake(a, b, MAX_EDIT_DISTANCE)?;
+    split_at_snake(a, b, snake, &mut edits);
+    Some(edits)
+}
+
+/// Diagonal run through the middle of a shortest edit path
+#[derive(Debug, Clone, Copy)]
+struct Snake {
+    /// Start in `a` and `b`
+    x: usize,
+    y: usize,
+    /// End in `a` and `b`
+    u: usize,
+    v: usize,
+    /// Length of the whole edit script
+    d: usize,
+}
+
+/// Append the shortest edit script from `a` to `b`
+fn edit_script<T: PartialEq>(a: &[T], b: &[T], edits: &mut Vec<Edit>) {
+    if a.is_empty() {
+        edits.extend(std::iter::repeat_n(Edit::Insert, b.len()));
+    } else if b.is_empty() {
+        edits.extend(std::iter::repeat_n(Edit::Delete, a.len()));
+    } else if let Some(snake) = middle_snake(a, b, a.len() + b.len()) {
+        split_at_snake(a, b, snake, edits);
+    }
+}
+
+/// Append the edit script through `snake`
+fn split_at_snake<T: PartialEq>(a: &[T], b: &[T], snake: Snake, edits: &mut Vec<Edit>) {
+    if snake.d > 1 {
+        edit_script(&a[..snake.x], &b[..snake.y], edits);
+        edits.extend(std::iter::repeat_n(Edit::Equal, snake.u - snake.x));
+        edit_script(&a[snake.u..], &b[snake.v..], edits);
+        return;
+    }
+
+    // At most one token added or removed, after a common prefix
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    edits.extend(std::iter::repeat_n(Edit::Equal, prefix));
+    if a.len() > b.len() {
+        edits.push(Edit::Delete);
+    } else if b.len() > a.len() {
+        edits.push(Edit::Insert);
+    }
+    edits.extend(std::iter::repeat_n(Edit::Equal, a.len().min(b.len()) - prefix));
+}
+
+/// Find the middle snake of a shortest edit path from `a` to `b`
+///
+/// Searches forward from the start and backward from the end until the
+/// two frontiers overlap. Returns `None` if the script is longer than
+/// `max_d`.
+fn middle_snake<T: PartialEq>(a: &[T], b: &[T], max_d: usize) -> Option<Snake> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let delta = n - m;
+    let odd = delta % 2 != 0;
+    let max = (a.len() + b.len()).min(max_d).div_ceil(2) as isize;
+    let offset = max + 1;
+
+    // Furthest x reached on each diagonal, forward and on the reversed
+    // sequences backward
+    let mut forward = vec![0isize; 2 * offset as usize + 1];
+    let mut backward = vec![0isize; 2 * offset as usize + 1];
+
+    for d in 0..=max {
+        let mut k = -d;
+        while k <= d {
+            let idx = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && forward[idx - 1] < forward[idx + 1]) {
+                forward[idx + 1]
+            } else {
+                forward[idx - 1] + 1
+            };
+            let mut y = x - k;
+            let (x0, y0) = (x, y);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[idx] = x;
+
+            let c = delta - k;
+            if odd
+                && (-(d - 1)..=d - 1).contains(&c)
+                && x + backward[(offset + c) as usize] >= n
+            {
+                let (x, y, u, v) = (x0 as usize, y0 as usize, x as usize, y as usize);
+                return Some(Snake { x, y, u, v, d: (2 * d - 1) as usize });
+            }
+            k += 2;
+        }
+
+        let mut c = -d;
+        while c <= d {
+            let idx = (offset + c) as usize;
+            let mut x = if c == -d || (c != d && backward[idx - 1] < backward[idx + 1]) {
+                backward[idx + 1]
+            } else {
+                backward[idx - 1] + 1
+            };
+            let mut y = x - c;
+            let (x0, y0) = (x, y);
+            while x < n && y < m && a[(n - 1 - x) as usize] == b[(m - 1 - y) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[idx] = x;
+
+            let k = delta - c;
+            if !odd && (-d..=d).contains(&k) && x + forward[(offset + k) as usize] >= n {
+                // Back in forward coordinates, the snake runs the other way
+                let (x, y) = ((n - x) as usize, (m - y) as usize);
+                let (u, v) = ((n - x0) as usize, (m - y0) as usize);
+                return Some(Snake { x, y, u, v, d: (2 * d) as usize });
+            }
+            c += 2;
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(old: &[u8], new: &[u8], mime: &str) -> Vec<DiffOp> {
+        let ops = diff_content(old, new, mime);
+        assert_eq!(apply_diff(old, &ops).unwrap(), new);
+        ops
+    }
+
+    #[test]
+    fn test_line_diff() {
+        let old = b"one\ntwo\nthree\n";
+        let new = b"one\n2\nthree\nfour\n";
+        let ops = roundtrip(old, new, "text/plain");
+
+        assert_eq!(
+            ops,
+            vec![
+                DiffOp::Equal(b"one\n".to_vec()),
+                DiffOp::Modify { old: b"two\n".to_vec(), new: b"2\n".to_vec() },
+                DiffOp::Equal(b"three\n".to_vec()),
+                DiffOp::Add(b"four\n".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_byte_diff() {
+        roundtrip(&[0, 1, 2, 3, 4], &[0, 2, 3, 9, 4, 5], "application/octet-stream");
+        roundtrip(b"", b"abc", "application/octet-stream");
+        roundtrip(b"abc", b"", "application/octet-stream");
+    }
+
+    #[test]
+    fn test_myers_shortest_script() {
+        let cost = |a: &[u8], b: &[u8]| {
+            let edits = myers(a, b).unwrap();
+            let from_a = edits.iter().filter(|e| **e != Edit::Insert).count();
+            let from_b = edits.iter().filter(|e| **e != Edit::Delete).count();
+            assert_eq!((from_a, from_b), (a.len(), b.len()));
+            edits.iter().filter(|e| **e != Edit::Equal).count()
+        };
+
+        assert_eq!(cost(b"abcabba", b"cbabac"), 5);
+        assert_eq!(cost(b"abc", b"abc"), 0);
+        assert_eq!(cost(b"abxc", b"abc"), 1);
+        assert_eq!(cost(b"xaxbxcx", b"abc"), 4);
+
+        // Unrelated inputs give up at the cap
+        assert!(myers(&[0u8; 5000], &[1u8; 5000]).is_none());
+    }
+
+    #[test]
+    fn test_apply_rejects_wrong_base() {
+        let ops = diff_content(b"a\nb\n", b"a\nc\n", "text/plain");
+        assert!(apply_diff(b"x\nb\n", &ops).is_err());
+        assert!(apply_diff(b"a\nb\nextra\n", &ops).is_err());
+    }
+
+    #[test]
+    fn test_patch_roundtrip() {
+        let old = b"line 1\nline 2\nline 3\n";
+        let new = b"line 1\nline two\nline 3\nline 4\n";
+        let ops = diff_content(old, new, "text/plain");
+
+        let patch = encode_patch(&ops);
+        assert_eq!(patch, b"=7;-7;+9:line two\n=7;+7:line 4\n");
+
+        let decoded = decode_patch(old, &patch).unwrap();
+        assert_eq!(apply_diff(old, &decoded).unwrap(), new);
+    }
+
+    #[test]
+    fn test_decode_patch_rejects_malformed() {
+        assert!(decode_patch(b"abc", b"=10;").is_err());
+        assert!(decode_patch(b"abc", b"+5:ab").is_err());
+        assert!(decode_patch(b"abc", b"?3;").is_err());
+
+        // Lengths that overflow the position are rejected, not wrapped
+        let overflowing: [&[u8]; 3] = [
+            b"=1;=18446744073709551615;",
+            b"=1;-18446744073709551615;",
+            b"+18446744073709551615:x",
+        ];
+        for patch in overflowing {
+            assert!(matches!(decode_patch(b"abc", patch), Err(XFilesError::InvalidDiff(_))));
+        }
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let ops = diff_content(b"a\nb\nc\n", b"a\nB\nc\n", "text/plain");
+        let rendered = unified_diff(&ops, "old", "new", 3);
+        assert_eq!(rendered, "--- old\n+++ new\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+
+        let same = diff_content(b"a\n", b"a\n", "text/plain");
+        assert!(unified_diff(&same, "old", "new", 3).is_empty());
+    }
 }
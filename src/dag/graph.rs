@@ -2,12 +2,15 @@
 
 use crate::dag::commit::{Commit, TweetId};
 use crate::error::Result;
+use crate::store::index::GraphIndex;
 use std::collections::{HashMap, HashSet, VecDeque};
 
 /// Manages the commit graph
 pub struct CommitGraph {
     /// In-memory commit cache
     commits: HashMap<TweetId, Commit>,
+    /// Adjacency and generation index over the cached commits
+    index: GraphIndex,
 }
 
 impl CommitGraph {
@@ -15,12 +18,17 @@ impl CommitGraph {
     pub fn new() -> Self {
         Self {
             commits: HashMap::new(),
+            index: GraphIndex::new(),
         }
     }
 
     /// Add a commit to the graph
+    ///
+    /// Commits that would close a cycle are ignored.
     pub fn add_commit(&mut self, commit: Commit) {
-        self.commits.insert(commit.id.clone(), commit);
+        if self.index.index_commit(&commit).is_ok() {
+            self.commits.insert(commit.id.clone(), commit);
+        }
     }
 
     /// Get a commit by ID
@@ -28,44 +36,20 @@ impl CommitGraph {
         self.commits.get(id)
     }
 
-    /// Find the latest commit in a chain (BFS traversal)
+    /// Get the graph index
+    pub fn index(&self) -> &GraphIndex {
+        &self.index
+    }
+
+    /// Find the latest commit in a chain
     /// Finds commits with no children (terminal nodes in the DAG)
     pub fn find_head(&self, start: &TweetId) -> Result<&Commit> {
-        // Find all descendants of the start commit
-        let mut reachable = HashSet::new();
-        let mut queue = VecDeque::new();
-
-        queue.push_back(start.clone());
-        reachable.insert(start.clone());
-
-        while let Some(current_id) = queue.pop_front() {
-            if self.commits.contains_key(&current_id) {
-                // Find children of this commit
-                for (id, candidate) in &self.commits {
-                    if candidate.parents.contains(&current_id) && !reachable.contains(id) {
-                        reachable.insert(id.clone());
-                        queue.push_back(id.clone());
-                    }
-                }
-            }
-        }
-
-        // Find commits in the reachable set that have no children (heads)
-        let mut heads = Vec::new();
-        for id in &reachable {
-            let has_children = self.commits.values()
-                .any(|c| c.parents.contains(id));
-
-            if !has_children {
-                if let Some(commit) = self.commits.get(id) {
-                    heads.push(commit);
-                }
-            }
-        }
-
         // Return the most recent head
-        heads.into_iter()
-            .max_by_key(|c| c.timestamp)
+        self.index
+            .heads(start)
+            .iter()
+            .filter_map(|id| self.commits.get(id))
+            .max_by_key(|c| (c.timestamp, self.index.generation(&c.id)))
             .ok_or_else(|| crate::error::XFilesError::CommitNotFound(start.clone()))
     }
 
@@ -94,40 +78,15 @@ impl CommitGraph {
         Ok(ancestors)
     }
 
+    /// Find the best common ancestor of two commits (merge base)
+    pub fn merge_base(&self, a: &TweetId, b: &TweetId) -> Option<TweetId> {
+        self.index.merge_base(a, b)
+    }
+
     /// Detect if there are multiple heads (fork)
     /// Returns all head commits reachable from the root
     pub fn detect_forks(&self, root: &TweetId) -> Result<Vec<TweetId>> {
-        // Find all descendants of the root
-        let mut reachable = HashSet::new();
-        let mut queue = VecDeque::new();
-
-        queue.push_back(root.clone());
-        reachable.insert(root.clone());
-
-        while let Some(current_id) = queue.pop_front() {
-            if self.commits.contains_key(&current_id) {
-                // Add children to the reachable set
-                for (id, candidate) in &self.commits {
-                    if candidate.parents.contains(&current_id) && !reachable.contains(id) {
-                        reachable.insert(id.clone());
-                        queue.push_back(id.clone());
-                    }
-                }
-            }
-        }
-
-        // Find all heads (commits with no children) in the reachable set
-        let mut heads = Vec::new();
-        for id in &reachable {
-            let has_children = self.commits.values()
-                .any(|c| c.parents.contains(id));
-
-            if !has_children {
-                heads.push(id.clone());
-            }
-        }
-
-        Ok(heads)
+        Ok(self.index.heads(root))
     }
 }
 
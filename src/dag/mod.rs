@@ -6,5 +6,5 @@ pub mod commit;
 pub mod graph;
 pub mod diff;
 
-pub use commit::{Commit, TweetId};
+pub use commit::{ChunkRef, Commit, TweetId};
 pub use graph::CommitGraph;
@@ -1,5 +1,7 @@
 //! Error types for xfiles
 
+use crate::fs::merge::ConflictHunk;
+use chrono::{DateTime, Utc};
 use thiserror::Error;
 
 /// Result type alias for xfiles operations
@@ -32,8 +34,14 @@ pub enum XFilesError {
     #[error("Invalid path: {0}")]
     InvalidPath(String),
 
-    #[error("Rate limit exceeded")]
-    RateLimitExceeded,
+    #[error(
+        "Rate limit exceeded on {endpoint}{}",
+        .reset.map(|r| format!(", resets at {}", r)).unwrap_or_default()
+    )]
+    RateLimitExceeded { endpoint: String, reset: Option<DateTime<Utc>> },
+
+    #[error("Twitter API error: {0}")]
+    Api(#[from] ApiError),
 
     #[error("Twitter API error: {0}")]
     TwitterApi(String),
@@ -44,9 +52,72 @@ pub enum XFilesError {
     #[error("Hash mismatch: expected {expected}, got {actual}")]
     HashMismatch { expected: String, actual: String },
 
-    #[error("Merge conflict")]
-    MergeConflict,
+    #[error("Decryption failed: {0}")]
+    Decryption(String),
+
+    #[error("Invalid signature on commit {id}: {reason}")]
+    InvalidSignature { id: String, reason: String },
+
+    #[error("Invalid diff: {0}")]
+    InvalidDiff(String),
+
+    #[error("Merge conflict in {} hunk(s)", .0.len())]
+    MergeConflict(Vec<ConflictHunk>),
+
+    #[error("Database schema version {found} is newer than supported version {supported}")]
+    SchemaTooNew { found: u32, supported: u32 },
 
     #[error("{0}")]
     Other(String),
 }
+
+impl XFilesError {
+    /// Whether the same operation may succeed if tried again later
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            XFilesError::Api(e) => e.is_retryable(),
+            XFilesError::Http(e) => e.is_timeout() || e.is_connect(),
+            XFilesError::RateLimitExceeded { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// An error response from the Twitter API
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Duplicate content")]
+    DuplicateContent,
+
+    #[error("Tweet not found: {0}")]
+    TweetNotFound(String),
+
+    #[error("Tweet too long: {0}")]
+    TooLong(String),
+
+    #[error("Account suspended: {0}")]
+    Suspended(String),
+
+    #[error("Server error {status}: {detail}")]
+    Server { status: u16, detail: String },
+
+    #[error("Error {status}: {detail}")]
+    Other { status: u16, detail: String },
+}
+
+impl ApiError {
+    /// Whether the request may succeed if sent again
+    ///
+    /// Only server errors are; everything else is a problem with the
+    /// request, the credentials or the account.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, ApiError::Server { .. })
+    }
+}
+
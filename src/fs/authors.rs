@@ -0,0 +1,133 @@
+//! Trusted authors of a file's commits
+//!
+//! Anyone can reply to a root tweet, so a reply is not necessarily a
+//! commit. An [`AuthorPolicy`] names the authors whose replies are taken
+//! as commits, for every file or per file. The account's own replies are
+//! always trusted.
+
+use crate::dag::commit::Commit;
+use std::collections::{HashMap, HashSet};
+
+/// Authors whose replies count as commits
+///
+/// Authors are identified by their ID as the remote reports it in
+/// [`Tweet::author_id`](crate::remote::twitter::Tweet::author_id) - the
+/// numeric user ID on Twitter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthorPolicy {
+    /// Authors trusted for every file, `None` trusting anyone
+    global: Option<HashSet<String>>,
+    /// Authors trusted for a single file, overriding the global set
+    files: HashMap<String, HashSet<String>>,
+}
+
+impl AuthorPolicy {
+    /// Trust replies from anyone (the default)
+    pub fn anyone() -> Self {
+        Self::default()
+    }
+
+    /// Trust only replies from the account and `authors`
+    pub fn only<I, S>(authors: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            global: Some(authors.into_iter().map(Into::into).collect()),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Trust only replies from the account and `authors` for `path`,
+    /// whatever the global set is
+    pub fn with_file<I, S>(mut self, path: &str, authors: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.files
+            .insert(path.to_string(), authors.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Whether `author` is trusted for `path`, besides the account itself
+    pub fn trusts(&self, path: &str, author: &str) -> bool {
+        match self.files.get(path).or(self.global.as_ref()) {
+            Some(authors) => authors.contains(author),
+            None => true,
+        }
+    }
+}
+
+/// Commits of a history that are by untrusted authors, or built on one
+///
+/// The root is never foreign. Returns the IDs of the foreign commits.
+pub fn foreign_commits(history: &[Commit], trusted: impl Fn(&Commit) -> bool) -> HashSet<String> {
+    let mut foreign: HashSet<String> = history
+        .iter()
+        .filter(|c| !c.parents.is_empty() && !trusted(c))
+        .map(|c| c.id.clone())
+        .collect();
+
+    // Spread to descendants until nothing changes
+    loop {
+        let before = foreign.len();
+        for commit in history {
+            if commit.parents.iter().any(|p| foreign.contains(p)) {
+                foreign.insert(commit.id.clone());
+            }
+        }
+        if foreign.len() == before {
+            return foreign;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(id: &str, parents: &[&str], author: &str) -> Commit {
+        Commit::new(
+            id.to_string(),
+            parents.iter().map(|p| p.to_string()).collect(),
+            author.to_string(),
+            String::new(),
+            "text/plain".to_string(),
+            0,
+        )
+    }
+
+    #[test]
+    fn test_policy() {
+        assert!(AuthorPolicy::anyone().trusts("a.txt", "stranger"));
+
+        let policy = AuthorPolicy::only(["friend"]).with_file("shared.txt", ["friend", "team"]);
+        assert!(policy.trusts("a.txt", "friend"));
+        assert!(!policy.trusts("a.txt", "team"));
+        assert!(policy.trusts("shared.txt", "team"));
+        assert!(!policy.trusts("shared.txt", "stranger"));
+
+        let per_file = AuthorPolicy::anyone().with_file("private.txt", Vec::<String>::new());
+        assert!(per_file.trusts("a.txt", "stranger"));
+        assert!(!per_file.trusts("private.txt", "stranger"));
+    }
+
+    #[test]
+    fn test_foreign_commits_spread_to_descendants() {
+        let history = vec![
+            commit("root", &[], "stranger"),
+            commit("a", &["root"], "me"),
+            commit("b", &["a"], "stranger"),
+            commit("c", &["b"], "me"),
+            commit("d", &["a"], "me"),
+            commit("m", &["d", "c"], "me"),
+        ];
+
+        let foreign = foreign_commits(&history, |c| c.author == "me");
+        let mut foreign: Vec<_> = foreign.into_iter().collect();
+        foreign.sort();
+        assert_eq!(foreign, vec!["b", "c", "m"]);
+    }
+}
@@ -0,0 +1,155 @@
+//! Content-defined chunking (FastCDC)
+//!
+//! Block boundaries are picked by a rolling gear hash over the content
+//! instead of fixed offsets, so an edit only changes the blocks around it
+//! and the rest can be shared with earlier commits.
+
+use unicode_segmentation::GraphemeCursor;
+
+/// Blocks are never cut shorter than this, except at the end of the content
+pub const MIN_BLOCK_SIZE: usize = 64;
+
+/// Block size the cut points are normalized around
+pub const AVG_BLOCK_SIZE: usize = 128;
+
+/// Blocks are cut at this size at the latest, which still leaves room in
+/// a tweet for the block's envelope
+pub const MAX_BLOCK_SIZE: usize = 192;
+
+/// Cut mask used before reaching the average size (harder to match)
+const MASK_SMALL: u64 = mask(AVG_BLOCK_SIZE.trailing_zeros() + 2);
+
+/// Cut mask used after reaching the average size (easier to match)
+const MASK_LARGE: u64 = mask(AVG_BLOCK_SIZE.trailing_zeros() - 2);
+
+/// Gear table: one pseudo-random value per byte
+const GEAR: [u64; 256] = gear_table();
+
+/// Mask of the top `bits` bits, which depend on the last 64 bytes hashed
+const fn mask(bits: u32) -> u64 {
+    !(u64::MAX >> bits)
+}
+
+/// Fill the gear table from a fixed splitmix64 sequence
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Length of the first block of `data`
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_BLOCK_SIZE {
+        return data.len();
+    }
+
+    let end = data.len().min(MAX_BLOCK_SIZE);
+    let normal = end.min(AVG_BLOCK_SIZE);
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_BLOCK_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { MASK_SMALL } else { MASK_LARGE };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+
+    end
+}
+
+/// Split content into content-defined blocks
+///
+/// When `text` is set the content must be UTF-8 and every cut is moved
+/// forward to the next grapheme boundary, so blocks can be posted as text.
+pub fn split_blocks(content: &[u8], text: bool) -> Vec<&[u8]> {
+    let as_text = if text { std::str::from_utf8(content).ok() } else { None };
+
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    while offset < content.len() {
+        let mut end = offset + cut_point(&content[offset..]);
+        if let Some(text) = as_text {
+            end = grapheme_boundary(text, end);
+        }
+        blocks.push(&content[offset..end]);
+        offset = end;
+    }
+
+    blocks
+}
+
+/// First grapheme boundary of `text` at or after `offset`
+fn grapheme_boundary(text: &str, mut offset: usize) -> usize {
+    while !text.is_char_boundary(offset) {
+        offset += 1;
+    }
+
+    let mut cursor = GraphemeCursor::new(offset, text.len(), true);
+    if cursor.is_boundary(text, 0).unwrap_or(true) {
+        return offset;
+    }
+    cursor.next_boundary(text, 0).ok().flatten().unwrap_or(text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use unicode_segmentation::UnicodeSegmentation;
+
+    fn sample(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_sizes() {
+        let content = sample(20_000, 1);
+        let blocks = split_blocks(&content, false);
+
+        assert_eq!(blocks.concat(), content);
+        assert!(blocks.iter().all(|b| b.len() <= MAX_BLOCK_SIZE));
+        assert!(blocks[..blocks.len() - 1].iter().all(|b| b.len() > MIN_BLOCK_SIZE));
+    }
+
+    #[test]
+    fn test_insert_only_changes_nearby_blocks() {
+        let content = sample(10_000, 2);
+        let mut edited = vec![b'!'];
+        edited.extend_from_slice(&content);
+
+        let before = split_blocks(&content, false);
+        let after = split_blocks(&edited, false);
+
+        // Boundaries resynchronize shortly after the insertion
+        let shared = after.iter().filter(|b| before.contains(b)).count();
+        assert!(shared >= before.len() - 3, "{} of {} blocks shared", shared, before.len());
+    }
+
+    #[test]
+    fn test_text_blocks_keep_graphemes() {
+        let content = "héllo 👨‍👩‍👧 wörld 日本 ".repeat(100);
+        let blocks = split_blocks(content.as_bytes(), true);
+
+        assert_eq!(blocks.concat(), content.as_bytes());
+        let boundaries: Vec<usize> = content.grapheme_indices(true).map(|(i, _)| i).collect();
+        let mut offset = 0;
+        for block in &blocks[..blocks.len() - 1] {
+            offset += block.len();
+            assert!(boundaries.contains(&offset));
+        }
+    }
+}
@@ -1,28 +1,257 @@
 //! Content chunking for tweets (280 char limit)
+//!
+//! Tweets are measured the way Twitter counts them (see
+//! [`weighted_length`]), and text is only ever cut between graphemes.
 
-use crate::error::Result;
+use crate::error::{Result, XFilesError};
+use crate::util::encoding::{ContentHeader, MAX_NONCE, encode_with_header};
+use unicode_segmentation::UnicodeSegmentation;
 
-/// Maximum size for a single tweet (in bytes)
+/// Maximum weighted length of a single tweet, see [`weighted_length`]
 pub const TWEET_MAX_SIZE: usize = 280;
 
-/// Split content into tweet-sized chunks
-pub fn chunk_content(content: &[u8]) -> Result<Vec<Vec<u8>>> {
-    if content.len() <= TWEET_MAX_SIZE {
-        return Ok(vec![content.to_vec()]);
+/// Weight of a URL once Twitter shortens it to a t.co link
+const URL_WEIGHT: usize = 23;
+
+/// Upper bound on the payload bytes one tweet can hold: every character
+/// weighs at least 1 and takes at most 4 bytes
+const MAX_CHUNK_BYTES: usize = 4 * TWEET_MAX_SIZE;
+
+/// Weighted length of a tweet as Twitter counts it
+///
+/// Follows the twitter-text v3 rules: code points in the Latin, general
+/// punctuation and similar ranges weigh 1, everything else (CJK, emoji,
+/// ...) weighs 2, and links count as 23. The count never comes out below
+/// Twitter's: without the full list of top-level domains, any dotted name
+/// ending in two or more letters is taken for a link, a link longer than
+/// a t.co URL is charged its own length, and emoji sequences are charged
+/// per code point rather than 2 in total.
+pub fn weighted_length(text: &str) -> usize {
+    // Every domain has a dot
+    if !text.contains('.') {
+        return text.chars().map(char_weight).sum();
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut length = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match link_end(&chars, i) {
+            Some(end) => {
+                let own: usize = chars[i..end].iter().map(|&c| char_weight(c)).sum();
+                length += own.max(URL_WEIGHT);
+                i = end;
+            }
+            None => {
+                length += char_weight(chars[i]);
+                i += 1;
+            }
+        }
+    }
+    length
+}
+
+/// End of a link starting at `start` that Twitter could shorten, if any
+///
+/// A link is an optional `http://` or `https://`, a domain, an optional
+/// port and a path, query or fragment running to the next whitespace. As
+/// in twitter-text, a bare name under a country code such as `main.rs` is
+/// only a link if a path follows.
+fn link_end(chars: &[char], start: usize) -> Option<usize> {
+    if !is_label_char(chars[start])
+        || start > 0 && (is_label_char(chars[start - 1]) || matches!(chars[start - 1], '.' | '/'))
+    {
+        return None;
+    }
+
+    let scheme = ["https://", "http://"].into_iter().find(|s| {
+        let rest = chars[start..].iter().take(s.len());
+        rest.map(char::to_ascii_lowercase).eq(s.chars())
+    });
+    let (mut end, labels, tld_len) = domain(chars, start + scheme.map_or(0, str::len))?;
+
+    if chars.get(end) == Some(&':') && chars.get(end + 1).is_some_and(char::is_ascii_digit) {
+        end += 1;
+        while chars.get(end).is_some_and(char::is_ascii_digit) {
+            end += 1;
+        }
+    }
+    let path = matches!(chars.get(end), Some('/' | '?' | '#'));
+    if path {
+        while chars.get(end).is_some_and(|c| !c.is_whitespace()) {
+            end += 1;
+        }
+    }
+
+    if scheme.is_none() && !path && labels == 2 && tld_len == 2 {
+        return None;
+    }
+    Some(end)
+}
+
+/// The longest domain starting at `start`: its end, number of labels and
+/// length of its top-level label
+fn domain(chars: &[char], start: usize) -> Option<(usize, usize, usize)> {
+    let mut found = None;
+    let mut pos = start;
+    let mut labels = 0;
+    loop {
+        let label = pos;
+        while chars.get(pos).is_some_and(|&c| is_label_char(c)) {
+            pos += 1;
+        }
+        if pos == label {
+            break;
+        }
+        labels += 1;
+
+        // Top-level domains are letters only, and not followed by more of
+        // a word
+        let tld = &chars[label..pos];
+        if labels >= 2
+            && tld.len() >= 2
+            && tld.iter().all(char::is_ascii_alphabetic)
+            && !chars.get(pos).is_some_and(|&c| c.is_alphanumeric() || matches!(c, '@' | '+'))
+        {
+            found = Some((pos, labels, tld.len()));
+        }
+
+        if chars.get(pos) != Some(&'.') {
+            break;
+        }
+        pos += 1;
     }
+    found
+}
 
+/// Whether a character can be part of a domain label
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// Weight of a single code point
+fn char_weight(c: char) -> usize {
+    match c as u32 {
+        // Rendered as emoji, which weigh 2
+        0xA9 | 0xAE => 2,
+        0..=4351 | 8192..=8205 | 8208..=8223 | 8242..=8247 => 1,
+        _ => 2,
+    }
+}
+
+/// Split text into tweet-sized chunks, cutting only between graphemes
+pub fn chunk_content(content: &[u8]) -> Result<Vec<Vec<u8>>> {
     let mut chunks = Vec::new();
     let mut offset = 0;
 
-    while offset < content.len() {
-        let end = (offset + TWEET_MAX_SIZE).min(content.len());
-        chunks.push(content[offset..end].to_vec());
-        offset = end;
+    while offset < content.len() || chunks.is_empty() {
+        let (len, chunk) = fit_chunk(&content[offset..], true, |body| Ok(body.to_vec()))?;
+        chunks.push(chunk);
+        offset += len;
     }
 
     Ok(chunks)
 }
 
+/// Split a commit payload into tweets that each start with an envelope
+/// header derived from `header`
+///
+/// Chunk bodies are encoded with the header's text codec, and each tweet,
+/// header included, weighs at most [`TWEET_MAX_SIZE`]. Payloads posted
+/// without a codec must be UTF-8 and are cut between graphemes.
+pub fn chunk_with_header(payload: &[u8], header: &ContentHeader) -> Result<Vec<Vec<u8>>> {
+    let text = header.encoding.is_none();
+
+    // Headers grow with the chunk count, so settle on a count first
+    let mut count = 1;
+    loop {
+        let mut tweets = Vec::with_capacity(count);
+        let mut offset = 0;
+        while offset < payload.len() || tweets.is_empty() {
+            let chunk_header = header.for_chunk(tweets.len(), count);
+
+            // Leave room for a nonce, should Twitter take the tweet for a
+            // duplicate
+            let reserved = ContentHeader { nonce: Some(MAX_NONCE), ..chunk_header.clone() };
+            let (len, _) = fit_chunk(&payload[offset..], text, |body| {
+                encode_with_header(body, &reserved)
+            })?;
+            tweets.push(encode_with_header(&payload[offset..offset + len], &chunk_header)?);
+            offset += len;
+        }
+
+        if tweets.len() == count {
+            return Ok(tweets);
+        }
+        count = tweets.len();
+    }
+}
+
+/// Encode the longest prefix of `remaining` that fits in one tweet
+///
+/// `encode` turns a prefix into the tweet to post. Returns the number of
+/// payload bytes used and the encoded tweet.
+fn fit_chunk(
+    remaining: &[u8],
+    text: bool,
+    encode: impl Fn(&[u8]) -> Result<Vec<u8>>,
+) -> Result<(usize, Vec<u8>)> {
+    let cuts = cut_points(remaining, text)?;
+    let fits = |tweet: &[u8]| {
+        std::str::from_utf8(tweet).is_ok_and(|t| weighted_length(t) <= TWEET_MAX_SIZE)
+    };
+
+    // Binary search for the longest prefix whose tweet fits
+    let (mut lo, mut hi) = (0, cuts.len() - 1);
+    while lo < hi {
+        let mid = (lo + hi).div_ceil(2);
+        if fits(&encode(&remaining[..cuts[mid]])?) {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+
+    let len = cuts[lo];
+    let tweet = encode(&remaining[..len])?;
+    if !fits(&tweet) || (len == 0 && !remaining.is_empty()) {
+        // Not even the header and one grapheme fit
+        return Err(XFilesError::ContentTooLarge(remaining.len()));
+    }
+
+    Ok((len, tweet))
+}
+
+/// Offsets a chunk of `payload` may end at, in increasing order
+///
+/// Encoded payloads can be cut at any byte, text only between graphemes.
+fn cut_points(payload: &[u8], text: bool) -> Result<Vec<usize>> {
+    let max = payload.len().min(MAX_CHUNK_BYTES);
+    if !text {
+        return Ok((0..=max).collect());
+    }
+
+    // Only the part a tweet could hold needs to be segmented
+    let prefix = match std::str::from_utf8(&payload[..max]) {
+        Ok(prefix) => prefix,
+        Err(e) if e.error_len().is_none() => {
+            // Cut short in the middle of a character
+            std::str::from_utf8(&payload[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(e) => return Err(XFilesError::InvalidEncoding(e.to_string())),
+    };
+
+    let mut cuts: Vec<usize> = prefix.grapheme_indices(true).map(|(i, _)| i).collect();
+    if cuts.is_empty() {
+        cuts.push(0);
+    }
+    // The end of the prefix is only a boundary if nothing follows it
+    if prefix.len() == payload.len() && !payload.is_empty() {
+        cuts.push(payload.len());
+    }
+    Ok(cuts)
+}
+
 /// Recombine chunks into original content
 pub fn recombine_chunks(chunks: &[Vec<u8>]) -> Result<Vec<u8>> {
     Ok(chunks.concat())
@@ -31,6 +260,10 @@ pub fn recombine_chunks(chunks: &[Vec<u8>]) -> Result<Vec<u8>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::codec::TextCodec;
+    use crate::util::encoding::{decode_with_header, with_nonce};
+    use crate::util::hash::compute_hash;
+    use proptest::prelude::*;
 
     #[test]
     fn test_chunk_small_content() {
@@ -49,4 +282,137 @@ mod tests {
         let recombined = recombine_chunks(&chunks).unwrap();
         assert_eq!(recombined, content);
     }
+
+    #[test]
+    fn test_chunk_with_header() {
+        let content: Vec<u8> = (0..5000).map(|i| b'a' + (i % 26) as u8).collect();
+        let header = ContentHeader::new("text/plain", content.len(), compute_hash(&content));
+        let tweets = chunk_with_header(&content, &header).unwrap();
+        assert!(tweets.len() > 1);
+
+        let mut bodies = Vec::new();
+        for (index, tweet) in tweets.iter().enumerate() {
+            assert!(tweet.len() <= TWEET_MAX_SIZE);
+            assert!(with_nonce(tweet, MAX_NONCE).unwrap().len() <= TWEET_MAX_SIZE);
+            let (decoded, body) = decode_with_header(tweet).unwrap();
+            assert_eq!(decoded, Some(header.for_chunk(index, tweets.len())));
+            bodies.push(body);
+        }
+        assert_eq!(recombine_chunks(&bodies).unwrap(), content);
+
+        // Empty content still gets a tweet
+        let empty = ContentHeader::new("text/plain", 0, compute_hash(b""));
+        assert_eq!(chunk_with_header(b"", &empty).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_weighted_length() {
+        assert_eq!(weighted_length("hello"), 5);
+        assert_eq!(weighted_length("café"), 4);
+        assert_eq!(weighted_length("日本語"), 6);
+        assert_eq!(weighted_length("😀"), 2);
+        assert_eq!(weighted_length("“quoted” – ok…"), 15);
+        assert_eq!(weighted_length("© 2024"), 7);
+
+        // Links count as a t.co URL, or their own length if longer
+        assert_eq!(weighted_length("see example.com"), 4 + URL_WEIGHT);
+        assert_eq!(weighted_length("https://example.com/a?b"), URL_WEIGHT);
+        assert_eq!(weighted_length("a.com,b.com"), 2 * URL_WEIGHT + 1);
+        assert_eq!(weighted_length("foo.bar()"), URL_WEIGHT + 2);
+        assert_eq!(weighted_length("docs.rs/xfiles"), URL_WEIGHT);
+        let long = format!("https://example.com/{}", "x".repeat(30));
+        assert_eq!(weighted_length(&long), long.len());
+
+        // Dots in prose and code that Twitter leaves alone
+        assert_eq!(weighted_length("the end. Next"), 13);
+        assert_eq!(weighted_length("e.g. this"), 9);
+        assert_eq!(weighted_length("main.rs"), 7);
+        assert_eq!(weighted_length("see src/fs/chunk.rs"), 19);
+        assert_eq!(weighted_length("v1.2.3"), 6);
+    }
+
+    #[test]
+    fn test_chunk_cjk_by_weight() {
+        let content = "漢".repeat(400);
+        let chunks = chunk_content(content.as_bytes()).unwrap();
+
+        // 140 double-weight characters per tweet, not 280 bytes
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].len(), 140 * "漢".len());
+        assert_eq!(recombine_chunks(&chunks).unwrap(), content.as_bytes());
+    }
+
+    #[test]
+    fn test_chunk_keeps_graphemes_together() {
+        // Each flag is two regional indicators that must stay together
+        let content = "a".repeat(279) + "🇸🇪🇸🇪";
+        let chunks = chunk_content(content.as_bytes()).unwrap();
+        assert_eq!(chunks[0], "a".repeat(279).as_bytes());
+        assert_eq!(chunks[1], "🇸🇪🇸🇪".as_bytes());
+
+        // A grapheme that cannot fit in any tweet is an error
+        let zalgo = "e".to_string() + &"\u{301}".repeat(300);
+        assert!(chunk_content(zalgo.as_bytes()).is_err());
+    }
+
+    /// Text mixing single-weight, double-weight and multi-code-point graphemes
+    fn unicode_text() -> impl Strategy<Value = String> {
+        let fragment = prop_oneof![
+            any::<char>().prop_map(String::from),
+            "[a-z ]{1,12}",
+            Just("e\u{301}".to_string()),
+            Just("👨‍👩‍👧".to_string()),
+            Just("🇸🇪".to_string()),
+            Just("日本語".to_string()),
+            Just("https://example.com/".to_string()),
+            Just("\r\n".to_string()),
+        ];
+        prop::collection::vec(fragment, 0..300).prop_map(|parts| parts.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn prop_recombine_round_trips_unicode(text in unicode_text()) {
+            let chunks = chunk_content(text.as_bytes()).unwrap();
+            prop_assert_eq!(recombine_chunks(&chunks).unwrap(), text.as_bytes());
+
+            let boundaries: Vec<usize> = text
+                .grapheme_indices(true)
+                .map(|(i, _)| i)
+                .chain([text.len()])
+                .collect();
+            let mut offset = 0;
+            for chunk in &chunks {
+                let chunk = std::str::from_utf8(chunk).unwrap();
+                prop_assert!(weighted_length(chunk) <= TWEET_MAX_SIZE);
+                offset += chunk.len();
+                prop_assert!(boundaries.contains(&offset));
+            }
+        }
+
+        #[test]
+        fn prop_chunk_with_header_round_trips(
+            text in unicode_text(),
+            binary in prop::collection::vec(any::<u8>(), 0..1500),
+        ) {
+            let codecs = [None, Some(TextCodec::Base64), Some(TextCodec::Base85), Some(TextCodec::Base2048)];
+            for encoding in codecs {
+                let content = match encoding {
+                    None => text.as_bytes(),
+                    Some(_) => &binary[..],
+                };
+                let mut header = ContentHeader::new("text/plain", content.len(), compute_hash(content));
+                header.encoding = encoding;
+
+                let tweets = chunk_with_header(content, &header).unwrap();
+                let mut bodies = Vec::new();
+                for tweet in &tweets {
+                    let tweet = std::str::from_utf8(tweet).unwrap();
+                    prop_assert!(weighted_length(tweet) <= TWEET_MAX_SIZE);
+                    bodies.push(decode_with_header(tweet.as_bytes()).unwrap().1);
+                }
+                prop_assert_eq!(recombine_chunks(&bodies).unwrap(), content);
+            }
+        }
+    }
 }
@@ -1,13 +1,111 @@
 //! File operations and XFile implementation
 
-use crate::dag::commit::{Commit, TweetId};
-use crate::error::Result;
+use crate::dag::commit::{ChunkRef, Commit, Hash, TweetId};
+use crate::dag::diff::{apply_diff, decode_patch, diff_content, encode_patch};
+use crate::error::{ApiError, Result, XFilesError};
 use crate::remote::RemoteAdapter;
 use crate::store::{SqliteStore, cache::ContentCache};
-use crate::fs::chunk::chunk_content;
-use crate::util::hash::compute_hash;
+use crate::fs::authors::{AuthorPolicy, foreign_commits};
+use crate::fs::cdc::split_blocks;
+use crate::fs::chunk::{TWEET_MAX_SIZE, chunk_with_header, recombine_chunks};
+use crate::fs::history::{Revision, get_history, get_version};
+use crate::util::codec::TextCodec;
+use crate::util::compress::Compression;
+use crate::util::crypto::{FileKey, Keyring};
+use crate::util::encoding::{
+    BLOCK_MIME, ContentHeader, MAX_NONCE, decode_with_header, delta_mime, is_text_mime,
+    parse_delta_mime, with_nonce,
+};
+use crate::util::hash::{compute_hash, verify_hash};
+use crate::util::sign::{KEY_SIZE, Signer, decode_writers, encode_writers, verify};
+use crate::util::time;
+use chrono::{DateTime, Utc};
 use std::sync::Arc;
 
+/// MIME type of tombstone commits
+pub(crate) const TOMBSTONE_MIME: &str = "application/x-xfiles-tombstone";
+
+/// Prefix of root tweets, followed by the file path
+pub(crate) const ROOT_PREFIX: &str = "📁 ";
+
+/// Start of the nonce line [`mark_root`] adds to a root tweet
+const NONCE_PREFIX: &str = "n=";
+
+/// How far the remote's clock, which keeps whole seconds, may lag ours
+/// when [`post_unique`] looks for a post whose response was lost
+const POST_CLOCK_SKEW_SECS: i64 = 5;
+
+/// Options controlling how content is committed
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    /// MIME type of the file content
+    pub mime: String,
+    /// Post delta commits (patches against the parent) instead of full
+    /// content, writing a full snapshot after this many consecutive deltas.
+    /// `None` always writes full snapshots.
+    pub snapshot_interval: Option<usize>,
+    /// Text codec for content that cannot be posted as plain text: any
+    /// content with a non-text MIME type, or text that is not valid UTF-8.
+    /// Defaults to base-2048, which packs the most bytes into a tweet.
+    pub codec: TextCodec,
+    /// How payloads that need more than one tweet are split
+    pub chunking: Chunking,
+    /// Compression to try on payloads. A compressed payload is only posted
+    /// if it takes fewer tweets, and never with content-defined chunking,
+    /// whose blocks could not be shared once compressed.
+    pub compression: Option<Compression>,
+    /// Smallest payload, in bytes, worth trying to compress
+    pub compression_threshold: usize,
+    /// Encrypt payloads under the keyring's current master key. The
+    /// envelope (MIME type, size, parents) stays public, and encrypted
+    /// payloads are never split into shared blocks.
+    pub encrypt: bool,
+}
+
+/// How a commit payload is split across tweets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Chunking {
+    /// Fill tweets in order, posting every chunk with its commit
+    #[default]
+    Fixed,
+    /// Split at content-defined boundaries into blocks addressed by their
+    /// hash. Blocks already posted by an earlier commit are referenced
+    /// instead of posted again, so a commit only costs its new blocks and
+    /// the list of block tweets.
+    ContentDefined,
+}
+
+/// How commit signatures are checked when reading and resolving heads
+///
+/// Commits are checked against the writer keys published in the file's
+/// root tweet. Reading an unsigned or wrongly signed commit fails with
+/// [`XFilesError::InvalidSignature`] in every mode but `Off`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verification {
+    /// Accept every commit, signed or not
+    #[default]
+    Off,
+    /// Leave unsigned or wrongly signed replies, and everything posted on
+    /// top of them, out of head resolution
+    Skip,
+    /// Fail head resolution when a reply is unsigned or wrongly signed
+    Strict,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            mime: "text/plain".to_string(),
+            snapshot_interval: None,
+            codec: TextCodec::Base2048,
+            chunking: Chunking::Fixed,
+            compression: None,
+            compression_threshold: TWEET_MAX_SIZE,
+            encrypt: false,
+        }
+    }
+}
+
 /// Represents a file in the xfiles filesystem
 pub struct XFile {
     /// Path to the file
@@ -22,6 +120,18 @@ pub struct XFile {
     cache: Arc<ContentCache>,
     /// Author username
     author: String,
+    /// Write options
+    options: WriteOptions,
+    /// Master keys for encrypted commits
+    keyring: Option<Arc<Keyring>>,
+    /// Key new commits are signed with
+    signer: Option<Arc<Signer>>,
+    /// How commit signatures are checked
+    verification: Verification,
+    /// Writer keys published in the root tweet, fetched on first use
+    writers: tokio::sync::OnceCell<Vec<[u8; KEY_SIZE]>>,
+    /// Authors whose commits are part of the history
+    authors: Arc<AuthorPolicy>,
 }
 
 impl XFile {
@@ -41,120 +151,774 @@ impl XFile {
             adapter,
             cache,
             author,
+            options: WriteOptions::default(),
+            keyring: None,
+            signer: None,
+            verification: Verification::Off,
+            writers: tokio::sync::OnceCell::new(),
+            authors: Arc::new(AuthorPolicy::anyone()),
         }
     }
 
     /// Read the current contents of the file
     pub async fn read(&self) -> Result<Vec<u8>> {
+        self.read_commit(&self.head).await
+    }
+
+    /// Read the file as it was at a historical revision
+    pub async fn read_at(&self, revision: &Revision) -> Result<Vec<u8>> {
+        get_version(self, revision).await
+    }
+
+    /// Read the content of a commit of this file
+    ///
+    /// Delta commits are rebuilt by replaying patches forward from the
+    /// closest snapshot (or cached revision). Content is verified against
+    /// the commit hash when the commit is known to the local index, and
+    /// signatures are checked as [`Verification`] says.
+    pub(crate) async fn read_commit(&self, id: &TweetId) -> Result<Vec<u8>> {
         // Check cache first
-        if let Some(content) = self.cache.get(&self.head) {
+        if let Some(content) = self.cache.get(id) {
             return Ok(content);
         }
 
-        // Fetch from remote
-        let content = self.adapter.fetch(&self.head).await?;
+        // Walk back to the closest snapshot, collecting delta commits
+        let mut deltas = Vec::new();
+        let mut current = id.clone();
+        let mut content = loop {
+            let commit = self.store.get_commit(&current).await?;
+            match commit {
+                Some(commit) if parse_delta_mime(&commit.mime).is_some() => {
+                    let parent = commit
+                        .parents
+                        .first()
+                        .cloned()
+                        .ok_or_else(|| XFilesError::CommitNotFound(commit.id.clone()))?;
+                    deltas.push(commit);
 
-        // Cache it
-        self.cache.put(self.head.clone(), content.clone());
+                    if let Some(content) = self.cache.get(&parent) {
+                        break content;
+                    }
+                    current = parent;
+                }
+                Some(commit) => {
+                    let (header, content) = self.fetch_payload(&current).await?;
+                    self.check_signature(&commit, header.as_ref()).await?;
+                    self.verify_content(header.as_ref(), &commit.hash, &content)?;
+                    self.cache.put(current.clone(), content.clone());
+                    break content;
+                }
+                None if self.verification != Verification::Off => {
+                    return Err(XFilesError::InvalidSignature {
+                        id: current,
+                        reason: "commit is not in the index".to_string(),
+                    });
+                }
+                None => {
+                    // Unknown to the index - trust the envelope, if any
+                    let (header, content) = self.fetch_payload(&current).await?;
+                    if let Some(header) = header {
+                        if parse_delta_mime(&header.mime).is_some() {
+                            return Err(XFilesError::CommitNotFound(format!(
+                                "parent of delta commit {}",
+                                current
+                            )));
+                        }
+                        self.verify_content(Some(&header), &header.hash, &content)?;
+                    }
+                    self.cache.put(current.clone(), content.clone());
+                    break content;
+                }
+            }
+        };
+
+        // Replay the patches forward
+        for commit in deltas.iter().rev() {
+            let (header, patch) = self.fetch_payload(&commit.id).await?;
+            self.check_signature(commit, header.as_ref()).await?;
+            content = apply_diff(&content, &decode_patch(&content, &patch)?)?;
+            self.verify_content(header.as_ref(), &commit.hash, &content)?;
+            self.cache.put(commit.id.clone(), content.clone());
+        }
 
         Ok(content)
     }
 
+    /// Fetch the payload of a commit
+    ///
+    /// Returns the envelope header of the first chunk, or `None` for tweets
+    /// posted before the envelope existed. Payloads posted as blocks are
+    /// reassembled from their block tweets, then decrypted and
+    /// decompressed as the envelope says.
+    async fn fetch_payload(&self, id: &TweetId) -> Result<(Option<ContentHeader>, Vec<u8>)> {
+        let (header, mut payload) = self.fetch_posted(id).await?;
+        if let Some(first) = &header {
+            if first.blocks {
+                payload = self.fetch_blocks(&payload).await?;
+            }
+            if let Some(key) = first.key {
+                payload = self.file_key(key)?.decrypt(&payload, first.hash.as_bytes())?;
+            }
+            if let Some(compression) = first.compression {
+                // Payloads are never larger than the content they describe
+                payload = compression.decompress(&payload, first.size)?;
+            }
+        }
+
+        Ok((header, payload))
+    }
+
+    /// Fetch what was posted for a commit
+    ///
+    /// Chunked payloads are reassembled from the commit's chunk manifest,
+    /// checking every tweet against the hash recorded when it was posted,
+    /// or by following the envelope headers through the reply chain when
+    /// there is no manifest.
+    async fn fetch_posted(&self, id: &TweetId) -> Result<(Option<ContentHeader>, Vec<u8>)> {
+        let chunks = self.store.get_chunks(id).await?;
+        if chunks.is_empty() {
+            return self.fetch_chain(id).await;
+        }
+
+        let mut first = None;
+        let mut parts = Vec::with_capacity(chunks.len());
+        for chunk in &chunks {
+            let tweet = self.adapter.fetch(&chunk.id).await?;
+            verify_hash_of(&chunk.hash, &tweet)?;
+            let (header, body) = decode_with_header(&tweet)?;
+            check_chunk(first.as_ref(), header.as_ref(), chunk.index, chunks.len())?;
+            if chunk.index == 0 {
+                first = header;
+            }
+            parts.push(body);
+        }
+
+        Ok((first, recombine_chunks(&parts)?))
+    }
+
+    /// Fetch a payload by following its chunks through the reply chain
+    async fn fetch_chain(&self, id: &TweetId) -> Result<(Option<ContentHeader>, Vec<u8>)> {
+        let (header, body) = decode_with_header(&self.adapter.fetch(id).await?)?;
+        let Some(first) = header else {
+            // No envelope - the tweet holds the whole payload
+            return Ok((None, body));
+        };
+
+        let mut parts = vec![body];
+        let mut prev_id = id.clone();
+        for index in 1..first.count {
+            let mut next = None;
+            for reply in self.adapter.fetch_replies(&prev_id).await? {
+                let (header, body) = decode_with_header(reply.text.as_bytes())?;
+                if check_chunk(Some(&first), header.as_ref(), index, first.count).is_ok() {
+                    next = Some((reply.id, body));
+                    break;
+                }
+            }
+            let (reply, body) = next.ok_or_else(|| {
+                XFilesError::InvalidEncoding(format!("Missing chunk {} of commit {}", index, id))
+            })?;
+            parts.push(body);
+            prev_id = reply;
+        }
+
+        Ok((Some(first), recombine_chunks(&parts)?))
+    }
+
+    /// Reassemble a payload from its block list
+    ///
+    /// Blocks are shared between commits, so they are cached by tweet ID.
+    async fn fetch_blocks(&self, list: &[u8]) -> Result<Vec<u8>> {
+        let list = std::str::from_utf8(list)
+            .map_err(|e| XFilesError::InvalidEncoding(format!("Invalid block list: {}", e)))?;
+
+        let mut parts = Vec::new();
+        for id in list.lines().map(str::to_string) {
+            if let Some(block) = self.cache.get(&id) {
+                parts.push(block);
+                continue;
+            }
+
+            let (header, block) = self.fetch_chain(&id).await?;
+            let header = header.filter(|h| h.mime == BLOCK_MIME).ok_or_else(|| {
+                XFilesError::InvalidEncoding(format!("Tweet {} is not a block", id))
+            })?;
+            verify_hash_of(&header.hash, &block)?;
+            self.cache.put(id, block.clone());
+            parts.push(block);
+        }
+
+        recombine_chunks(&parts)
+    }
+
     /// Write new content to the file (creates a new commit)
+    ///
+    /// With [`WriteOptions::snapshot_interval`] set, only a patch against
+    /// the current head is posted while the delta chain is short enough and
+    /// the patch is smaller than the content.
     pub async fn write(&mut self, data: impl AsRef<[u8]>) -> Result<()> {
         let data = data.as_ref();
-        let hash = compute_hash(data);
-
-        // Chunk the content if needed
-        let chunks = chunk_content(data)?;
-
-        // Post chunks to remote
-        let mut chunk_ids = Vec::new();
-        if chunks.len() == 1 {
-            // Single chunk - post as reply to current head
-            let id = self.adapter.store_reply(&self.head, &chunks[0]).await?;
-            chunk_ids.push(id.clone());
-
-            // Create and store commit
-            let commit = Commit::new(
-                id.clone(),
-                vec![self.head.clone()],
-                self.author.clone(),
-                hash.clone(),
-                "text/plain".to_string(),
-                data.len(),
-            );
-
-            self.store.store_commit(&commit).await?;
-            self.store.set_head(&id).await?;
-
-            // Update head
-            self.head = id;
-        } else {
-            // Multiple chunks - post first as reply, rest as chain
-            let first_id = self.adapter.store_reply(&self.head, &chunks[0]).await?;
-            chunk_ids.push(first_id.clone());
-
-            let mut prev_id = first_id.clone();
-            for chunk in chunks.iter().skip(1) {
-                let id = self.adapter.store_reply(&prev_id, chunk).await?;
-                chunk_ids.push(id.clone());
-                prev_id = id;
+        let mime = self.options.mime.clone();
+
+        if let Some(base) = self.delta_base().await? {
+            let patch = encode_patch(&diff_content(&base, data, &mime));
+            if patch.len() < data.len() {
+                let parents = vec![self.head.clone()];
+                return self.commit(data, &patch, delta_mime(&mime), parents).await;
+            }
+        }
+
+        let parents = vec![self.head.clone()];
+        self.commit(data, data, mime, parents).await
+    }
+
+    /// Write merged content as a merge commit of the head and `other`
+    ///
+    /// The commit is posted as a reply to the current head and records both
+    /// heads as its parents.
+    pub(crate) async fn write_merge(&mut self, data: &[u8], mime: String, other: &TweetId) -> Result<()> {
+        let parents = vec![self.head.clone(), other.clone()];
+        self.commit(data, data, mime, parents).await
+    }
+
+    /// Content of the head if the next write may be a delta against it
+    async fn delta_base(&self) -> Result<Option<Vec<u8>>> {
+        let Some(interval) = self.options.snapshot_interval.filter(|&n| n > 0) else {
+            return Ok(None);
+        };
+
+        // Count the deltas since the last snapshot
+        let mut deltas = 0;
+        let mut current = self.head.clone();
+        loop {
+            let Some(commit) = self.store.get_commit(&current).await? else {
+                return Ok(None);
+            };
+            if parse_delta_mime(&commit.mime).is_none() {
+                // Never build deltas on the root marker or a tombstone
+                if commit.parents.is_empty() || commit.mime == TOMBSTONE_MIME {
+                    return Ok(None);
+                }
+                break;
+            }
+
+            deltas += 1;
+            if deltas >= interval {
+                return Ok(None);
+            }
+            match commit.parents.first() {
+                Some(parent) => current = parent.clone(),
+                None => return Ok(None),
             }
+        }
+
+        self.read().await.map(Some)
+    }
+
+    /// Post a payload as a new commit on top of the current head
+    ///
+    /// `data` is the full file content the commit represents, `payload` is
+    /// what actually gets posted. The first parent must be the current head.
+    async fn commit(
+        &mut self,
+        data: &[u8],
+        payload: &[u8],
+        mime: String,
+        parents: Vec<TweetId>,
+    ) -> Result<()> {
+        // Encrypted commits are hashed with the file's keyed hash
+        let key = if self.options.encrypt {
+            Some(self.keyring()?.current_key(&self.path))
+        } else {
+            None
+        };
+        let hash = match &key {
+            Some(key) => key.hash(data),
+            None => compute_hash(data),
+        };
 
-            // Create commit pointing to first chunk
-            let commit = Commit::new(
-                first_id.clone(),
-                vec![self.head.clone()],
-                self.author.clone(),
-                hash.clone(),
-                "text/plain".to_string(),
-                data.len(),
-            );
+        // Describe the commit in every tweet, chunking the payload if needed
+        let mut header = ContentHeader::new(&mime, data.len(), hash.clone());
+        header.encoding = self.codec_for(&mime, payload);
+        header.parents = parents[1..].to_vec();
+        if let Some(signer) = &self.signer {
+            header.signature = Some(signer.sign(&parents[0], &header));
+        }
+        let mut chunks = chunk_with_header(payload, &header)?;
 
-            self.store.store_commit(&commit).await?;
-            self.store.set_head(&first_id).await?;
+        // Payloads that need several tweets can go out as shared blocks
+        if self.options.chunking == Chunking::ContentDefined && chunks.len() > 1 && key.is_none() {
+            let list = self.post_blocks(payload, header.encoding).await?;
+            header.encoding = None;
+            header.blocks = true;
+            chunks = chunk_with_header(&list, &header)?;
+        } else {
+            let mut posted = payload.to_vec();
+            if let Some(compression) = self.options.compression
+                && payload.len() >= self.options.compression_threshold
+            {
+                // Compress only when it saves tweets
+                let mut compressed = header.clone();
+                compressed.compression = Some(compression);
+                compressed.encoding = Some(self.options.codec);
+                let packed = compression.compress(payload)?;
+                let tweets = chunk_with_header(&packed, &compressed)?;
+                if tweets.len() < chunks.len() {
+                    header = compressed;
+                    chunks = tweets;
+                    posted = packed;
+                }
+            }
 
-            // Update head
-            self.head = first_id;
+            if let Some(key) = &key {
+                header.key = Some(key.id());
+                header.encoding = Some(self.options.codec);
+                posted = key.encrypt(&posted, header.hash.as_bytes())?;
+                chunks = chunk_with_header(&posted, &header)?;
+            }
         }
 
+        // Post first chunk as reply to current head, rest as a reply chain
+        let manifest = self.post_chain(&chunks).await?;
+
+        // Create commit pointing to first chunk
+        let first_id = manifest[0].id.clone();
+        let mut commit = Commit::new(
+            first_id.clone(),
+            parents,
+            self.author.clone(),
+            hash,
+            mime,
+            data.len(),
+        );
+        commit.encoding = header.encoding;
+
+        self.store.advance_head(&self.path, &commit, &manifest).await?;
+
+        // Update head
+        self.head = first_id;
+
         // Cache the content
         self.cache.put(self.head.clone(), data.to_vec());
 
         Ok(())
     }
 
+    /// Post the chunks of a payload as a reply chain under the head
+    async fn post_chain(&self, chunks: &[Vec<u8>]) -> Result<Vec<ChunkRef>> {
+        let mut manifest = Vec::with_capacity(chunks.len());
+        let mut prev_id = self.head.clone();
+        for (index, chunk) in chunks.iter().enumerate() {
+            let (id, posted) =
+                post_unique(self.adapter.as_ref(), Some(&prev_id), chunk, |n| with_nonce(chunk, n))
+                    .await?;
+            manifest.push(ChunkRef {
+                id: id.clone(),
+                index,
+                size: posted.len(),
+                hash: compute_hash(&posted),
+            });
+            prev_id = id;
+        }
+
+        Ok(manifest)
+    }
+
+    /// Post the content-defined blocks of a payload that are not on Twitter
+    /// yet, each as its own reply chain under the head
+    ///
+    /// Returns the block list: the tweet ID of every block, one per line.
+    async fn post_blocks(&self, payload: &[u8], encoding: Option<TextCodec>) -> Result<Vec<u8>> {
+        let mut ids = Vec::new();
+        for block in split_blocks(payload, encoding.is_none()) {
+            let hash = compute_hash(block);
+            let id = match self.store.get_block(&hash).await? {
+                Some(id) => id,
+                None => {
+                    let mut header = ContentHeader::new(BLOCK_MIME, block.len(), hash.clone());
+                    header.encoding = encoding;
+                    let chunks = chunk_with_header(block, &header)?;
+                    let id = self.post_chain(&chunks).await?[0].id.clone();
+                    self.store.store_block(&hash, &id, block.len()).await?;
+                    id
+                }
+            };
+            ids.push(id);
+        }
+
+        Ok(ids.join("\n").into_bytes())
+    }
+
+    /// Text codec a payload must be posted with, if any
+    fn codec_for(&self, mime: &str, payload: &[u8]) -> Option<TextCodec> {
+        let content_mime = parse_delta_mime(mime).unwrap_or(mime);
+        let text = mime == TOMBSTONE_MIME || is_text_mime(content_mime);
+        if text && std::str::from_utf8(payload).is_ok() {
+            None
+        } else {
+            Some(self.options.codec)
+        }
+    }
+
     /// Delete the file (creates a tombstone commit)
     pub async fn delete(&mut self) -> Result<()> {
         // Post a tombstone marker
         let tombstone = b"[DELETED]";
-        let id = self.adapter.store_reply(&self.head, tombstone).await?;
+        let parents = vec![self.head.clone()];
+        self.commit(tombstone, tombstone, TOMBSTONE_MIME.to_string(), parents).await
+    }
+
+    /// Get the current head commit ID
+    pub fn head(&self) -> &TweetId {
+        &self.head
+    }
+
+    /// Get the file path
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Get the write options
+    pub fn options(&self) -> &WriteOptions {
+        &self.options
+    }
+
+    /// Set the write options used by subsequent writes
+    pub fn set_options(&mut self, options: WriteOptions) {
+        self.options = options;
+    }
+
+    /// Get the root tweet ID of the file
+    pub(crate) async fn root(&self) -> Result<TweetId> {
+        self.store
+            .get_file_root(&self.path)
+            .await?
+            .ok_or_else(|| XFilesError::FileNotFound(self.path.clone()))
+    }
+
+    /// Set the master keys used for encrypted commits
+    pub fn set_keyring(&mut self, keyring: Option<Arc<Keyring>>) {
+        self.keyring = keyring;
+    }
+
+    /// Rewrite the current content as a snapshot encrypted under the
+    /// keyring's current master key
+    ///
+    /// Use after [`Keyring::rotate`]: earlier commits stay encrypted under
+    /// the keys they were written with, but reading the head only needs
+    /// the new one. Later writes to this handle are encrypted too.
+    pub async fn reencrypt(&mut self) -> Result<()> {
+        let Some(commit) = self.store.get_commit(&self.head).await? else {
+            return Err(XFilesError::CommitNotFound(self.head.clone()));
+        };
+        if commit.parents.is_empty() {
+            // Nothing written yet
+            return Ok(());
+        }
+
+        let data = self.read().await?;
+        let mime = parse_delta_mime(&commit.mime).unwrap_or(&commit.mime).to_string();
+        self.options.encrypt = true;
+        let parents = vec![self.head.clone()];
+        self.commit(&data, &data, mime, parents).await
+    }
+
+    /// The keyring, which encrypted commits need
+    fn keyring(&self) -> Result<&Keyring> {
+        self.keyring.as_deref().ok_or_else(|| {
+            XFilesError::Decryption(format!("{} is encrypted but no keyring is set", self.path))
+        })
+    }
+
+    /// Keys of this file under master key `id`
+    fn file_key(&self, id: u32) -> Result<FileKey> {
+        self.keyring()?.file_key(id, &self.path)
+    }
+
+    /// Check content against the hash recorded for it
+    ///
+    /// Encrypted commits carry the file's keyed hash instead of a plain one.
+    fn verify_content(
+        &self,
+        header: Option<&ContentHeader>,
+        expected: &Hash,
+        content: &[u8],
+    ) -> Result<()> {
+        let Some(key) = header.and_then(|h| h.key) else {
+            return verify_hash_of(expected, content);
+        };
+
+        let actual = self.file_key(key)?.hash(content);
+        if actual != *expected {
+            return Err(XFilesError::HashMismatch {
+                expected: expected.clone(),
+                actual,
+            });
+        }
+
+        Ok(())
+    }
 
-        let commit = Commit::new(
-            id.clone(),
-            vec![self.head.clone()],
+    /// A handle on the same file with an empty content cache of its own,
+    /// so every read goes to the remote
+    pub(crate) fn uncached(&self) -> XFile {
+        let mut file = XFile::new(
+            self.path.clone(),
+            self.head.clone(),
+            self.store.clone(),
+            self.adapter.clone(),
+            Arc::new(ContentCache::new()),
             self.author.clone(),
-            compute_hash(tombstone),
-            "application/x-xfiles-tombstone".to_string(),
-            tombstone.len(),
         );
+        file.options = self.options.clone();
+        file.keyring = self.keyring.clone();
+        file.signer = self.signer.clone();
+        file.verification = self.verification;
+        file.authors = self.authors.clone();
+        file
+    }
+
+    /// Get the SQLite store backing this file
+    pub(crate) fn store(&self) -> &SqliteStore {
+        &self.store
+    }
+
+    /// Get the remote adapter backing this file
+    pub(crate) fn adapter(&self) -> &dyn RemoteAdapter {
+        self.adapter.as_ref()
+    }
+
+    /// Set the authors whose commits are part of the history
+    pub fn set_authors(&mut self, authors: Arc<AuthorPolicy>) {
+        self.authors = authors;
+    }
+
+    /// Commits of this file in chronological order
+    ///
+    /// Commits by authors the [`AuthorPolicy`] does not trust, and commits
+    /// built on them, are left out. Commits by the local user are always
+    /// trusted.
+    pub(crate) async fn history(&self) -> Result<Vec<Commit>> {
+        let history = get_history(&self.store, &self.root().await?).await?;
+        let foreign = foreign_commits(&history, |c| {
+            c.author == self.author || self.authors.trusts(&self.path, &c.author)
+        });
+
+        Ok(history.into_iter().filter(|c| !foreign.contains(&c.id)).collect())
+    }
+
+    /// Set the key new commits are signed with
+    pub fn set_signer(&mut self, signer: Option<Arc<Signer>>) {
+        self.signer = signer;
+    }
+
+    /// Set how commit signatures are checked
+    pub fn set_verification(&mut self, verification: Verification) {
+        self.verification = verification;
+    }
 
-        self.store.store_commit(&commit).await?;
-        self.store.set_head(&id).await?;
+    /// Check a commit's signature, fetching its envelope
+    ///
+    /// Does nothing with [`Verification::Off`].
+    pub(crate) async fn verify_commit(&self, commit: &Commit) -> Result<()> {
+        if self.verification == Verification::Off || commit.parents.is_empty() {
+            return Ok(());
+        }
+
+        let (header, _) = decode_with_header(&self.adapter.fetch(&commit.id).await?)?;
+        self.check_signature(commit, header.as_ref()).await
+    }
 
-        self.head = id;
+    /// Check a commit's envelope against the writer keys of the file
+    ///
+    /// The root tweet publishes the keys, so it is not signed itself.
+    async fn check_signature(&self, commit: &Commit, header: Option<&ContentHeader>) -> Result<()> {
+        if self.verification == Verification::Off || commit.parents.is_empty() {
+            return Ok(());
+        }
+
+        let invalid = |reason: &str| XFilesError::InvalidSignature {
+            id: commit.id.clone(),
+            reason: reason.to_string(),
+        };
+        let Some(header) = header.filter(|h| h.signature.is_some()) else {
+            return Err(invalid("commit is not signed"));
+        };
+        if header.hash != commit.hash || header.parents != commit.parents[1..] {
+            return Err(invalid("envelope does not match the index"));
+        }
+
+        let writers = self.writers().await?;
+        if writers.is_empty() {
+            return Err(invalid("file publishes no writer keys"));
+        }
+        if !verify(writers, &commit.parents[0], header) {
+            return Err(invalid("signature does not match any writer key"));
+        }
 
         Ok(())
     }
 
-    /// Get the current head commit ID
-    pub fn head(&self) -> &TweetId {
-        &self.head
+    /// Writer keys published in the file's root tweet
+    async fn writers(&self) -> Result<&[[u8; KEY_SIZE]]> {
+        let writers = self
+            .writers
+            .get_or_try_init(|| async {
+                let root = self.root().await?;
+                let text = self.adapter.fetch(&root).await?;
+                Ok::<_, XFilesError>(
+                    std::str::from_utf8(&text)
+                        .ok()
+                        .and_then(parse_root)
+                        .map(|(_, writers)| writers)
+                        .unwrap_or_default(),
+                )
+            })
+            .await?;
+
+        Ok(writers)
     }
+}
 
-    /// Get the file path
-    pub fn path(&self) -> &str {
-        &self.path
+/// Text of a file's root tweet, publishing its writers' keys if any
+pub(crate) fn root_text(path: &str, writers: &[[u8; KEY_SIZE]]) -> String {
+    if writers.is_empty() {
+        format!("{}{}", ROOT_PREFIX, path)
+    } else {
+        format!("{}{}\n{}", ROOT_PREFIX, path, encode_writers(writers))
+    }
+}
+
+/// Root tweet text with a nonce line, telling it apart from an identical
+/// root posted earlier
+pub(crate) fn mark_root(text: &str, nonce: u32) -> String {
+    format!("{}\n{}{}", text, NONCE_PREFIX, nonce)
+}
+
+/// Path and writer keys named by a root tweet, or `None` if it is not one
+///
+/// A nonce line added by [`mark_root`] is skipped.
+pub(crate) fn parse_root(text: &str) -> Option<(&str, Vec<[u8; KEY_SIZE]>)> {
+    let rest = text.strip_prefix(ROOT_PREFIX)?;
+    let rest = match rest.rsplit_once('\n') {
+        Some((text, line)) if is_nonce_line(line) => text,
+        _ => rest,
+    };
+    let published = rest
+        .split_once('\n')
+        .and_then(|(path, line)| Some((path, decode_writers(line)?)));
+
+    Some(published.unwrap_or((rest, Vec::new())))
+}
+
+/// Whether a root tweet line is a nonce added by [`mark_root`]
+fn is_nonce_line(line: &str) -> bool {
+    line.strip_prefix(NONCE_PREFIX)
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Post a tweet, marking it with a nonce if the remote rejects it as a
+/// duplicate of a recent one
+///
+/// A tweet rejected as a duplicate may be our own post coming back: the
+/// adapter retries posts that timed out, and the first attempt may have
+/// gone through. If the account's recent timeline has a tweet with the
+/// same text and parent, posted since this call started, that tweet is
+/// used instead of posting a second copy.
+///
+/// Otherwise `mark` renders the tweet with a given nonce; nonces are tried
+/// from 1 up to [`MAX_NONCE`]. Returns the tweet ID and the text actually
+/// posted.
+pub(crate) async fn post_unique(
+    adapter: &dyn RemoteAdapter,
+    parent: Option<&TweetId>,
+    tweet: &[u8],
+    mark: impl Fn(u32) -> Result<Vec<u8>>,
+) -> Result<(TweetId, Vec<u8>)> {
+    let started = time::now() - chrono::Duration::seconds(POST_CLOCK_SKEW_SECS);
+    let mut posted = tweet.to_vec();
+    let mut nonce = 0;
+    loop {
+        let result = match parent {
+            Some(parent) => adapter.store_reply(parent, &posted).await,
+            None => adapter.store(&posted).await,
+        };
+        match result {
+            Ok(id) => return Ok((id, posted)),
+            Err(XFilesError::Api(ApiError::DuplicateContent)) if nonce < MAX_NONCE => {
+                if let Some(id) = posted_since(adapter, parent, &posted, started).await? {
+                    return Ok((id, posted));
+                }
+                nonce += 1;
+                posted = mark(nonce)?;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// A tweet on the account's recent timeline with exactly the text `tweet`
+/// and parent `parent`, created no earlier than `since`
+///
+/// The timeline is read rather than the parent's replies because search
+/// takes a while to show a new tweet.
+async fn posted_since(
+    adapter: &dyn RemoteAdapter,
+    parent: Option<&TweetId>,
+    tweet: &[u8],
+    since: DateTime<Utc>,
+) -> Result<Option<TweetId>> {
+    Ok(adapter
+        .fetch_recent()
+        .await?
+        .into_iter()
+        .find(|t| {
+            t.text.as_bytes() == tweet
+                && t.in_reply_to.as_ref() == parent
+                && time::parse_timestamp(&t.created_at).is_ok_and(|at| at >= since)
+        })
+        .map(|t| t.id))
+}
+
+/// Check content against an expected hash
+fn verify_hash_of(expected: &Hash, content: &[u8]) -> Result<()> {
+    if !verify_hash(content, expected) {
+        return Err(XFilesError::HashMismatch {
+            expected: expected.clone(),
+            actual: compute_hash(content),
+        });
     }
+
+    Ok(())
+}
+
+/// Check a chunk's envelope against its position and the first chunk
+///
+/// Chunks without an envelope are accepted as they are.
+fn check_chunk(
+    first: Option<&ContentHeader>,
+    header: Option<&ContentHeader>,
+    index: usize,
+    count: usize,
+) -> Result<()> {
+    let Some(header) = header else {
+        return Ok(());
+    };
+
+    if header.index != index || header.count != count {
+        return Err(XFilesError::InvalidEncoding(format!(
+            "Chunk {}/{} found where {}/{} was expected",
+            header.index, header.count, index, count
+        )));
+    }
+    if let Some(first) = first
+        && (header.hash != first.hash || header.mime != first.mime)
+    {
+        return Err(XFilesError::InvalidEncoding(format!(
+            "Chunk {} belongs to a different commit",
+            index
+        )));
+    }
+
+    Ok(())
 }
@@ -0,0 +1,337 @@
+//! Checking the local index against the remote
+//!
+//! The SQLite index drifts from what the remote serves: tweets get deleted
+//! by their author or by moderation, interrupted writes leave commits no
+//! file reaches, and head flags or refs outlive the commits they name.
+//! [`fsck`] scans the whole index and reports what it finds, and in repair
+//! mode fixes what can be fixed locally.
+
+use crate::dag::commit::{Commit, TweetId};
+use crate::error::{ApiError, Result, XFilesError};
+use crate::fs::file::parse_root;
+use crate::remote::RemoteAdapter;
+use crate::store::SqliteStore;
+use crate::util::hash::compute_hash;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// A disagreement between the index and the remote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// A file's root commit is not in the index
+    MissingRoot {
+        /// File path
+        path: String,
+        /// Root tweet of the file
+        root: TweetId,
+    },
+    /// A file's root tweet can no longer be fetched
+    DeletedRoot {
+        /// File path
+        path: String,
+        /// Root tweet of the file
+        root: TweetId,
+        /// Why the remote could not serve it
+        reason: String,
+    },
+    /// A tweet of a commit can no longer be fetched
+    DeletedTweet {
+        /// Commit ID
+        commit: TweetId,
+        /// The missing tweet
+        tweet: TweetId,
+        /// Why the remote could not serve it
+        reason: String,
+    },
+    /// A file's head ref points outside the file's history
+    StaleRef {
+        /// File path
+        path: String,
+        /// The recorded head
+        head: TweetId,
+    },
+    /// A commit that no file's root reaches
+    OrphanCommit {
+        /// Commit ID
+        commit: TweetId,
+    },
+    /// A commit flagged as head that has children
+    StaleHead {
+        /// Commit ID
+        commit: TweetId,
+    },
+    /// A recorded block whose tweet can no longer be fetched
+    DeletedBlock {
+        /// Blake3 hash of the block
+        hash: String,
+        /// The missing tweet
+        tweet: TweetId,
+        /// Why the remote could not serve it
+        reason: String,
+    },
+}
+
+/// A problem found by [`fsck`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// What is wrong
+    pub problem: Problem,
+    /// Whether the index was fixed
+    pub repaired: bool,
+}
+
+/// Outcome of an index check
+#[derive(Debug, Clone, Default)]
+pub struct FsckReport {
+    /// Number of files checked
+    pub files: usize,
+    /// Number of commits checked, orphans left out
+    pub commits: usize,
+    /// Problems found, in the order they were found
+    pub findings: Vec<Finding>,
+}
+
+impl FsckReport {
+    /// Whether the index agrees with the remote
+    pub fn is_ok(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    /// Problems left in the index
+    pub fn unrepaired(&self) -> impl Iterator<Item = &Problem> {
+        self.findings.iter().filter(|f| !f.repaired).map(|f| &f.problem)
+    }
+
+    fn found(&mut self, problem: Problem, repaired: bool) {
+        self.findings.push(Finding { problem, repaired });
+    }
+}
+
+/// Check the `files`, `refs`, `commits` and `blocks` tables against the remote
+///
+/// Every root, commit and block tweet the index names is fetched once.
+/// With `repair`, the index is fixed where it can be:
+///
+/// - a missing root commit is indexed again from its root tweet, authored
+///   by `author`
+/// - a file whose root tweet is gone is unregistered, and its commits are
+///   then dropped as orphans
+/// - orphan commits are dropped
+/// - stale head flags are cleared
+/// - head refs pointing outside their file are dropped, so the next open
+///   resolves the head again
+/// - blocks whose tweet is gone are forgotten, so later writes post them
+///   again
+///
+/// Commits whose tweets are gone are only reported: the index is right
+/// about them, and [`verify_file`](crate::fs::verify::verify_file) shows
+/// which revisions can no longer be read.
+pub async fn fsck(
+    store: &SqliteStore,
+    adapter: &dyn RemoteAdapter,
+    author: &str,
+    repair: bool,
+) -> Result<FsckReport> {
+    let mut report = FsckReport::default();
+
+    let commits = store.list_commits().await?;
+    let indexed: HashSet<&TweetId> = commits.iter().map(|c| &c.id).collect();
+    let mut children: HashMap<&TweetId, Vec<&TweetId>> = HashMap::new();
+    for commit in &commits {
+        for parent in &commit.parents {
+            children.entry(parent).or_default().push(&commit.id);
+        }
+    }
+
+    // Files: every root must still be there and indexed
+    let mut reachable = HashSet::new();
+    for path in store.list_files().await? {
+        let Some(root) = store.get_file_root(&path).await? else {
+            continue;
+        };
+        report.files += 1;
+
+        let file_commits = descendants(&children, &root);
+        if let Some(head) = store.get_file_head(&path).await?
+            && !file_commits.contains(&head)
+        {
+            if repair {
+                store.remove_file_head(&path).await?;
+            }
+            report.found(Problem::StaleRef { path: path.clone(), head }, repair);
+        }
+
+        match adapter.fetch(&root).await {
+            Ok(text) => {
+                if !indexed.contains(&root) {
+                    let repaired = repair
+                        && reindex_root(store, &path, &root, &text, author, &commits, &file_commits)
+                            .await?;
+                    report.found(Problem::MissingRoot { path, root }, repaired);
+                }
+            }
+            Err(e @ XFilesError::Api(ApiError::TweetNotFound(_))) => {
+                let reason = e.to_string();
+                if repair {
+                    store.remove_file(&path).await?;
+                    report.found(Problem::DeletedRoot { path, root, reason }, true);
+                    continue;
+                }
+                report.found(Problem::DeletedRoot { path, root, reason }, false);
+            }
+            Err(e) => return Err(e),
+        }
+        reachable.extend(file_commits);
+    }
+
+    // Commits: reachable from a root, flagged right, tweets still there
+    for commit in &commits {
+        if !reachable.contains(&commit.id) {
+            if repair {
+                store.delete_commit(&commit.id).await?;
+            }
+            report.found(Problem::OrphanCommit { commit: commit.id.clone() }, repair);
+            continue;
+        }
+        report.commits += 1;
+
+        if commit.is_head && children.contains_key(&commit.id) {
+            if repair {
+                store.clear_head(&commit.id).await?;
+            }
+            report.found(Problem::StaleHead { commit: commit.id.clone() }, repair);
+        }
+
+        // Roots were fetched with their file
+        if commit.parents.is_empty() {
+            continue;
+        }
+        if let Some((tweet, reason)) = missing_tweet(store, adapter, commit).await? {
+            report.found(
+                Problem::DeletedTweet { commit: commit.id.clone(), tweet, reason },
+                false,
+            );
+        }
+    }
+
+    // Blocks: shared tweets later writes will point at
+    for (hash, tweet) in store.list_blocks().await? {
+        match adapter.fetch(&tweet).await {
+            Ok(_) => {}
+            Err(e @ XFilesError::Api(ApiError::TweetNotFound(_))) => {
+                let reason = e.to_string();
+                if repair {
+                    store.delete_block(&hash).await?;
+                }
+                report.found(Problem::DeletedBlock { hash, tweet, reason }, repair);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(report)
+}
+
+/// A commit and everything built on it
+fn descendants(children: &HashMap<&TweetId, Vec<&TweetId>>, id: &TweetId) -> HashSet<TweetId> {
+    let mut seen = HashSet::from([id.clone()]);
+    let mut queue = VecDeque::from([id]);
+    while let Some(id) = queue.pop_front() {
+        for &child in children.get(id).into_iter().flatten() {
+            if seen.insert(child.clone()) {
+                queue.push_back(child);
+            }
+        }
+    }
+
+    seen
+}
+
+/// The first tweet of a commit the remote no longer serves, with the reason
+async fn missing_tweet(
+    store: &SqliteStore,
+    adapter: &dyn RemoteAdapter,
+    commit: &Commit,
+) -> Result<Option<(TweetId, String)>> {
+    let mut tweets: Vec<TweetId> = store
+        .get_chunks(&commit.id)
+        .await?
+        .into_iter()
+        .map(|chunk| chunk.id)
+        .collect();
+    if tweets.is_empty() {
+        tweets.push(commit.id.clone());
+    }
+
+    for tweet in tweets {
+        match adapter.fetch(&tweet).await {
+            Ok(_) => {}
+            Err(e @ XFilesError::Api(ApiError::TweetNotFound(_))) => {
+                return Ok(Some((tweet, e.to_string())));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Index a file's root commit again from its root tweet
+///
+/// The commit is dated no later than the file's oldest indexed commit, so
+/// history stays in order. Returns `false` if the tweet is not the root of
+/// `path`.
+async fn reindex_root(
+    store: &SqliteStore,
+    path: &str,
+    root: &TweetId,
+    text: &[u8],
+    author: &str,
+    commits: &[Commit],
+    file_commits: &HashSet<TweetId>,
+) -> Result<bool> {
+    let named = std::str::from_utf8(text).ok().and_then(parse_root).map(|(p, _)| p);
+    if named != Some(path) {
+        return Ok(false);
+    }
+
+    let mut commit = Commit::new(
+        root.clone(),
+        Vec::new(),
+        author.to_string(),
+        compute_hash(text),
+        "text/plain".to_string(),
+        text.len(),
+    );
+    if let Some(oldest) = commits
+        .iter()
+        .filter(|c| file_commits.contains(&c.id))
+        .map(|c| c.timestamp)
+        .min()
+    {
+        commit.timestamp = commit.timestamp.min(oldest);
+    }
+    commit.is_head = file_commits.len() == 1;
+    store.store_commit(&commit).await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_descendants() {
+        let ids: Vec<TweetId> = ["root", "a", "b", "c", "other"].iter().map(|s| s.to_string()).collect();
+        let mut children: HashMap<&TweetId, Vec<&TweetId>> = HashMap::new();
+        children.entry(&ids[0]).or_default().push(&ids[1]);
+        children.entry(&ids[1]).or_default().push(&ids[2]);
+        children.entry(&ids[0]).or_default().push(&ids[3]);
+        children.entry(&ids[3]).or_default().push(&ids[2]);
+
+        let mut found: Vec<_> = descendants(&children, &ids[0]).into_iter().collect();
+        found.sort();
+        assert_eq!(found, vec!["a", "b", "c", "root"]);
+        assert_eq!(descendants(&children, &ids[4]).len(), 1);
+    }
+}
@@ -1,14 +1,165 @@
 //! File history and versioning operations
 
-use crate::dag::commit::Commit;
-use crate::error::Result;
+use crate::dag::commit::{Commit, TweetId};
+use crate::dag::CommitGraph;
+use crate::error::{Result, XFilesError};
+use crate::fs::file::XFile;
+use crate::store::SqliteStore;
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+
+/// Identifies a historical revision of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Revision {
+    /// A specific commit
+    Commit(TweetId),
+    /// The latest revision committed at or before a point in time
+    At(DateTime<Utc>),
+    /// N revisions before the current head (`Back(0)` is the head itself)
+    Back(usize),
+}
 
 /// Retrieve the full history of a file
-pub async fn get_history(_path: &str) -> Result<Vec<Commit>> {
-    todo!("Implement history retrieval")
+///
+/// Returns all commits reachable from the root in chronological order
+pub async fn get_history(store: &SqliteStore, root: &TweetId) -> Result<Vec<Commit>> {
+    let mut commits = Vec::new();
+    let mut to_process = vec![root.clone()];
+    let mut processed = HashSet::new();
+
+    while let Some(id) = to_process.pop() {
+        if processed.contains(&id) {
+            continue;
+        }
+        processed.insert(id.clone());
+
+        if let Some(commit) = store.get_commit(&id).await? {
+            // Get children
+            let children = store.get_children(&id).await?;
+            for child in children {
+                to_process.push(child.id);
+            }
+
+            commits.push(commit);
+        }
+    }
+
+    // Sort by timestamp
+    commits.sort_by_key(|c| c.timestamp);
+
+    Ok(commits)
+}
+
+/// Resolve a revision to a commit in a file's history
+///
+/// Time- and offset-based revisions follow the first-parent chain back
+/// from the current head, so they describe what the file looked like on
+/// its main line of history.
+pub fn resolve_revision<'a>(
+    history: &'a [Commit],
+    root: &TweetId,
+    revision: &Revision,
+) -> Result<&'a Commit> {
+    let mut graph = CommitGraph::new();
+    for commit in history {
+        graph.add_commit(commit.clone());
+    }
+
+    let find = |id: &TweetId| {
+        history
+            .iter()
+            .find(|c| &c.id == id)
+            .ok_or_else(|| XFilesError::CommitNotFound(id.clone()))
+    };
+
+    match revision {
+        Revision::Commit(id) => find(id),
+        Revision::At(time) => {
+            let mut current = find(&graph.find_head(root)?.id)?;
+            while current.timestamp > *time {
+                let parent = current.parents.first().ok_or_else(|| {
+                    XFilesError::CommitNotFound(format!("no revision at {}", time))
+                })?;
+                current = find(parent)?;
+            }
+            Ok(current)
+        }
+        Revision::Back(n) => {
+            let mut current = find(&graph.find_head(root)?.id)?;
+            for _ in 0..*n {
+                let parent = current.parents.first().ok_or_else(|| {
+                    XFilesError::CommitNotFound(format!("{} revisions back", n))
+                })?;
+                current = find(parent)?;
+            }
+            Ok(current)
+        }
+    }
+}
+
+/// Look up the commit a revision of a file refers to
+pub async fn get_commit(file: &XFile, revision: &Revision) -> Result<Commit> {
+    let root = file.root().await?;
+    let history = file.history().await?;
+
+    resolve_revision(&history, &root, revision).cloned()
 }
 
 /// Get a specific version of a file
-pub async fn get_version(_path: &str, _commit_id: &str) -> Result<Vec<u8>> {
-    todo!("Implement version retrieval")
+///
+/// The content is verified against the commit hash before it is returned.
+pub async fn get_version(file: &XFile, revision: &Revision) -> Result<Vec<u8>> {
+    let commit = get_commit(file, revision).await?;
+
+    file.read_commit(&commit.id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn chain(len: usize) -> Vec<Commit> {
+        let start = Utc::now() - Duration::days(len as i64);
+        (0..len)
+            .map(|i| {
+                let parents = if i == 0 { vec![] } else { vec![format!("c{}", i - 1)] };
+                let mut commit = Commit::new(
+                    format!("c{}", i),
+                    parents,
+                    "author".to_string(),
+                    String::new(),
+                    "text/plain".to_string(),
+                    0,
+                );
+                commit.timestamp = start + Duration::days(i as i64);
+                commit
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve_back() {
+        let history = chain(4);
+        let root = "c0".to_string();
+
+        let head = resolve_revision(&history, &root, &Revision::Back(0)).unwrap();
+        assert_eq!(head.id, "c3");
+        let prev = resolve_revision(&history, &root, &Revision::Back(2)).unwrap();
+        assert_eq!(prev.id, "c1");
+        assert!(resolve_revision(&history, &root, &Revision::Back(4)).is_err());
+    }
+
+    #[test]
+    fn test_resolve_at() {
+        let history = chain(4);
+        let root = "c0".to_string();
+
+        let time = history[2].timestamp + Duration::hours(12);
+        let commit = resolve_revision(&history, &root, &Revision::At(time)).unwrap();
+        assert_eq!(commit.id, "c2");
+
+        let before = history[0].timestamp - Duration::hours(1);
+        assert!(resolve_revision(&history, &root, &Revision::At(before)).is_err());
+    }
 }
@@ -1,20 +1,373 @@
 //! Merge strategies for concurrent writes
 
 use crate::dag::commit::Commit;
-use crate::error::Result;
+use crate::dag::diff::{DiffOp, diff_content};
+use crate::error::{Result, XFilesError};
+use serde_json::{Map, Value};
+
+/// One version of a file taking part in a merge
+#[derive(Debug, Clone, Copy)]
+pub struct MergeInput<'a> {
+    /// Commit of this version
+    pub commit: &'a Commit,
+    /// Full content at that commit
+    pub content: &'a [u8],
+}
+
+/// A region both sides of a merge changed in different ways
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictHunk {
+    /// Where the conflict is (a base line for text, a JSON pointer for JSON)
+    pub location: String,
+    /// Content of the region in the common ancestor
+    pub base: Vec<u8>,
+    /// Content of the region on the left side
+    pub left: Vec<u8>,
+    /// Content of the region on the right side
+    pub right: Vec<u8>,
+}
 
 /// Trait for implementing custom merge strategies
-pub trait MergeStrategy {
+pub trait MergeStrategy: Send + Sync {
     /// Merge two conflicting commits
-    fn merge(&self, base: &Commit, left: &Commit, right: &Commit) -> Result<Vec<u8>>;
+    ///
+    /// `base` is the common ancestor of `left` and `right`.
+    fn merge(
+        &self,
+        base: &MergeInput<'_>,
+        left: &MergeInput<'_>,
+        right: &MergeInput<'_>,
+    ) -> Result<Vec<u8>>;
 }
 
 /// Last-writer-wins merge strategy (default for v0.1)
+///
+/// Keeps the content of the most recent commit. Ties on timestamp are
+/// broken by tweet ID, which is monotonic on Twitter.
 pub struct LastWriterWins;
 
 impl MergeStrategy for LastWriterWins {
-    fn merge(&self, _base: &Commit, _left: &Commit, _right: &Commit) -> Result<Vec<u8>> {
-        // For v0.1, simply take the latest commit
-        todo!("Implement last-writer-wins merge")
+    fn merge(
+        &self,
+        _base: &MergeInput<'_>,
+        left: &MergeInput<'_>,
+        right: &MergeInput<'_>,
+    ) -> Result<Vec<u8>> {
+        let key = |input: &MergeInput<'_>| {
+            (input.commit.timestamp, input.commit.id.len(), input.commit.id.clone())
+        };
+
+        let winner = if key(right) > key(left) { right } else { left };
+        Ok(winner.content.to_vec())
+    }
+}
+
+/// Line-based three-way text merge
+///
+/// Changes made on only one side are applied, identical changes on both
+/// sides are applied once, and overlapping different changes are reported
+/// as [`XFilesError::MergeConflict`].
+pub struct ThreeWayMerge;
+
+impl MergeStrategy for ThreeWayMerge {
+    fn merge(
+        &self,
+        base: &MergeInput<'_>,
+        left: &MergeInput<'_>,
+        right: &MergeInput<'_>,
+    ) -> Result<Vec<u8>> {
+        let base_lines = split_lines(base.content);
+        let left_changes = line_changes(&diff_content(base.content, left.content, "text/plain"));
+        let right_changes = line_changes(&diff_content(base.content, right.content, "text/plain"));
+
+        let mut output = Vec::new();
+        let mut conflicts = Vec::new();
+        let mut cursor = 0;
+        let (mut l, mut r) = (0, 0);
+
+        while l < left_changes.len() || r < right_changes.len() {
+            // Start a region at the earliest pending change
+            let first = match (left_changes.get(l), right_changes.get(r)) {
+                (Some(a), Some(b)) if b.start < a.start => b,
+                (Some(a), _) => a,
+                (None, Some(b)) => b,
+                (None, None) => break,
+            };
+            let start = first.start;
+            let mut end = first.end;
+            let (l_start, r_start) = (l, r);
+
+            // Grow the region while changes from either side overlap it
+            loop {
+                let overlaps = |c: &LineChange| c.start < end || c.start == start;
+                if left_changes.get(l).is_some_and(overlaps) {
+                    end = end.max(left_changes[l].end);
+                    l += 1;
+                } else if right_changes.get(r).is_some_and(overlaps) {
+                    end = end.max(right_changes[r].end);
+                    r += 1;
+                } else {
+                    break;
+                }
+            }
+
+            for line in &base_lines[cursor..start] {
+                output.extend_from_slice(line);
+            }
+
+            let left_region = apply_changes(&base_lines, start, end, &left_changes[l_start..l]);
+            let right_region = apply_changes(&base_lines, start, end, &right_changes[r_start..r]);
+            if l == l_start {
+                output.extend_from_slice(&right_region);
+            } else if r == r_start || left_region == right_region {
+                output.extend_from_slice(&left_region);
+            } else {
+                conflicts.push(ConflictHunk {
+                    location: format!("line {}", start + 1),
+                    base: base_lines[start..end].concat(),
+                    left: left_region,
+                    right: right_region,
+                });
+            }
+
+            cursor = end;
+        }
+
+        for line in &base_lines[cursor..] {
+            output.extend_from_slice(line);
+        }
+
+        if !conflicts.is_empty() {
+            return Err(XFilesError::MergeConflict(conflicts));
+        }
+
+        Ok(output)
+    }
+}
+
+/// JSON deep-merge strategy
+///
+/// Objects are merged key by key, recursively. Any other value changed
+/// differently on both sides is reported as [`XFilesError::MergeConflict`],
+/// with the conflict location given as a JSON pointer.
+pub struct JsonMerge;
+
+impl MergeStrategy for JsonMerge {
+    fn merge(
+        &self,
+        base: &MergeInput<'_>,
+        left: &MergeInput<'_>,
+        right: &MergeInput<'_>,
+    ) -> Result<Vec<u8>> {
+        let base: Value = serde_json::from_slice(base.content)?;
+        let left: Value = serde_json::from_slice(left.content)?;
+        let right: Value = serde_json::from_slice(right.content)?;
+
+        let mut conflicts = Vec::new();
+        let merged = merge_json(Some(&base), Some(&left), Some(&right), "", &mut conflicts);
+
+        if !conflicts.is_empty() {
+            return Err(XFilesError::MergeConflict(conflicts));
+        }
+
+        Ok(serde_json::to_vec(&merged.unwrap_or(Value::Null))?)
+    }
+}
+
+/// Three-way merge of JSON values, where `None` means the value is absent
+fn merge_json(
+    base: Option<&Value>,
+    left: Option<&Value>,
+    right: Option<&Value>,
+    pointer: &str,
+    conflicts: &mut Vec<ConflictHunk>,
+) -> Option<Value> {
+    if left == right || right == base {
+        return left.cloned();
+    }
+    if left == base {
+        return right.cloned();
+    }
+
+    if let (Some(Value::Object(l)), Some(Value::Object(r))) = (left, right) {
+        let empty = Map::new();
+        let b = match base {
+            Some(Value::Object(b)) => b,
+            _ => &empty,
+        };
+
+        let mut keys: Vec<&String> = b.keys().chain(l.keys()).chain(r.keys()).collect();
+        keys.sort();
+        keys.dedup();
+
+        let mut merged = Map::new();
+        for key in keys {
+            let child = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+            if let Some(value) = merge_json(b.get(key), l.get(key), r.get(key), &child, conflicts) {
+                merged.insert(key.clone(), value);
+            }
+        }
+        return Some(Value::Object(merged));
+    }
+
+    let render = |value: Option<&Value>| {
+        value.map(|v| serde_json::to_vec(v).unwrap_or_default()).unwrap_or_default()
+    };
+    conflicts.push(ConflictHunk {
+        location: if pointer.is_empty() { "/".to_string() } else { pointer.to_string() },
+        base: render(base),
+        left: render(left),
+        right: render(right),
+    });
+
+    left.cloned()
+}
+
+/// A change to a range of base lines
+#[derive(Debug)]
+struct LineChange {
+    /// First base line replaced
+    start: usize,
+    /// One past the last base line replaced
+    end: usize,
+    /// Replacement content
+    content: Vec<u8>,
+}
+
+/// Convert line diff ops into changes over base line ranges
+fn line_changes(ops: &[DiffOp]) -> Vec<LineChange> {
+    let mut changes = Vec::new();
+    let mut line = 0;
+
+    for op in ops {
+        match op {
+            DiffOp::Equal(data) => line += split_lines(data).len(),
+            DiffOp::Add(data) => changes.push(LineChange {
+                start: line,
+                end: line,
+                content: data.clone(),
+            }),
+            DiffOp::Remove(old) | DiffOp::Modify { old, .. } => {
+                let count = split_lines(old).len();
+                let content = match op {
+                    DiffOp::Modify { new, .. } => new.clone(),
+                    _ => Vec::new(),
+                };
+                changes.push(LineChange { start: line, end: line + count, content });
+                line += count;
+            }
+        }
+    }
+
+    changes
+}
+
+/// Apply one side's changes to the base lines in `start..end`
+fn apply_changes(base: &[&[u8]], start: usize, end: usize, changes: &[LineChange]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut cursor = start;
+
+    for change in changes {
+        for line in &base[cursor..change.start] {
+            output.extend_from_slice(line);
+        }
+        output.extend_from_slice(&change.content);
+        cursor = change.end;
+    }
+    for line in &base[cursor..end] {
+        output.extend_from_slice(line);
+    }
+
+    output
+}
+
+/// Split content into lines, keeping line terminators
+fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(id: &str) -> Commit {
+        Commit::new(
+            id.to_string(),
+            Vec::new(),
+            "author".to_string(),
+            String::new(),
+            "text/plain".to_string(),
+            0,
+        )
+    }
+
+    fn merge(strategy: &dyn MergeStrategy, base: &[u8], left: &[u8], right: &[u8]) -> Result<Vec<u8>> {
+        let (b, l, r) = (commit("1"), commit("2"), commit("3"));
+        strategy.merge(
+            &MergeInput { commit: &b, content: base },
+            &MergeInput { commit: &l, content: left },
+            &MergeInput { commit: &r, content: right },
+        )
+    }
+
+    #[test]
+    fn test_last_writer_wins() {
+        let merged = merge(&LastWriterWins, b"base", b"left", b"right").unwrap();
+        assert_eq!(merged, b"right");
+    }
+
+    #[test]
+    fn test_three_way_clean() {
+        let base = b"a\nb\nc\nd\n";
+        let left = b"A\nb\nc\nd\n";
+        let right = b"a\nb\nc\nD\ne\n";
+        let merged = merge(&ThreeWayMerge, base, left, right).unwrap();
+        assert_eq!(merged, b"A\nb\nc\nD\ne\n");
+
+        // The same change on both sides is applied once
+        let merged = merge(&ThreeWayMerge, base, left, left).unwrap();
+        assert_eq!(merged, left);
+    }
+
+    #[test]
+    fn test_three_way_conflict() {
+        let err = merge(&ThreeWayMerge, b"a\nb\nc\n", b"a\nX\nc\n", b"a\nY\nc\n").unwrap_err();
+        match err {
+            XFilesError::MergeConflict(hunks) => {
+                assert_eq!(hunks.len(), 1);
+                assert_eq!(hunks[0].location, "line 2");
+                assert_eq!(hunks[0].base, b"b\n");
+                assert_eq!(hunks[0].left, b"X\n");
+                assert_eq!(hunks[0].right, b"Y\n");
+            }
+            other => panic!("expected merge conflict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_json_deep_merge() {
+        let base = br#"{"state":"idle","stats":{"runs":1,"errors":0}}"#;
+        let left = br#"{"state":"active","stats":{"runs":1,"errors":0}}"#;
+        let right = br#"{"state":"idle","stats":{"runs":2,"errors":0},"tag":"x"}"#;
+        let merged = merge(&JsonMerge, base, left, right).unwrap();
+
+        let value: Value = serde_json::from_slice(&merged).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"state": "active", "stats": {"runs": 2, "errors": 0}, "tag": "x"})
+        );
+    }
+
+    #[test]
+    fn test_json_conflict() {
+        let err = merge(&JsonMerge, br#"{"a":{"b":1}}"#, br#"{"a":{"b":2}}"#, br#"{"a":{"b":3}}"#)
+            .unwrap_err();
+        match err {
+            XFilesError::MergeConflict(hunks) => {
+                assert_eq!(hunks.len(), 1);
+                assert_eq!(hunks[0].location, "/a/b");
+            }
+            other => panic!("expected merge conflict, got {:?}", other),
+        }
     }
 }
@@ -3,8 +3,14 @@
 //! This module provides the logical filesystem API on top of the DAG layer.
 
 pub mod file;
+pub mod authors;
 pub mod history;
 pub mod merge;
 pub mod chunk;
+pub mod cdc;
+pub mod recovery;
+pub mod verify;
+pub mod fsck;
 
-pub use file::XFile;
+pub use authors::AuthorPolicy;
+pub use file::{Chunking, Verification, WriteOptions, XFile};
@@ -0,0 +1,418 @@
+//! Rebuilding the local index from the remote timeline
+//!
+//! Everything xfiles posts is public, so a lost or fresh SQLite index can
+//! be rebuilt from the account's own tweets: root tweets name the files,
+//! and the reply trees below them hold the commits.
+
+use crate::dag::commit::{ChunkRef, Commit, TweetId};
+use crate::error::Result;
+use crate::fs::authors::AuthorPolicy;
+use crate::fs::chunk::TWEET_MAX_SIZE;
+use crate::fs::file::{TOMBSTONE_MIME, parse_root};
+use crate::remote::RemoteAdapter;
+use crate::remote::twitter::Tweet;
+use crate::store::SqliteStore;
+use crate::util::encoding::{BLOCK_MIME, ContentHeader, decode_with_header, parse_delta_mime};
+use crate::util::hash::compute_hash;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// A tweet recovery could not place in the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedTweet {
+    /// Tweet ID
+    pub id: TweetId,
+    /// Why the tweet was not recovered
+    pub reason: String,
+}
+
+/// Outcome of a recovery run
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    /// Paths of the files found on the timeline
+    pub files: Vec<String>,
+    /// Number of commits added to the index
+    pub commits: usize,
+    /// Tweets that belong to a file but could not be recovered
+    pub unresolved: Vec<UnresolvedTweet>,
+    /// Replies by authors the [`AuthorPolicy`] does not trust, left out of
+    /// the index along with everything posted below them
+    pub foreign: Vec<TweetId>,
+}
+
+impl RecoveryReport {
+    fn unresolved(&mut self, id: &TweetId, reason: impl Into<String>) {
+        self.unresolved.push(UnresolvedTweet {
+            id: id.clone(),
+            reason: reason.into(),
+        });
+    }
+}
+
+/// Rebuild the `files`, `commits` and `chunks` tables from the timeline
+///
+/// Commits already in the index are kept as they are, so recovery can be
+/// run against a partial index. Only tweets the timeline still serves can
+/// be recovered (Twitter returns the most recent 3200).
+///
+/// Commit metadata (MIME type, size, hash, merge parents) is taken from
+/// the envelope posted with each commit. Tweets posted before the envelope
+/// existed are indexed as plain text. Block tweets are added to the
+/// `blocks` table so later writes keep sharing them. Encrypted commits are
+/// indexed from their envelope without checking their keyed hash.
+///
+/// Files that gained commits have their head ref dropped, so their next
+/// open resolves the head again.
+///
+/// The account's own commits are recorded as authored by `author`, and
+/// those of others by their remote author ID. Replies by authors `authors`
+/// does not trust are reported as foreign instead.
+pub async fn recover(
+    store: &SqliteStore,
+    adapter: &dyn RemoteAdapter,
+    author: &str,
+    authors: &AuthorPolicy,
+) -> Result<RecoveryReport> {
+    let account = adapter.account_id().await?;
+    let mut timeline = adapter.fetch_timeline().await?;
+    timeline.sort_by(|a, b| tweet_order(&a.id).cmp(&tweet_order(&b.id)));
+
+    let mut replies: HashMap<&TweetId, Vec<&Tweet>> = HashMap::new();
+    for tweet in &timeline {
+        if let Some(parent) = &tweet.in_reply_to {
+            replies.entry(parent).or_default().push(tweet);
+        }
+    }
+    let tweets = Tweets {
+        by_id: timeline.iter().map(|t| (&t.id, t)).collect(),
+        replies,
+    };
+
+    let mut report = RecoveryReport::default();
+    let mut roots = HashSet::new();
+    let mut claimed = HashSet::new();
+
+    for root in timeline.iter().filter(|t| t.in_reply_to.is_none()) {
+        let Some((path, _)) = parse_root(&root.text) else {
+            continue;
+        };
+        roots.insert(root.id.clone());
+
+        if path.is_empty() {
+            report.unresolved(&root.id, "root tweet names no path");
+            continue;
+        }
+        match store.get_file_root(path).await? {
+            Some(existing) if existing != root.id => {
+                report.unresolved(&root.id, format!("{} already has root {}", path, existing));
+                continue;
+            }
+            Some(_) => {}
+            None => store.register_file(path, &root.id).await?,
+        }
+
+        if store.get_commit(&root.id).await?.is_none() {
+            let content = root.text.as_bytes();
+            let mut commit = Commit::new(
+                root.id.clone(),
+                Vec::new(),
+                author.to_string(),
+                compute_hash(content),
+                "text/plain".to_string(),
+                content.len(),
+            );
+            commit.timestamp = tweet_time(root);
+            store.store_commit(&commit).await?;
+            report.commits += 1;
+        }
+        claimed.insert(root.id.clone());
+        report.files.push(path.to_string());
+        let indexed = report.commits;
+
+        // Walk the reply tree, one commit at a time
+        let mut queue = VecDeque::from([root.id.clone()]);
+        while let Some(parent) = queue.pop_front() {
+            let chunk_ids: HashSet<TweetId> = store
+                .get_chunks(&parent)
+                .await?
+                .into_iter()
+                .map(|c| c.id)
+                .collect();
+            claimed.extend(chunk_ids.iter().cloned());
+
+            let (replies, foreign): (Vec<&Tweet>, Vec<&Tweet>) = tweets
+                .replies
+                .get(&parent)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|t| !chunk_ids.contains(&t.id))
+                .partition(|t| {
+                    t.author_id == account || authors.trusts(path, &t.author_id)
+                });
+            for tweet in foreign {
+                report.foreign.push(tweet.id.clone());
+                claimed.extend(tweets.descendants(&tweet.id));
+            }
+
+            let (blocks, children): (Vec<&Tweet>, Vec<&Tweet>) =
+                replies.into_iter().partition(|t| is_block(t));
+            if children.is_empty() {
+                store.set_head(&parent).await?;
+            }
+
+            // Blocks posted alongside a commit are content, not commits
+            for tweet in blocks {
+                match read_commit(tweet, &tweets) {
+                    Ok(block) => {
+                        if let Some(header) = &block.header {
+                            store.store_block(&header.hash, &tweet.id, header.size).await?;
+                        }
+                        claimed.extend(block.chunks.iter().map(|t| t.id.clone()));
+                    }
+                    Err(reason) => report.unresolved(&tweet.id, reason),
+                }
+            }
+
+            for tweet in children {
+                if store.get_commit(&tweet.id).await?.is_none() {
+                    let recovered = match read_commit(tweet, &tweets) {
+                        Ok(recovered) => recovered,
+                        Err(reason) => {
+                            report.unresolved(&tweet.id, reason);
+                            continue;
+                        }
+                    };
+                    let author = if tweet.author_id == account { author } else { tweet.author_id.as_str() };
+                    recovered.store(store, &parent, author, tweet_time(tweet)).await?;
+                    report.commits += 1;
+                }
+                claimed.insert(tweet.id.clone());
+                queue.push_back(tweet.id.clone());
+            }
+        }
+
+        // New commits may have moved the head
+        if report.commits > indexed {
+            store.remove_file_head(path).await?;
+        }
+    }
+
+    // Anything left in a file's conversation was not reachable from its root
+    for tweet in &timeline {
+        let in_file = tweet.conversation_id.as_ref().is_some_and(|c| roots.contains(c));
+        let reported = report.unresolved.iter().any(|u| u.id == tweet.id);
+        if in_file && !claimed.contains(&tweet.id) && !reported {
+            report.unresolved(&tweet.id, "not reachable from the file root");
+        }
+    }
+
+    report.files.sort();
+    report.files.dedup();
+
+    Ok(report)
+}
+
+/// The timeline, indexed for walking reply trees
+struct Tweets<'a> {
+    /// Tweets by ID
+    by_id: HashMap<&'a TweetId, &'a Tweet>,
+    /// Replies to each tweet, in posting order
+    replies: HashMap<&'a TweetId, Vec<&'a Tweet>>,
+}
+
+impl Tweets<'_> {
+    /// IDs of a tweet and every tweet below it in the reply tree
+    fn descendants(&self, id: &TweetId) -> Vec<TweetId> {
+        let mut found = vec![id.clone()];
+        let mut index = 0;
+        while let Some(current) = found.get(index) {
+            let below = self.replies.get(current).into_iter().flatten();
+            let below: Vec<TweetId> = below.map(|t| t.id.clone()).collect();
+            found.extend(below);
+            index += 1;
+        }
+
+        found
+    }
+}
+
+/// A commit read back from its chunk tweets
+struct RecoveredCommit<'a> {
+    /// Chunk tweets in order
+    chunks: Vec<&'a Tweet>,
+    /// Envelope of the first chunk, if it has one
+    header: Option<ContentHeader>,
+    /// Reassembled payload, with blocks resolved
+    payload: Vec<u8>,
+}
+
+impl RecoveredCommit<'_> {
+    /// Index the commit and its chunk manifest
+    async fn store(
+        &self,
+        store: &SqliteStore,
+        parent: &TweetId,
+        author: &str,
+        timestamp: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut parents = vec![parent.clone()];
+        let mut commit = match &self.header {
+            Some(header) => {
+                parents.extend(header.parents.iter().cloned());
+                Commit::new(
+                    self.chunks[0].id.clone(),
+                    parents,
+                    author.to_string(),
+                    header.hash.clone(),
+                    header.mime.clone(),
+                    header.size,
+                )
+            }
+            None => {
+                let mime = if self.payload == b"[DELETED]" { TOMBSTONE_MIME } else { "text/plain" };
+                Commit::new(
+                    self.chunks[0].id.clone(),
+                    parents,
+                    author.to_string(),
+                    compute_hash(&self.payload),
+                    mime.to_string(),
+                    self.payload.len(),
+                )
+            }
+        };
+        commit.timestamp = timestamp;
+        commit.encoding = self.header.as_ref().and_then(|h| h.encoding);
+
+        let manifest: Vec<ChunkRef> = self
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(index, tweet)| ChunkRef {
+                id: tweet.id.clone(),
+                index,
+                size: tweet.text.len(),
+                hash: compute_hash(tweet.text.as_bytes()),
+            })
+            .collect();
+
+        store.store_commit(&commit).await?;
+        store.store_chunks(&commit.id, &manifest).await
+    }
+}
+
+/// Read a commit (or block) starting at its first chunk tweet
+///
+/// Chunks are followed through their envelope headers. Tweets posted
+/// before the envelope existed continue only if they fill a whole tweet,
+/// with the earliest reply as the continuation. Block lists are resolved
+/// against the timeline. Returns why the commit could not be read on
+/// failure.
+fn read_commit<'a>(
+    first: &'a Tweet,
+    tweets: &Tweets<'a>,
+) -> std::result::Result<RecoveredCommit<'a>, String> {
+    let replies = &tweets.replies;
+    let (header, body) = decode_with_header(first.text.as_bytes()).map_err(|e| e.to_string())?;
+    let mut chunks = vec![first];
+    let mut parts = vec![body];
+
+    match &header {
+        Some(header) => {
+            if header.index != 0 {
+                return Err(format!("chunk {} of a commit not found", header.index));
+            }
+            for index in 1..header.count {
+                // Nonces differ between chunks that were posted again
+                let expected = ContentHeader { nonce: None, ..header.for_chunk(index, header.count) };
+                let next = replies
+                    .get(&chunks[index - 1].id)
+                    .into_iter()
+                    .flatten()
+                    .find_map(|t| match decode_with_header(t.text.as_bytes()) {
+                        Ok((Some(mut h), body)) => {
+                            h.nonce = None;
+                            (h == expected).then_some((*t, body))
+                        }
+                        _ => None,
+                    })
+                    .ok_or_else(|| format!("commit is missing chunk {} of {}", index, header.count))?;
+                chunks.push(next.0);
+                parts.push(next.1);
+            }
+        }
+        None => {
+            let mut last = first;
+            while last.text.len() == TWEET_MAX_SIZE {
+                last = replies
+                    .get(&last.id)
+                    .and_then(|r| r.first())
+                    .copied()
+                    .ok_or("commit is missing its final chunk")?;
+                chunks.push(last);
+                parts.push(last.text.as_bytes().to_vec());
+            }
+        }
+    }
+
+    let mut payload = parts.concat();
+    if let Some(header) = &header
+        && header.blocks
+    {
+        payload = read_blocks(&payload, tweets)?;
+    }
+    if header.as_ref().is_some_and(|h| h.key.is_some()) {
+        // Encrypted - only key holders can decrypt and check the hash
+        return Ok(RecoveredCommit { chunks, header, payload });
+    }
+    if let Some(header) = &header
+        && let Some(compression) = header.compression
+    {
+        payload = compression.decompress(&payload, header.size).map_err(|e| e.to_string())?;
+    }
+    if let Some(header) = &header
+        && parse_delta_mime(&header.mime).is_none()
+        && compute_hash(&payload) != header.hash
+    {
+        return Err("content does not match its hash".to_string());
+    }
+
+    Ok(RecoveredCommit { chunks, header, payload })
+}
+
+/// Reassemble a payload from its block list
+fn read_blocks(list: &[u8], tweets: &Tweets) -> std::result::Result<Vec<u8>, String> {
+    let list = std::str::from_utf8(list).map_err(|e| format!("invalid block list: {}", e))?;
+
+    let mut parts = Vec::new();
+    for id in list.lines().map(str::to_string) {
+        let tweet = tweets
+            .by_id
+            .get(&id)
+            .filter(|t| is_block(t))
+            .ok_or_else(|| format!("block {} not found", id))?;
+        parts.push(read_commit(tweet, tweets)?.payload);
+    }
+
+    Ok(parts.concat())
+}
+
+/// Whether a tweet holds a content-defined block
+fn is_block(tweet: &Tweet) -> bool {
+    matches!(
+        decode_with_header(tweet.text.as_bytes()),
+        Ok((Some(header), _)) if header.mime == BLOCK_MIME && !header.blocks
+    )
+}
+
+/// Sort key putting tweet IDs in posting order
+fn tweet_order(id: &TweetId) -> (usize, &str) {
+    (id.len(), id.as_str())
+}
+
+/// Creation time of a tweet
+fn tweet_time(tweet: &Tweet) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(&tweet.created_at)
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now())
+}
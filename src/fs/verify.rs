@@ -0,0 +1,137 @@
+//! Checking a file's commits against what is on the remote
+//!
+//! Reads only fetch the commits they need and trust the content cache.
+//! [`verify_file`] downloads every commit of a file again and reports the
+//! ones that are no longer what was posted: tweets that were deleted or
+//! edited, and content that no longer matches its hash.
+
+use crate::dag::commit::{Commit, TweetId};
+use crate::error::{ApiError, Result, XFilesError};
+use crate::fs::file::XFile;
+use crate::util::encoding::parse_delta_mime;
+use crate::util::hash::compute_hash;
+use std::collections::HashSet;
+
+/// What is wrong with a commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Damage {
+    /// A tweet of the commit can no longer be fetched
+    Missing {
+        /// The missing tweet
+        tweet: TweetId,
+        /// Why the remote could not serve it
+        reason: String,
+    },
+    /// A tweet of the commit differs from what was posted
+    Edited {
+        /// The edited tweet
+        tweet: TweetId,
+    },
+    /// The commit's tweets are there, but its content cannot be read back
+    /// or does not match its hash
+    Corrupt {
+        /// Why the content could not be read
+        reason: String,
+    },
+    /// A delta commit whose base revision is damaged, so its content
+    /// cannot be rebuilt
+    BrokenBase {
+        /// The damaged commit the patch applies to
+        base: TweetId,
+    },
+}
+
+/// A damaged commit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DamagedCommit {
+    /// Commit ID
+    pub commit: TweetId,
+    /// What is wrong with it
+    pub damage: Damage,
+}
+
+/// Outcome of verifying a file
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    /// Path of the file
+    pub path: String,
+    /// Number of commits checked
+    pub checked: usize,
+    /// Damaged commits, in history order
+    pub damaged: Vec<DamagedCommit>,
+}
+
+impl VerifyReport {
+    /// Whether every commit is intact
+    pub fn is_ok(&self) -> bool {
+        self.damaged.is_empty()
+    }
+}
+
+/// Download every commit of a file again and check it
+///
+/// Every tweet listed in a commit's chunk manifest must still be served
+/// exactly as posted, and the reassembled content must match the commit
+/// hash. Commits are checked oldest first, bypassing the content cache.
+pub async fn verify_file(file: &XFile) -> Result<VerifyReport> {
+    let file = file.uncached();
+    let mut report = VerifyReport {
+        path: file.path().to_string(),
+        ..Default::default()
+    };
+
+    let mut damaged = HashSet::new();
+    for commit in file.history().await? {
+        report.checked += 1;
+        if let Some(damage) = check_commit(&file, &commit, &damaged).await? {
+            damaged.insert(commit.id.clone());
+            report.damaged.push(DamagedCommit {
+                commit: commit.id,
+                damage,
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+/// Check a single commit, given the commits already found damaged
+async fn check_commit(
+    file: &XFile,
+    commit: &Commit,
+    damaged: &HashSet<TweetId>,
+) -> Result<Option<Damage>> {
+    if parse_delta_mime(&commit.mime).is_some()
+        && let Some(base) = commit.parents.first().filter(|p| damaged.contains(*p))
+    {
+        return Ok(Some(Damage::BrokenBase { base: base.clone() }));
+    }
+
+    // Every chunk must still be there as posted
+    let chunks = file.store().get_chunks(&commit.id).await?;
+    let manifest = !chunks.is_empty();
+    for chunk in chunks {
+        match file.adapter().fetch(&chunk.id).await {
+            Ok(tweet) if compute_hash(&tweet) != chunk.hash => {
+                return Ok(Some(Damage::Edited { tweet: chunk.id }));
+            }
+            Ok(_) => {}
+            Err(e @ XFilesError::Api(ApiError::TweetNotFound(_))) => {
+                return Ok(Some(Damage::Missing { tweet: chunk.id, reason: e.to_string() }));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // Then the content must read back and match its hash
+    match file.read_commit(&commit.id).await {
+        Ok(_) => Ok(None),
+        Err(e @ XFilesError::Api(ApiError::TweetNotFound(_))) if !manifest => {
+            // Without a manifest, the commit tweet itself was fetched
+            Ok(Some(Damage::Missing { tweet: commit.id.clone(), reason: e.to_string() }))
+        }
+        Err(e @ (XFilesError::Database(_) | XFilesError::Http(_) | XFilesError::Api(_)
+            | XFilesError::TwitterApi(_) | XFilesError::RateLimitExceeded { .. })) => Err(e),
+        Err(e) => Ok(Some(Damage::Corrupt { reason: e.to_string() })),
+    }
+}
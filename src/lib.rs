@@ -41,13 +41,17 @@ pub mod remote;
 pub mod util;
 
 // Re-export commonly used types
-pub use error::{Result, XFilesError};
-pub use fs::{XFile, chunk::TWEET_MAX_SIZE};
-pub use dag::{Commit, TweetId};
+pub use error::{ApiError, Result, XFilesError};
+pub use fs::{AuthorPolicy, Chunking, Verification, WriteOptions, XFile, chunk::TWEET_MAX_SIZE, history::Revision, recovery::RecoveryReport, verify::VerifyReport, fsck::FsckReport};
+pub use dag::{Commit, TweetId, diff::DiffOp};
+pub use util::{codec::TextCodec, compress::Compression, crypto::Keyring, sign::Signer};
 pub use remote::{RemoteAdapter, MockAdapter};
 
+use fs::merge::{MergeInput, MergeStrategy};
+use remote::twitter::Tweet;
 use store::{SqliteStore, ContentCache};
 use remote::TwitterAdapter;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 /// File open mode
@@ -71,6 +75,21 @@ pub struct XFS {
     adapter: Arc<dyn RemoteAdapter>,
     /// Content cache
     cache: Arc<ContentCache>,
+    /// Write options applied to opened files
+    write_options: WriteOptions,
+    /// Master keys for encrypted files
+    keyring: Option<Arc<Keyring>>,
+    /// Key commits are signed with
+    signer: Option<Arc<Signer>>,
+    /// How commit signatures are checked
+    verification: Verification,
+    /// Authors whose replies count as commits
+    authors: Arc<AuthorPolicy>,
+    /// Author ID of the account, fetched on first use
+    account: tokio::sync::OnceCell<String>,
+    /// Paths whose head was resolved again since the author policy or
+    /// verification mode changed, `None` while recorded heads still hold
+    refreshed: Option<HashSet<String>>,
 }
 
 impl XFS {
@@ -136,6 +155,13 @@ impl XFS {
             store: Arc::new(store),
             adapter: Arc::new(adapter),
             cache: Arc::new(cache),
+            write_options: WriteOptions::default(),
+            keyring: None,
+            signer: None,
+            verification: Verification::Off,
+            authors: Arc::new(AuthorPolicy::anyone()),
+            account: tokio::sync::OnceCell::new(),
+            refreshed: None,
         })
     }
 
@@ -167,6 +193,13 @@ impl XFS {
             store: Arc::new(store),
             adapter,
             cache: Arc::new(cache),
+            write_options: WriteOptions::default(),
+            keyring: None,
+            signer: None,
+            verification: Verification::Off,
+            authors: Arc::new(AuthorPolicy::anyone()),
+            account: tokio::sync::OnceCell::new(),
+            refreshed: None,
         })
     }
 
@@ -186,46 +219,51 @@ impl XFS {
                 Err(XFilesError::Other(format!("File already exists: {}", path)))
             }
             (None, OpenMode::Create) => {
-                // Create new file - post root tweet with filename
-                let initial_content = format!("📁 {}", path);
-                let initial_bytes = initial_content.as_bytes();
-                let root_id = self.adapter.store(initial_bytes).await?;
+                // Create new file - post root tweet with filename and writer keys
+                let writers = self.signer.as_ref().map(|s| s.writers()).unwrap_or_default();
+                let initial_content = fs::file::root_text(path, writers);
+                let (root_id, initial_bytes) = fs::file::post_unique(
+                    self.adapter.as_ref(),
+                    None,
+                    initial_content.as_bytes(),
+                    |n| Ok(fs::file::mark_root(&initial_content, n).into_bytes()),
+                )
+                .await?;
 
                 // Create root commit
                 let commit = Commit::new(
                     root_id.clone(),
                     Vec::new(), // No parents for root
                     self.user.clone(),
-                    util::hash::compute_hash(initial_bytes),
+                    util::hash::compute_hash(&initial_bytes),
                     "text/plain".to_string(),
                     initial_bytes.len(),
                 );
 
-                self.store.store_commit(&commit).await?;
                 self.store.register_file(path, &root_id).await?;
-                self.store.set_head(&root_id).await?;
-
-                Ok(XFile::new(
-                    path.to_string(),
-                    root_id,
-                    self.store.clone(),
-                    self.adapter.clone(),
-                    self.cache.clone(),
-                    self.user.clone(),
-                ))
+                self.store.advance_head(path, &commit, &[]).await?;
+
+                Ok(self.file_handle(path, root_id))
             }
             (Some(root_id), OpenMode::ReadOnly) | (Some(root_id), OpenMode::ReadWrite) => {
-                // Open existing file - find current head
-                let head = self.find_head(&root_id).await?;
-
-                Ok(XFile::new(
-                    path.to_string(),
-                    head,
-                    self.store.clone(),
-                    self.adapter.clone(),
-                    self.cache.clone(),
-                    self.user.clone(),
-                ))
+                // Open existing file - use the recorded head, resolving it
+                // from the remote only for files opened before refs existed
+                let recorded = match &self.refreshed {
+                    Some(done) if !done.contains(path) => None,
+                    _ => self.store.get_file_head(path).await?,
+                };
+                let head = match recorded {
+                    Some(head) => head,
+                    None => {
+                        let head = self.resolve_head(path, &root_id).await?;
+                        if let Some(done) = &mut self.refreshed {
+                            done.insert(path.to_string());
+                        }
+                        head
+                    }
+                };
+
+                Ok(self.file_handle(path, head))
             }
             (None, OpenMode::ReadOnly) | (None, OpenMode::ReadWrite) => {
                 // File doesn't exist
@@ -234,10 +272,45 @@ impl XFS {
         }
     }
 
+    /// Resolve the head of a file from the remote again
+    ///
+    /// [`XFS::open`] uses the head recorded by the last write or open.
+    /// Refreshing walks the file's reply tree from the root, with the
+    /// current [`AuthorPolicy`] and [`Verification`] mode, and records
+    /// the head it finds.
+    pub async fn refresh(&self, path: &str) -> Result<TweetId> {
+        let root = self.store.get_file_root(path).await?
+            .ok_or_else(|| XFilesError::FileNotFound(path.to_string()))?;
+
+        self.resolve_head(path, &root).await
+    }
+
+    /// Resolve the head of a file from the remote and record it
+    async fn resolve_head(&self, path: &str, root_id: &TweetId) -> Result<TweetId> {
+        let head = self.find_head(path, root_id).await?;
+        self.store.set_file_head(path, &head).await?;
+
+        Ok(head)
+    }
+
     /// Find the current head commit for a file
-    async fn find_head(&self, root_id: &TweetId) -> Result<TweetId> {
-        // Get all replies to find the head
-        let replies = self.adapter.fetch_replies(root_id).await?;
+    ///
+    /// Replies by authors the [`AuthorPolicy`] does not trust are ignored,
+    /// and the rest are checked against the file's writer keys as the
+    /// [`Verification`] mode says.
+    async fn find_head(&self, path: &str, root_id: &TweetId) -> Result<TweetId> {
+        // List the whole thread once, and index it by the tweet replied to
+        let mut thread: HashMap<TweetId, Vec<Tweet>> = HashMap::new();
+        for reply in self.adapter.fetch_thread(root_id).await? {
+            if let Some(parent) = reply.in_reply_to.clone() {
+                thread.entry(parent).or_default().push(reply);
+            }
+        }
+        let no_replies = Vec::new();
+        let replies_to = |id: &TweetId| thread.get(id).unwrap_or(&no_replies);
+
+        // Get all trusted replies to find the head
+        let replies = self.trusted_replies(path, replies_to(root_id)).await?;
 
         if replies.is_empty() {
             // Root is the head
@@ -253,18 +326,24 @@ impl XFS {
         }
 
         // Add all descendants
-        let mut to_process = replies.clone();
-        while !to_process.is_empty() {
-            let id = to_process.remove(0);
-
+        let file = self.file_handle(path, root_id.clone());
+        let mut seen: std::collections::HashSet<TweetId> = replies.iter().cloned().collect();
+        let mut to_process: std::collections::VecDeque<TweetId> = replies.into();
+        while let Some(id) = to_process.pop_front() {
             if let Some(commit) = self.store.get_commit(&id).await? {
-                graph.add_commit(commit.clone());
+                match file.verify_commit(&commit).await {
+                    Ok(()) => {}
+                    Err(XFilesError::InvalidSignature { .. })
+                        if self.verification == Verification::Skip => continue,
+                    Err(e) => return Err(e),
+                }
+                graph.add_commit(commit);
 
                 // Get replies to this commit
-                let child_replies = self.adapter.fetch_replies(&id).await?;
+                let child_replies = self.trusted_replies(path, replies_to(&id)).await?;
                 for reply_id in child_replies {
-                    if !to_process.contains(&reply_id) {
-                        to_process.push(reply_id);
+                    if seen.insert(reply_id.clone()) {
+                        to_process.push_back(reply_id);
                     }
                 }
             }
@@ -275,6 +354,31 @@ impl XFS {
         Ok(head_commit.id.clone())
     }
 
+    /// Replies by the account or a trusted author
+    async fn trusted_replies(&self, path: &str, replies: &[Tweet]) -> Result<Vec<TweetId>> {
+        let mut trusted = Vec::new();
+        for reply in replies {
+            if self.is_trusted(path, reply).await? {
+                trusted.push(reply.id.clone());
+            }
+        }
+
+        Ok(trusted)
+    }
+
+    /// Whether a reply is by the account or an author trusted for `path`
+    async fn is_trusted(&self, path: &str, reply: &Tweet) -> Result<bool> {
+        if self.authors.trusts(path, &reply.author_id) {
+            return Ok(true);
+        }
+
+        let account = self
+            .account
+            .get_or_try_init(|| self.adapter.account_id())
+            .await?;
+        Ok(reply.author_id == *account)
+    }
+
     /// List files in a directory
     ///
     /// # Arguments
@@ -303,37 +407,220 @@ impl XFS {
 
     /// Get the history of a file
     ///
-    /// Returns all commits in chronological order
+    /// Returns all commits in chronological order, leaving out those by
+    /// authors the [`AuthorPolicy`] does not trust and everything built on
+    /// them.
     pub async fn history(&self, path: &str) -> Result<Vec<Commit>> {
+        self.history_handle(path).await?.history().await
+    }
+
+    /// Read a file as it was at a historical revision
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use xfiles::{XFS, Revision};
+    /// # async fn example(fs: &XFS) -> xfiles::error::Result<()> {
+    /// // What the agent believed yesterday
+    /// let yesterday = chrono::Utc::now() - chrono::Duration::days(1);
+    /// let memory = fs.read_at("memory.txt", Revision::At(yesterday)).await?;
+    ///
+    /// // The revision before the current one
+    /// let previous = fs.read_at("memory.txt", Revision::Back(1)).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn read_at(&self, path: &str, revision: Revision) -> Result<Vec<u8>> {
+        let file = self.history_handle(path).await?;
+        fs::history::get_version(&file, &revision).await
+    }
+
+    /// Compute the diff between two revisions of a file
+    ///
+    /// Use [`dag::diff::unified_diff`] to render the result for review.
+    pub async fn diff(&self, path: &str, old: Revision, new: Revision) -> Result<Vec<DiffOp>> {
+        let file = self.history_handle(path).await?;
+
+        let old_commit = fs::history::get_commit(&file, &old).await?;
+        let new_commit = fs::history::get_commit(&file, &new).await?;
+        let old_content = file.read_commit(&old_commit.id).await?;
+        let new_content = file.read_commit(&new_commit.id).await?;
+
+        dag::diff::diff_commits(&old_commit, &old_content, &new_commit, &new_content)
+    }
+
+    /// Build a file handle for history lookups
+    ///
+    /// Revisions resolve the head from the history, so the handle points at
+    /// the root.
+    async fn history_handle(&self, path: &str) -> Result<XFile> {
         let root = self.store.get_file_root(path).await?
             .ok_or_else(|| XFilesError::FileNotFound(path.to_string()))?;
 
-        // Get all commits starting from root
-        let mut commits = Vec::new();
-        let mut to_process = vec![root.clone()];
-        let mut processed = std::collections::HashSet::new();
+        Ok(self.file_handle(path, root))
+    }
 
-        while let Some(id) = to_process.pop() {
-            if processed.contains(&id) {
-                continue;
-            }
-            processed.insert(id.clone());
+    /// Build a file handle at the given head with the default write options
+    fn file_handle(&self, path: &str, head: TweetId) -> XFile {
+        let mut file = XFile::new(
+            path.to_string(),
+            head,
+            self.store.clone(),
+            self.adapter.clone(),
+            self.cache.clone(),
+            self.user.clone(),
+        );
+        file.set_options(self.write_options.clone());
+        file.set_keyring(self.keyring.clone());
+        file.set_signer(self.signer.clone());
+        file.set_verification(self.verification);
+        file.set_authors(self.authors.clone());
+        file
+    }
 
-            if let Some(commit) = self.store.get_commit(&id).await? {
-                // Get children
-                let children = self.store.get_children(&id).await?;
-                for child in children {
-                    to_process.push(child.id);
-                }
+    /// Merge forked heads of a file
+    ///
+    /// Finds every head of the file, and merges them one by one into the
+    /// most recent head using `strategy` and their common ancestor. Each
+    /// step posts a merge commit with two parents. Returns the final head.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use xfiles::XFS;
+    /// # use xfiles::fs::merge::ThreeWayMerge;
+    /// # async fn example(fs: &XFS) -> xfiles::error::Result<()> {
+    /// let head = fs.merge("memory.txt", &ThreeWayMerge).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn merge(&self, path: &str, strategy: &dyn MergeStrategy) -> Result<TweetId> {
+        let root = self.store.get_file_root(path).await?
+            .ok_or_else(|| XFilesError::FileNotFound(path.to_string()))?;
 
-                commits.push(commit);
-            }
+        let mut graph = dag::CommitGraph::new();
+        for commit in self.history_handle(path).await?.history().await? {
+            graph.add_commit(commit);
         }
 
-        // Sort by timestamp
-        commits.sort_by_key(|c| c.timestamp);
+        // Merge everything into the most recent head
+        let mut heads: Vec<Commit> = graph.detect_forks(&root)?
+            .iter()
+            .filter_map(|id| graph.get_commit(id).cloned())
+            .collect();
+        heads.sort_by_key(|c| std::cmp::Reverse((c.timestamp, c.id.len(), c.id.clone())));
+
+        let Some((latest, others)) = heads.split_first() else {
+            return Err(XFilesError::CommitNotFound(root));
+        };
+        let mut file = self.file_handle(path, latest.id.clone());
+
+        for other in others {
+            let base_id = graph.merge_base(&file.head, &other.id)
+                .ok_or_else(|| XFilesError::CommitNotFound(other.id.clone()))?;
+            let (Some(base), Some(left)) =
+                (graph.get_commit(&base_id), graph.get_commit(&file.head))
+            else {
+                return Err(XFilesError::CommitNotFound(base_id));
+            };
+
+            let base_content = file.read_commit(&base.id).await?;
+            let left_content = file.read_commit(&left.id).await?;
+            let right_content = file.read_commit(&other.id).await?;
+
+            let merged = strategy.merge(
+                &MergeInput { commit: base, content: &base_content },
+                &MergeInput { commit: left, content: &left_content },
+                &MergeInput { commit: other, content: &right_content },
+            )?;
 
-        Ok(commits)
+            let mime = util::encoding::parse_delta_mime(&left.mime)
+                .unwrap_or(&left.mime)
+                .to_string();
+            file.write_merge(&merged, mime, &other.id).await?;
+
+            let commit = self.store.get_commit(&file.head).await?
+                .ok_or_else(|| XFilesError::CommitNotFound(file.head.clone()))?;
+            graph.add_commit(commit);
+        }
+
+        Ok(file.head)
+    }
+
+    /// Rebuild the local index from the account's timeline
+    ///
+    /// Registers every file whose root tweet is found and indexes the
+    /// commits in its reply tree. Use this after losing the SQLite database
+    /// or when moving to a new machine. Tweets that belong to a file but
+    /// could not be placed are listed in the report.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use xfiles::XFS;
+    /// # async fn example(fs: XFS) -> xfiles::error::Result<()> {
+    /// let report = fs.recover().await?;
+    /// println!("recovered {} files", report.files.len());
+    /// for tweet in &report.unresolved {
+    ///     eprintln!("{}: {}", tweet.id, tweet.reason);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn recover(&self) -> Result<RecoveryReport> {
+        fs::recovery::recover(&self.store, self.adapter.as_ref(), &self.user, &self.authors).await
+    }
+
+    /// Download every commit of a file again and check it
+    ///
+    /// Reports commits whose tweets were deleted or edited, or whose
+    /// content no longer matches its hash. See [`fs::verify::verify_file`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use xfiles::XFS;
+    /// # async fn example(fs: &XFS) -> xfiles::error::Result<()> {
+    /// let report = fs.verify("memory.txt").await?;
+    /// for damaged in &report.damaged {
+    ///     eprintln!("{}: {:?}", damaged.commit, damaged.damage);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn verify(&self, path: &str) -> Result<VerifyReport> {
+        let file = self.history_handle(path).await?;
+        fs::verify::verify_file(&file).await
+    }
+
+    /// Check the local index against the remote
+    ///
+    /// Reports root, commit and block tweets that are gone, commits no
+    /// file reaches, and stale head flags, without changing anything. See
+    /// [`fs::fsck::fsck`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use xfiles::XFS;
+    /// # async fn example(fs: &XFS) -> xfiles::error::Result<()> {
+    /// let report = fs.fsck().await?;
+    /// for finding in &report.findings {
+    ///     eprintln!("{:?}", finding.problem);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn fsck(&self) -> Result<FsckReport> {
+        fs::fsck::fsck(&self.store, self.adapter.as_ref(), &self.user, false).await
+    }
+
+    /// Check the local index against the remote and fix it where possible
+    ///
+    /// Finds the same problems as [`XFS::fsck`]. Findings the index could
+    /// be fixed for are marked as repaired.
+    pub async fn repair(&self) -> Result<FsckReport> {
+        fs::fsck::fsck(&self.store, self.adapter.as_ref(), &self.user, true).await
     }
 
     /// Check if a file exists
@@ -345,6 +632,125 @@ impl XFS {
     pub fn user(&self) -> &str {
         &self.user
     }
+
+    /// Drop all cached content, forcing the next reads to hit the remote
+    pub fn clear_cache(&self) {
+        self.cache.clear();
+    }
+
+    /// Set the write options applied to files opened from now on
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use xfiles::{XFS, WriteOptions};
+    /// # fn example(fs: &mut XFS) {
+    /// // Post patches instead of full content, with a snapshot every 10 deltas
+    /// fs.set_write_options(WriteOptions {
+    ///     snapshot_interval: Some(10),
+    ///     ..Default::default()
+    /// });
+    /// # }
+    /// ```
+    pub fn set_write_options(&mut self, options: WriteOptions) {
+        self.write_options = options;
+    }
+
+    /// Set the master keys for encrypted files
+    ///
+    /// Each file gets its own keys, derived from the master key and its
+    /// path. Writes are only encrypted with [`WriteOptions::encrypt`] set,
+    /// but reading an encrypted file always needs the keyring.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use xfiles::{Keyring, OpenMode, WriteOptions, XFS};
+    /// # async fn example(mut fs: XFS, master: [u8; 32], new_master: [u8; 32]) -> xfiles::error::Result<()> {
+    /// fs.set_keyring(Keyring::new(1, master));
+    /// fs.set_write_options(WriteOptions {
+    ///     encrypt: true,
+    ///     ..Default::default()
+    /// });
+    /// let mut file = fs.open("private/memory.md", OpenMode::Create).await?;
+    /// file.write(b"only key holders can read this").await?;
+    ///
+    /// // Rotate: new commits use key 2, old ones stay readable with key 1
+    /// let mut keyring = Keyring::new(1, master);
+    /// keyring.rotate(2, new_master);
+    /// fs.set_keyring(keyring);
+    /// fs.reencrypt("private/memory.md").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_keyring(&mut self, keyring: Keyring) {
+        self.keyring = Some(Arc::new(keyring));
+    }
+
+    /// Sign commits with an Ed25519 key from now on
+    ///
+    /// Files created from now on publish the signer's writer keys in their
+    /// root tweet. Set a [`Verification`] mode to check signatures when
+    /// reading, so replies by anyone else are not taken for commits.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use xfiles::{OpenMode, Signer, Verification, XFS};
+    /// # async fn example(mut fs: XFS, secret: [u8; 32], other_agent: [u8; 32]) -> xfiles::error::Result<()> {
+    /// let mut signer = Signer::new(secret);
+    /// signer.add_writer(other_agent);
+    /// fs.set_signer(signer);
+    /// fs.set_verification(Verification::Skip);
+    ///
+    /// let mut file = fs.open("memory.txt", OpenMode::Create).await?;
+    /// file.write(b"signed by me").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_signer(&mut self, signer: Signer) {
+        self.signer = Some(Arc::new(signer));
+    }
+
+    /// Set how commit signatures are checked by files opened from now on
+    ///
+    /// The next open of each file resolves its head from the remote again.
+    pub fn set_verification(&mut self, verification: Verification) {
+        self.verification = verification;
+        self.refreshed = Some(HashSet::new());
+    }
+
+    /// Set whose replies count as commits
+    ///
+    /// Replies by anyone else are ignored when resolving heads and listing
+    /// history, and reported as foreign by [`XFS::recover`]. The account's
+    /// own replies are always trusted. The next open of each file resolves
+    /// its head from the remote again.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use xfiles::{AuthorPolicy, XFS};
+    /// # fn example(fs: &mut XFS) {
+    /// // Only our own commits, but a teammate may also write the shared log
+    /// fs.set_author_policy(
+    ///     AuthorPolicy::only(Vec::<String>::new()).with_file("shared.log", ["1234567890"]),
+    /// );
+    /// # }
+    /// ```
+    pub fn set_author_policy(&mut self, authors: AuthorPolicy) {
+        self.authors = Arc::new(authors);
+        self.refreshed = Some(HashSet::new());
+    }
+
+    /// Rewrite the head of a file under the keyring's current master key
+    ///
+    /// Returns the new head. See [`XFile::reencrypt`].
+    pub async fn reencrypt(&mut self, path: &str) -> Result<TweetId> {
+        let mut file = self.open(path, OpenMode::ReadWrite).await?;
+        file.reencrypt().await?;
+        Ok(file.head().clone())
+    }
 }
 
 #[cfg(test)]
@@ -1,12 +1,16 @@
 //! Mock adapter for testing without real Twitter API
 
 use crate::dag::commit::TweetId;
-use crate::error::Result;
+use crate::error::{ApiError, Result};
 use crate::remote::twitter::{RemoteAdapter, Tweet};
 use async_trait::async_trait;
-use std::collections::HashMap;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, Mutex};
 
+/// Author ID of the account the mock adapter posts as
+pub const MOCK_ACCOUNT: &str = "mock_user";
+
 /// Mock adapter that simulates Twitter API in memory
 #[derive(Clone)]
 pub struct MockAdapter {
@@ -14,6 +18,10 @@ pub struct MockAdapter {
     tweets: Arc<Mutex<HashMap<TweetId, MockTweet>>>,
     /// Counter for generating tweet IDs
     next_id: Arc<Mutex<u64>>,
+    /// Posts still to go through with their response lost
+    lost_responses: Arc<Mutex<usize>>,
+    /// Number of API requests made so far
+    requests: Arc<Mutex<usize>>,
 }
 
 #[derive(Debug, Clone)]
@@ -22,6 +30,8 @@ struct MockTweet {
     content: Vec<u8>,
     parent_id: Option<TweetId>,
     author: String,
+    created_at: DateTime<Utc>,
+    conversation_id: TweetId,
 }
 
 impl MockAdapter {
@@ -30,6 +40,8 @@ impl MockAdapter {
         Self {
             tweets: Arc::new(Mutex::new(HashMap::new())),
             next_id: Arc::new(Mutex::new(1)),
+            lost_responses: Arc::new(Mutex::new(0)),
+            requests: Arc::new(Mutex::new(0)),
         }
     }
 
@@ -44,13 +56,88 @@ impl MockAdapter {
     /// Get a tweet by ID
     pub fn get_tweet(&self, id: &TweetId) -> Option<Tweet> {
         let tweets = self.tweets.lock().unwrap();
-        tweets.get(id).map(|t| Tweet {
-            id: t.id.clone(),
-            author_id: t.author.clone(),
-            text: String::from_utf8_lossy(&t.content).to_string(),
-            created_at: "2024-01-01T00:00:00Z".to_string(),
-            in_reply_to: t.parent_id.clone(),
-        })
+        tweets.get(id).map(Self::to_tweet)
+    }
+
+    /// Delete a tweet, as its author could on Twitter
+    pub fn delete_tweet(&self, id: &TweetId) -> bool {
+        self.tweets.lock().unwrap().remove(id).is_some()
+    }
+
+    /// Move a tweet's creation time `by` into the past
+    pub fn backdate_tweet(&self, id: &TweetId, by: chrono::Duration) -> bool {
+        match self.tweets.lock().unwrap().get_mut(id) {
+            Some(tweet) => {
+                tweet.created_at -= by;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replace the content of a tweet, as its author could within
+    /// Twitter's edit window
+    pub fn edit_tweet(&self, id: &TweetId, content: &[u8]) -> bool {
+        match self.tweets.lock().unwrap().get_mut(id) {
+            Some(tweet) => {
+                tweet.content = content.to_vec();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of API requests made through the adapter so far
+    ///
+    /// Each [`RemoteAdapter`] call counts as one request, as it would be
+    /// when a single API call can serve it.
+    pub fn requests(&self) -> usize {
+        *self.requests.lock().unwrap()
+    }
+
+    /// Count an API request
+    fn request(&self) {
+        *self.requests.lock().unwrap() += 1;
+    }
+
+    /// Let the next post go through but fail it with the duplicate-content
+    /// error, as a post retried after its response timed out would
+    pub fn lose_next_response(&self) {
+        *self.lost_responses.lock().unwrap() += 1;
+    }
+
+    /// Fail a post that went through if its response is to be lost
+    fn respond(&self, id: TweetId) -> Result<TweetId> {
+        let mut lost = self.lost_responses.lock().unwrap();
+        if *lost > 0 {
+            *lost -= 1;
+            return Err(ApiError::DuplicateContent.into());
+        }
+
+        Ok(id)
+    }
+
+    /// Reject content identical to a tweet the account already posted, as
+    /// Twitter does
+    fn check_duplicate(&self, content: &[u8]) -> Result<()> {
+        let tweets = self.tweets.lock().unwrap();
+        if tweets.values().any(|t| t.author == MOCK_ACCOUNT && t.content == content) {
+            return Err(ApiError::DuplicateContent.into());
+        }
+
+        Ok(())
+    }
+
+    /// Convert a stored tweet into its API representation
+    fn to_tweet(tweet: &MockTweet) -> Tweet {
+        Tweet {
+            id: tweet.id.clone(),
+            author_id: tweet.author.clone(),
+            text: String::from_utf8_lossy(&tweet.content).to_string(),
+            created_at: tweet.created_at.to_rfc3339(),
+            in_reply_to: tweet.parent_id.clone(),
+            conversation_id: Some(tweet.conversation_id.clone()),
+        }
     }
 
     /// Get all replies to a tweet
@@ -62,6 +149,30 @@ impl MockAdapter {
             .map(|t| t.id.clone())
             .collect()
     }
+
+    /// Post a reply as another account, as anyone could on Twitter
+    pub fn reply_as(&self, author: &str, parent_id: &TweetId, content: &[u8]) -> TweetId {
+        let id = self.generate_id();
+        let mut tweets = self.tweets.lock().unwrap();
+
+        // Replies belong to the conversation their parent started
+        let conversation_id = tweets
+            .get(parent_id)
+            .map(|t| t.conversation_id.clone())
+            .unwrap_or_else(|| parent_id.clone());
+
+        let tweet = MockTweet {
+            id: id.clone(),
+            content: content.to_vec(),
+            parent_id: Some(parent_id.clone()),
+            author: author.to_string(),
+            created_at: Utc::now(),
+            conversation_id,
+        };
+        tweets.insert(id.clone(), tweet);
+
+        id
+    }
 }
 
 impl Default for MockAdapter {
@@ -73,53 +184,90 @@ impl Default for MockAdapter {
 #[async_trait]
 impl RemoteAdapter for MockAdapter {
     async fn fetch(&self, id: &TweetId) -> Result<Vec<u8>> {
+        self.request();
         let tweets = self.tweets.lock().unwrap();
         tweets
             .get(id)
             .map(|t| t.content.clone())
             .ok_or_else(|| {
-                crate::error::XFilesError::TwitterApi(format!("Tweet not found: {}", id))
+                ApiError::TweetNotFound(format!("Could not find tweet with id: [{}].", id)).into()
             })
     }
 
     async fn store(&self, content: &[u8]) -> Result<TweetId> {
+        self.request();
+        self.check_duplicate(content)?;
         let id = self.generate_id();
         let tweet = MockTweet {
             id: id.clone(),
             content: content.to_vec(),
             parent_id: None,
-            author: "mock_user".to_string(),
+            author: MOCK_ACCOUNT.to_string(),
+            created_at: Utc::now(),
+            conversation_id: id.clone(),
         };
 
-        let mut tweets = self.tweets.lock().unwrap();
-        tweets.insert(id.clone(), tweet);
+        self.tweets.lock().unwrap().insert(id.clone(), tweet);
 
-        Ok(id)
+        self.respond(id)
     }
 
     async fn store_reply(&self, parent_id: &TweetId, content: &[u8]) -> Result<TweetId> {
-        let id = self.generate_id();
-        let tweet = MockTweet {
-            id: id.clone(),
-            content: content.to_vec(),
-            parent_id: Some(parent_id.clone()),
-            author: "mock_user".to_string(),
-        };
+        self.request();
+        self.check_duplicate(content)?;
+        let id = self.reply_as(MOCK_ACCOUNT, parent_id, content);
+        self.respond(id)
+    }
 
-        let mut tweets = self.tweets.lock().unwrap();
-        tweets.insert(id.clone(), tweet);
+    async fn fetch_replies(&self, id: &TweetId) -> Result<Vec<Tweet>> {
+        self.request();
+        let tweets = self.tweets.lock().unwrap();
+        Ok(tweets
+            .values()
+            .filter(|t| t.parent_id.as_ref() == Some(id))
+            .map(Self::to_tweet)
+            .collect())
+    }
 
-        Ok(id)
+    async fn fetch_thread(&self, id: &TweetId) -> Result<Vec<Tweet>> {
+        self.request();
+        let tweets = self.tweets.lock().unwrap();
+        let mut found = Vec::new();
+        let mut queue = VecDeque::from([id.clone()]);
+        while let Some(parent) = queue.pop_front() {
+            for tweet in tweets.values().filter(|t| t.parent_id.as_ref() == Some(&parent)) {
+                queue.push_back(tweet.id.clone());
+                found.push(Self::to_tweet(tweet));
+            }
+        }
+
+        Ok(found)
+    }
+
+    async fn account_id(&self) -> Result<String> {
+        self.request();
+        Ok(MOCK_ACCOUNT.to_string())
+    }
+
+    async fn fetch_timeline(&self) -> Result<Vec<Tweet>> {
+        self.request();
+        let tweets = self.tweets.lock().unwrap();
+        Ok(tweets.values().map(Self::to_tweet).collect())
     }
 
-    async fn fetch_replies(&self, id: &TweetId) -> Result<Vec<TweetId>> {
-        Ok(self.get_replies(id))
+    async fn fetch_recent(&self) -> Result<Vec<Tweet>> {
+        self.request();
+        let tweets = self.tweets.lock().unwrap();
+        let mut own: Vec<&MockTweet> = tweets.values().filter(|t| t.author == MOCK_ACCOUNT).collect();
+        own.sort_by_key(|t| std::cmp::Reverse(t.created_at));
+        Ok(own.into_iter().take(100).map(Self::to_tweet).collect())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::XFilesError;
 
     #[tokio::test]
     async fn test_mock_adapter_store_fetch() {
@@ -130,6 +278,10 @@ mod tests {
         let fetched = adapter.fetch(&id).await.unwrap();
 
         assert_eq!(fetched, content);
+        assert!(matches!(
+            adapter.store(content).await,
+            Err(XFilesError::Api(ApiError::DuplicateContent))
+        ));
     }
 
     #[tokio::test]
@@ -140,10 +292,14 @@ mod tests {
         let reply1_id = adapter.store_reply(&root_id, b"Reply 1").await.unwrap();
         let reply2_id = adapter.store_reply(&root_id, b"Reply 2").await.unwrap();
 
+        let reply3_id = adapter.reply_as("stranger", &root_id, b"Reply 3");
+
         let replies = adapter.fetch_replies(&root_id).await.unwrap();
 
-        assert_eq!(replies.len(), 2);
-        assert!(replies.contains(&reply1_id));
-        assert!(replies.contains(&reply2_id));
+        assert_eq!(replies.len(), 3);
+        let author = |id: &TweetId| replies.iter().find(|t| &t.id == id).map(|t| t.author_id.clone());
+        assert_eq!(author(&reply1_id).as_deref(), Some(MOCK_ACCOUNT));
+        assert_eq!(author(&reply2_id).as_deref(), Some(MOCK_ACCOUNT));
+        assert_eq!(author(&reply3_id).as_deref(), Some("stranger"));
     }
 }
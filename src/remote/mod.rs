@@ -8,5 +8,5 @@ pub mod mock;
 pub mod rate_limit;
 pub mod retry;
 
-pub use twitter::{TwitterAdapter, RemoteAdapter};
+pub use twitter::{ReplySearch, TwitterAdapter, TwitterAdapterBuilder, RemoteAdapter};
 pub use mock::MockAdapter;
@@ -1,16 +1,27 @@
 //! Rate limiting for Twitter API
 
+use std::collections::HashMap;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
 /// Rate limiter for API calls
+///
+/// Counts requests against local quotas, and waits out any pause the
+/// server asked for through [`RateLimiter::update`]. The server keeps a
+/// quota per endpoint, so requests and pauses are kept per endpoint too:
+/// endpoints given quotas of their own with [`RateLimiter::with_quota`] are
+/// held to those, every other endpoint to the limiter's own quota.
 pub struct RateLimiter {
     /// Maximum requests per window
     max_requests: usize,
     /// Time window duration
     window: Duration,
-    /// Request timestamps
-    requests: Mutex<Vec<Instant>>,
+    /// Quotas of endpoints that have their own, as requests per window
+    quotas: HashMap<String, Vec<(usize, Duration)>>,
+    /// Request timestamps of each endpoint
+    requests: Mutex<HashMap<String, Vec<Instant>>>,
+    /// When each endpoint's server quota resets, while it is used up
+    paused_until: Mutex<HashMap<String, Instant>>,
 }
 
 impl RateLimiter {
@@ -19,44 +30,121 @@ impl RateLimiter {
         Self {
             max_requests,
             window,
-            requests: Mutex::new(Vec::new()),
+            quotas: HashMap::new(),
+            requests: Mutex::new(HashMap::new()),
+            paused_until: Mutex::new(HashMap::new()),
         }
     }
 
-    /// Wait if necessary to respect rate limits, then record the request
+    /// Hold `endpoint` to `max_requests` per `window` instead of the
+    /// limiter's own quota
+    ///
+    /// Endpoints can be given several quotas, such as a per-second and a
+    /// per-window one; requests wait until all of them allow it.
+    pub fn with_quota(mut self, endpoint: &str, max_requests: usize, window: Duration) -> Self {
+        self.quotas.entry(endpoint.to_string()).or_default().push((max_requests, window));
+        self
+    }
+
+    /// Record the quota the server reported for `endpoint`
+    ///
+    /// With no requests `remaining`, further requests to `endpoint` wait
+    /// until `reset`.
+    pub async fn update(&self, endpoint: &str, remaining: usize, reset: Instant) {
+        let mut paused_until = self.paused_until.lock().await;
+        if remaining == 0 {
+            paused_until.insert(endpoint.to_string(), reset);
+        } else {
+            paused_until.remove(endpoint);
+        }
+    }
+
+    /// Wait out any pause the server asked for on `endpoint` and respect
+    /// its quotas, then record the request
+    pub async fn acquire_for(&self, endpoint: &str) {
+        let paused_until = self.paused_until.lock().await.get(endpoint).copied();
+        if let Some(until) = paused_until {
+            tokio::time::sleep_until(until.into()).await;
+        }
+
+        self.take(endpoint).await;
+    }
+
+    /// Wait if necessary to respect the limiter's own quota, then record
+    /// the request
     pub async fn acquire(&self) {
-        let mut requests = self.requests.lock().await;
+        self.take("").await;
+    }
 
-        // Remove expired timestamps
-        let cutoff = Instant::now() - self.window;
-        requests.retain(|&ts| ts > cutoff);
-
-        // If at limit, wait until oldest request expires
-        if requests.len() >= self.max_requests {
-            if let Some(&oldest) = requests.first() {
-                let wait_time = self.window - (Instant::now() - oldest);
-                if !wait_time.is_zero() {
-                    drop(requests); // Release lock while waiting
-                    tokio::time::sleep(wait_time).await;
-                    requests = self.requests.lock().await;
-                    requests.retain(|&ts| ts > Instant::now() - self.window);
-                }
-            }
+    /// Check if we can make a request to `endpoint` without waiting
+    pub async fn can_proceed_for(&self, endpoint: &str) -> bool {
+        let paused = self.paused_until.lock().await.get(endpoint).copied();
+        if paused.is_some_and(|until| until > Instant::now()) {
+            return false;
         }
 
-        // Record this request
-        requests.push(Instant::now());
+        self.time_to_wait(endpoint, Instant::now()).await.is_zero()
     }
 
-    /// Check if we can make a request without waiting
+    /// Check if the limiter's own quota allows a request without waiting
     pub async fn can_proceed(&self) -> bool {
+        self.time_to_wait("", Instant::now()).await.is_zero()
+    }
+
+    /// Quotas `endpoint` is held to
+    fn quotas_of(&self, endpoint: &str) -> Vec<(usize, Duration)> {
+        match self.quotas.get(endpoint) {
+            Some(quotas) => quotas.clone(),
+            None => vec![(self.max_requests, self.window)],
+        }
+    }
+
+    /// Wait until the quotas of `endpoint` allow a request, then record it
+    async fn take(&self, endpoint: &str) {
+        let quotas = self.quotas_of(endpoint);
+        loop {
+            let mut requests = self.requests.lock().await;
+            let log = requests.entry(endpoint.to_string()).or_default();
+            let now = Instant::now();
+            let wait = wait_time(log, &quotas, now);
+            if wait.is_zero() {
+                log.push(now);
+                return;
+            }
+
+            drop(requests); // Release lock while waiting
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// How long until the quotas of `endpoint` allow a request at `now`
+    async fn time_to_wait(&self, endpoint: &str, now: Instant) -> Duration {
         let mut requests = self.requests.lock().await;
-        let cutoff = Instant::now() - self.window;
-        requests.retain(|&ts| ts > cutoff);
-        requests.len() < self.max_requests
+        let log = requests.entry(endpoint.to_string()).or_default();
+        wait_time(log, &self.quotas_of(endpoint), now)
     }
 }
 
+/// How long until `quotas` allow a request at `now`, given the times of
+/// earlier requests
+fn wait_time(log: &mut Vec<Instant>, quotas: &[(usize, Duration)], now: Instant) -> Duration {
+    // Remove timestamps no quota counts any more
+    let longest = quotas.iter().map(|&(_, window)| window).max().unwrap_or_default();
+    log.retain(|&ts| now - ts < longest);
+
+    // At a limit, wait until the oldest request it counts expires
+    quotas
+        .iter()
+        .filter_map(|&(max_requests, window)| {
+            let counted: Vec<Instant> =
+                log.iter().copied().filter(|&ts| now - ts < window).collect();
+            let oldest = counted.len().checked_sub(max_requests.max(1))?;
+            Some(window.saturating_sub(now - counted[oldest]))
+        })
+        .max()
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -76,4 +164,64 @@ mod tests {
 
         assert!(elapsed >= Duration::from_millis(90)); // Allow some slack
     }
+
+    #[tokio::test]
+    async fn test_server_pause() {
+        let limiter = RateLimiter::new(10, Duration::from_secs(60));
+
+        // The server says the quota is used up for another 100ms
+        limiter.update("GET /a", 0, Instant::now() + Duration::from_millis(100)).await;
+        assert!(!limiter.can_proceed_for("GET /a").await);
+
+        let start = Instant::now();
+        limiter.acquire_for("GET /a").await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+
+        // Requests left lift the pause
+        limiter.update("GET /a", 0, Instant::now() + Duration::from_secs(60)).await;
+        limiter.update("GET /a", 5, Instant::now() + Duration::from_secs(60)).await;
+        assert!(limiter.can_proceed_for("GET /a").await);
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_quotas() {
+        let limiter = RateLimiter::new(10, Duration::from_secs(60))
+            .with_quota("GET /a", 1, Duration::from_millis(100))
+            .with_quota("GET /a", 3, Duration::from_secs(60));
+
+        // The endpoint's own quota applies, not the limiter's
+        limiter.acquire_for("GET /a").await;
+        assert!(!limiter.can_proceed_for("GET /a").await);
+        assert!(limiter.can_proceed_for("GET /b").await);
+
+        let start = Instant::now();
+        limiter.acquire_for("GET /a").await;
+        assert!(start.elapsed() >= Duration::from_millis(90));
+
+        // Every quota of the endpoint must allow the request
+        limiter.acquire_for("GET /a").await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!limiter.can_proceed_for("GET /a").await);
+    }
+
+    #[tokio::test]
+    async fn test_pauses_per_endpoint() {
+        let limiter = RateLimiter::new(10, Duration::from_secs(60));
+        let reset = Instant::now() + Duration::from_secs(60);
+
+        // Quota left on one endpoint does not lift another's pause
+        limiter.update("GET /a", 0, reset).await;
+        limiter.update("GET /b", 5, reset).await;
+        limiter.update("GET /a", 0, reset).await;
+        limiter.update("GET /b", 4, reset).await;
+        assert!(!limiter.can_proceed_for("GET /a").await);
+        assert!(limiter.can_proceed_for("GET /b").await);
+
+        let start = Instant::now();
+        limiter.acquire_for("GET /b").await;
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        limiter.update("GET /a", 3, reset).await;
+        assert!(limiter.can_proceed_for("GET /a").await);
+    }
 }
@@ -4,6 +4,7 @@ use std::time::Duration;
 use tokio::time::sleep;
 
 /// Retry configuration
+#[derive(Debug, Clone)]
 pub struct RetryConfig {
     /// Maximum number of retry attempts
     pub max_attempts: usize,
@@ -29,6 +30,21 @@ impl Default for RetryConfig {
 /// Retry a function with exponential backoff
 pub async fn retry_with_backoff<F, Fut, T, E>(
     config: &RetryConfig,
+    f: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, E>>,
+{
+    retry_when(config, |_| true, f).await
+}
+
+/// Retry a function with exponential backoff while its errors are transient
+///
+/// Errors `transient` rejects are returned at once.
+pub async fn retry_when<F, Fut, T, E>(
+    config: &RetryConfig,
+    transient: impl Fn(&E) -> bool,
     mut f: F,
 ) -> Result<T, E>
 where
@@ -43,7 +59,7 @@ where
             Ok(result) => return Ok(result),
             Err(e) => {
                 attempt += 1;
-                if attempt >= config.max_attempts {
+                if attempt >= config.max_attempts || !transient(&e) {
                     return Err(e);
                 }
 
@@ -104,4 +120,27 @@ mod tests {
         assert_eq!(result, Err("persistent error"));
         assert_eq!(attempts.load(Ordering::SeqCst), 2);
     }
+
+    #[tokio::test]
+    async fn test_retry_stops_on_permanent_error() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let config = RetryConfig::default();
+        let attempts = AtomicUsize::new(0);
+
+        let result = retry_when(&config, |e: &&str| *e == "timeout", || {
+            let count = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                if count < 2 {
+                    Err::<i32, _>("timeout")
+                } else {
+                    Err("bad request")
+                }
+            }
+        })
+        .await;
+
+        assert_eq!(result, Err("bad request"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
 }
@@ -1,18 +1,213 @@
 //! Twitter API client implementation with OAuth 1.0a
 
 use crate::dag::commit::TweetId;
-use crate::error::{Result, XFilesError};
+use crate::error::{ApiError, Result, XFilesError};
+use crate::remote::rate_limit::RateLimiter;
+use crate::remote::retry::{RetryConfig, retry_when};
 use async_trait::async_trait;
-use reqwest::Client;
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use oauth::{Token, HmacSha1};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 
 const TWITTER_API_BASE: &str = "https://api.twitter.com/2";
 
+/// Endpoint of single tweet lookups
+pub const TWEET_LOOKUP: &str = "GET /2/tweets/:id";
+
+/// Endpoint of recent search, covering the last 7 days
+pub const SEARCH_RECENT: &str = "GET /2/tweets/search/recent";
+
+/// Endpoint of full-archive search
+pub const SEARCH_ALL: &str = "GET /2/tweets/search/all";
+
+/// Endpoint of the authenticated user lookup
+pub const USERS_ME: &str = "GET /2/users/me";
+
+/// Endpoint of user timelines
+pub const USER_TWEETS: &str = "GET /2/users/:id/tweets";
+
+/// Endpoint tweets are posted to
+pub const POST_TWEET: &str = "POST /2/tweets";
+
+/// Requests per window Twitter allows a user on reads without a quota of
+/// their own below
+pub const READ_LIMIT: usize = 900;
+
+/// Tweet lookups Twitter allows a user per window
+pub const TWEET_LOOKUP_LIMIT: usize = 900;
+
+/// Recent searches Twitter allows a user per window
+pub const SEARCH_RECENT_LIMIT: usize = 180;
+
+/// Full-archive searches Twitter allows per window, at most one a second
+pub const SEARCH_ALL_LIMIT: usize = 300;
+
+/// Authenticated user lookups Twitter allows a user per window
+pub const USERS_ME_LIMIT: usize = 75;
+
+/// Timeline pages Twitter allows a user per window
+pub const USER_TWEETS_LIMIT: usize = 900;
+
+/// Tweets Twitter allows a user to post per window
+pub const WRITE_LIMIT: usize = 200;
+
+/// Window Twitter's per-user quotas are counted over
+pub const LIMIT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// Time allowed for a single request before it is retried
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Fields requested for every tweet
+const TWEET_FIELDS: &str = "created_at,author_id,conversation_id,in_reply_to_user_id,referenced_tweets";
+
+/// Earliest time a full-archive search can start from, the day before the
+/// first tweet
+const ARCHIVE_START: &str = "2006-03-21T00:00:00Z";
+
+/// How [`TwitterAdapter::get_replies`] finds the replies to a tweet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplySearch {
+    /// Search the tweet's conversation with recent search, which only
+    /// covers the last 7 days
+    #[default]
+    Recent,
+    /// Search the tweet's conversation with full-archive search, which
+    /// needs Pro or Enterprise API access
+    FullArchive,
+    /// Walk the authenticated user's timeline, which covers the most recent
+    /// 3200 tweets but only finds the user's own replies
+    Timeline,
+}
+
 /// Twitter API adapter with OAuth 1.0a authentication
+///
+/// Reads and writes wait on separate [`RateLimiter`]s, and requests that
+/// fail transiently (timeouts, 429 and 5xx responses) are retried with
+/// backoff. Other 4xx responses are returned at once.
 pub struct TwitterAdapter {
     client: Client,
     token: Token<Box<str>>,
+    /// ID of the authenticated user, fetched on first use
+    user_id: tokio::sync::OnceCell<String>,
+    /// Quotas of lookups, searches and timeline pages, per endpoint
+    reads: RateLimiter,
+    /// Quota of posted tweets
+    writes: RateLimiter,
+    /// How transient failures are retried
+    retry: RetryConfig,
+    /// How replies are found
+    reply_search: ReplySearch,
+}
+
+/// Builder for a [`TwitterAdapter`] with custom limits
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::time::Duration;
+/// # use xfiles::remote::TwitterAdapter;
+/// # use xfiles::remote::retry::RetryConfig;
+/// # use xfiles::remote::twitter::SEARCH_RECENT;
+/// let adapter = TwitterAdapter::builder("key", "secret", "token", "token secret")
+///     .write_limit(50, Duration::from_secs(15 * 60))
+///     .endpoint_limit(SEARCH_RECENT, 450, Duration::from_secs(15 * 60))
+///     .retry(RetryConfig { max_attempts: 5, ..Default::default() })
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct TwitterAdapterBuilder {
+    consumer_key: String,
+    consumer_secret: String,
+    access_token: String,
+    access_token_secret: String,
+    read_limit: (usize, Duration),
+    write_limit: (usize, Duration),
+    endpoint_limits: HashMap<String, Vec<(usize, Duration)>>,
+    retry: RetryConfig,
+    timeout: Duration,
+    reply_search: ReplySearch,
+}
+
+impl TwitterAdapterBuilder {
+    /// Allow `max_requests` reads per `window` on endpoints without a quota
+    /// of their own (default [`READ_LIMIT`] per [`LIMIT_WINDOW`])
+    pub fn read_limit(mut self, max_requests: usize, window: Duration) -> Self {
+        self.read_limit = (max_requests, window);
+        self
+    }
+
+    /// Allow `max_requests` posts per `window` (default [`WRITE_LIMIT`] per
+    /// [`LIMIT_WINDOW`])
+    pub fn write_limit(mut self, max_requests: usize, window: Duration) -> Self {
+        self.write_limit = (max_requests, window);
+        self
+    }
+
+    /// Allow `max_requests` per `window` on `endpoint`, such as
+    /// [`SEARCH_RECENT`], replacing its default quotas
+    ///
+    /// By default lookups, searches and timeline pages are held to the
+    /// per-user quotas Twitter documents for each: [`TWEET_LOOKUP_LIMIT`],
+    /// [`SEARCH_RECENT_LIMIT`], [`SEARCH_ALL_LIMIT`], [`USERS_ME_LIMIT`]
+    /// and [`USER_TWEETS_LIMIT`] per [`LIMIT_WINDOW`].
+    pub fn endpoint_limit(mut self, endpoint: &str, max_requests: usize, window: Duration) -> Self {
+        self.endpoint_limits.insert(endpoint.to_string(), vec![(max_requests, window)]);
+        self
+    }
+
+    /// Set how transient failures are retried
+    pub fn retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Set the time allowed for a single request (default
+    /// [`REQUEST_TIMEOUT`])
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how replies are found (default [`ReplySearch::Recent`])
+    pub fn reply_search(mut self, reply_search: ReplySearch) -> Self {
+        self.reply_search = reply_search;
+        self
+    }
+
+    /// Build the adapter
+    pub fn build(self) -> TwitterAdapter {
+        let client = Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .expect("Failed to build HTTP client");
+
+        let token = Token::from_parts(
+            self.consumer_key.into(),
+            self.consumer_secret.into(),
+            self.access_token.into(),
+            self.access_token_secret.into(),
+        );
+
+        TwitterAdapter {
+            client,
+            token,
+            user_id: tokio::sync::OnceCell::new(),
+            reads: with_quotas(
+                RateLimiter::new(self.read_limit.0, self.read_limit.1),
+                &self.endpoint_limits,
+            ),
+            writes: with_quotas(
+                RateLimiter::new(self.write_limit.0, self.write_limit.1),
+                &self.endpoint_limits,
+            ),
+            retry: self.retry,
+            reply_search: self.reply_search,
+        }
+    }
 }
 
 impl TwitterAdapter {
@@ -26,6 +221,9 @@ impl TwitterAdapter {
     ///
     /// Get these from: https://developer.twitter.com/en/portal/dashboard
     ///
+    /// Uses the default limits; see [`TwitterAdapter::builder`] to change
+    /// them.
+    ///
     /// # Arguments
     /// * `consumer_key` - Your Twitter API Key
     /// * `consumer_secret` - Your Twitter API Secret
@@ -37,18 +235,37 @@ impl TwitterAdapter {
         access_token: String,
         access_token_secret: String,
     ) -> Self {
-        let client = Client::builder()
-            .build()
-            .expect("Failed to build HTTP client");
-
-        let token = Token::from_parts(
-            consumer_key.into(),
-            consumer_secret.into(),
-            access_token.into(),
-            access_token_secret.into(),
-        );
+        Self::builder(consumer_key, consumer_secret, access_token, access_token_secret).build()
+    }
 
-        Self { client, token }
+    /// Start building an adapter with OAuth 1.0a credentials
+    pub fn builder(
+        consumer_key: impl Into<String>,
+        consumer_secret: impl Into<String>,
+        access_token: impl Into<String>,
+        access_token_secret: impl Into<String>,
+    ) -> TwitterAdapterBuilder {
+        TwitterAdapterBuilder {
+            consumer_key: consumer_key.into(),
+            consumer_secret: consumer_secret.into(),
+            access_token: access_token.into(),
+            access_token_secret: access_token_secret.into(),
+            read_limit: (READ_LIMIT, LIMIT_WINDOW),
+            write_limit: (WRITE_LIMIT, LIMIT_WINDOW),
+            endpoint_limits: HashMap::from([
+                (TWEET_LOOKUP.to_string(), vec![(TWEET_LOOKUP_LIMIT, LIMIT_WINDOW)]),
+                (SEARCH_RECENT.to_string(), vec![(SEARCH_RECENT_LIMIT, LIMIT_WINDOW)]),
+                (
+                    SEARCH_ALL.to_string(),
+                    vec![(SEARCH_ALL_LIMIT, LIMIT_WINDOW), (1, Duration::from_secs(1))],
+                ),
+                (USERS_ME.to_string(), vec![(USERS_ME_LIMIT, LIMIT_WINDOW)]),
+                (USER_TWEETS.to_string(), vec![(USER_TWEETS_LIMIT, LIMIT_WINDOW)]),
+            ]),
+            retry: RetryConfig::default(),
+            timeout: REQUEST_TIMEOUT,
+            reply_search: ReplySearch::default(),
+        }
     }
 
     /// Generate OAuth 1.0a Authorization header
@@ -60,139 +277,256 @@ impl TwitterAdapter {
         }
     }
 
+    /// Send a request within `limiter`'s quota, retrying transient failures
+    ///
+    /// Error responses are returned as [`ApiError`]s, and those that are
+    /// retryable are tried again along with timeouts. `request` builds the
+    /// request again for every attempt, so each one is signed with a fresh
+    /// OAuth nonce. A post retried after a timeout may already have gone
+    /// through; Twitter rejects the repeat as duplicate content.
+    ///
+    /// The quota each response reports in its `x-rate-limit-*` headers is
+    /// fed into `limiter` for `endpoint`, so once it runs out the next
+    /// request to the same endpoint waits for the reset. A 429 is only
+    /// retried if the quota resets within the longest backoff; otherwise
+    /// [`XFilesError::RateLimitExceeded`] is returned at once with the
+    /// reset time.
+    async fn send(
+        &self,
+        limiter: &RateLimiter,
+        endpoint: &str,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Result<Response> {
+        let request = &request;
+        retry_when(&self.retry, |failure: &Failure| failure.transient, || async move {
+            limiter.acquire_for(endpoint).await;
+
+            let response = request().send().await.map_err(|e| {
+                let error = XFilesError::Http(e);
+                Failure { transient: error.is_retryable(), error }
+            })?;
+
+            let status = response.status();
+            let quota = rate_limit_headers(response.headers());
+            if let Some((remaining, reset)) = quota {
+                limiter.update(endpoint, remaining, instant_at(reset)).await;
+            }
+            if status.is_success() {
+                return Ok(response);
+            }
+
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let reset = quota.map(|(_, reset)| reset);
+                let soon = reset.is_none_or(|reset| until(reset) <= self.retry.max_backoff);
+                return Err(Failure {
+                    transient: soon,
+                    error: XFilesError::RateLimitExceeded {
+                        endpoint: endpoint.to_string(),
+                        reset,
+                    },
+                });
+            }
+
+            let body = response.text().await.unwrap_or_default();
+            let error = Problem::parse(&body).classify(status);
+            Err(Failure { transient: error.is_retryable(), error: error.into() })
+        })
+        .await
+        .map_err(|failure| failure.error)
+    }
+
     /// Get a tweet by ID
     pub async fn get_tweet(&self, id: &TweetId) -> Result<Tweet> {
         let base_url = format!("{}/tweets/{}", TWITTER_API_BASE, id);
-        let url_with_params = format!("{}?tweet.fields=created_at,author_id,in_reply_to_user_id,referenced_tweets", base_url);
-
-        let auth_header = self.generate_oauth_header("GET", &url_with_params);
+        let url_with_params = format!("{}?tweet.fields={}", base_url, TWEET_FIELDS);
 
         let response = self
-            .client
-            .get(&base_url)
-            .query(&[("tweet.fields", "created_at,author_id,in_reply_to_user_id,referenced_tweets")])
-            .header("Authorization", auth_header)
-            .send()
-            .await
-            .map_err(|e| XFilesError::TwitterApi(format!("Failed to fetch tweet: {}", e)))?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(XFilesError::TwitterApi(format!(
-                "Twitter API error {}: {}",
-                status, error_text
-            )));
-        }
+            .send(&self.reads, TWEET_LOOKUP, || {
+                self.client
+                    .get(&base_url)
+                    .query(&[("tweet.fields", TWEET_FIELDS)])
+                    .header("Authorization", self.generate_oauth_header("GET", &url_with_params))
+            })
+            .await?;
 
         let api_response: TwitterApiResponse<TweetData> = response
             .json()
             .await
             .map_err(|e| XFilesError::TwitterApi(format!("Failed to parse response: {}", e)))?;
 
-        let tweet_data = api_response
-            .data
-            .ok_or_else(|| XFilesError::TwitterApi("No tweet data in response".to_string()))?;
-
-        Ok(Tweet::from(tweet_data))
+        // Deleted and withheld tweets come back as errors of a 200 response
+        match api_response.data {
+            Some(tweet_data) => Ok(Tweet::from(tweet_data)),
+            None if !api_response.errors.is_empty() => {
+                let problem = Problem { errors: api_response.errors, ..Default::default() };
+                Err(problem.classify(StatusCode::OK).into())
+            }
+            None => Err(XFilesError::TwitterApi("No tweet data in response".to_string())),
+        }
     }
 
-    /// Get replies to a tweet
+    /// Get the direct replies to a tweet
+    ///
+    /// Searches follow pagination through the whole conversation the tweet
+    /// belongs to, starting from the tweet itself, and keep only the
+    /// replies to it; see [`ReplySearch`] for what each mode covers. Each
+    /// call lists the whole conversation or timeline again, so walking a
+    /// thread should use [`get_thread`](Self::get_thread) instead.
     pub async fn get_replies(&self, id: &TweetId) -> Result<Vec<Tweet>> {
-        let base_url = format!("{}/tweets/search/recent", TWITTER_API_BASE);
-        let query = format!("conversation_id:{}", id);
-        // Note: OAuth library will handle URL encoding
-        let url_with_params = format!(
-            "{}?query={}&tweet.fields=created_at,author_id,in_reply_to_user_id,referenced_tweets&max_results=100",
-            base_url, query
-        );
+        Ok(direct_replies(self.reply_candidates(id).await?, id))
+    }
 
-        let auth_header = self.generate_oauth_header("GET", &url_with_params);
+    /// Get every reply below a tweet, at any depth
+    ///
+    /// The conversation or timeline is listed once for the whole thread.
+    pub async fn get_thread(&self, id: &TweetId) -> Result<Vec<Tweet>> {
+        Ok(thread(self.reply_candidates(id).await?, id))
+    }
 
-        let response = self
-            .client
-            .get(&base_url)
-            .query(&[
-                ("query", query.as_str()),
-                ("tweet.fields", "created_at,author_id,in_reply_to_user_id,referenced_tweets"),
-                ("max_results", "100"),
-            ])
-            .header("Authorization", auth_header)
-            .send()
-            .await
-            .map_err(|e| XFilesError::TwitterApi(format!("Failed to fetch replies: {}", e)))?;
+    /// List the tweets replies to `id` are among, as [`ReplySearch`] says
+    async fn reply_candidates(&self, id: &TweetId) -> Result<Vec<Tweet>> {
+        if self.reply_search == ReplySearch::Timeline {
+            return self.get_timeline().await;
+        }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(XFilesError::TwitterApi(format!(
-                "Twitter API error {}: {}",
-                status, error_text
-            )));
+        // Replies carry the ID of the conversation's first tweet, not of
+        // the tweet they reply to
+        let tweet = self.get_tweet(id).await?;
+        let query = format!("conversation_id:{}", tweet.conversation_id.as_ref().unwrap_or(id));
+        let mut params = vec![("query", query.as_str())];
+
+        if self.reply_search == ReplySearch::FullArchive {
+            // Full-archive search covers only the last 30 days unless told
+            // where to start; nothing replies to a tweet before it exists
+            let start = DateTime::parse_from_rfc3339(&tweet.created_at)
+                .map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+                .unwrap_or_else(|_| ARCHIVE_START.to_string());
+            params.extend([("start_time", start.as_str()), ("max_results", "500")]);
+            let url = format!("{}/tweets/search/all", TWITTER_API_BASE);
+            self.get_pages(SEARCH_ALL, &url, &params, "next_token", None).await
+        } else {
+            params.push(("max_results", "100"));
+            let url = format!("{}/tweets/search/recent", TWITTER_API_BASE);
+            self.get_pages(SEARCH_RECENT, &url, &params, "next_token", None).await
         }
+    }
+
+    /// Get the ID of the authenticated user
+    ///
+    /// The ID never changes, so it is only fetched once.
+    pub async fn get_user_id(&self) -> Result<String> {
+        self.user_id
+            .get_or_try_init(|| self.fetch_user_id())
+            .await
+            .cloned()
+    }
 
-        let api_response: TwitterApiListResponse<TweetData> = response
+    /// Fetch the ID of the authenticated user
+    async fn fetch_user_id(&self) -> Result<String> {
+        let url = format!("{}/users/me", TWITTER_API_BASE);
+
+        let response = self
+            .send(&self.reads, USERS_ME, || {
+                self.client
+                    .get(&url)
+                    .header("Authorization", self.generate_oauth_header("GET", &url))
+            })
+            .await?;
+
+        let api_response: TwitterApiResponse<UserData> = response
             .json()
             .await
             .map_err(|e| XFilesError::TwitterApi(format!("Failed to parse response: {}", e)))?;
 
         Ok(api_response
             .data
-            .unwrap_or_default()
-            .into_iter()
-            .map(Tweet::from)
-            .collect())
+            .ok_or_else(|| XFilesError::TwitterApi("No user data in response".to_string()))?
+            .id)
     }
 
-    /// Post a new tweet
-    pub async fn post_tweet(&self, content: &str) -> Result<TweetId> {
-        let url = format!("{}/tweets", TWITTER_API_BASE);
+    /// Get the authenticated user's tweets, newest first
+    ///
+    /// Follows pagination to the end of the timeline. Twitter only serves
+    /// the most recent 3200 tweets of an account this way.
+    pub async fn get_timeline(&self) -> Result<Vec<Tweet>> {
+        let user_id = self.get_user_id().await?;
+        let url = format!("{}/users/{}/tweets", TWITTER_API_BASE, user_id);
 
-        let auth_header = self.generate_oauth_header("POST", &url);
+        self.get_pages(USER_TWEETS, &url, &[("max_results", "100")], "pagination_token", None)
+            .await
+    }
 
-        let payload = CreateTweetRequest {
-            text: content.to_string(),
-            reply: None,
-        };
+    /// Get the authenticated user's latest 100 tweets, newest first
+    ///
+    /// Unlike search, the timeline shows a tweet as soon as it is posted.
+    pub async fn get_recent_tweets(&self) -> Result<Vec<Tweet>> {
+        let user_id = self.get_user_id().await?;
+        let url = format!("{}/users/{}/tweets", TWITTER_API_BASE, user_id);
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", auth_header)
-            .header("Content-Type", "application/json")
-            .json(&payload)
-            .send()
+        self.get_pages(USER_TWEETS, &url, &[("max_results", "100")], "pagination_token", Some(1))
             .await
-            .map_err(|e| XFilesError::TwitterApi(format!("Failed to post tweet: {}", e)))?;
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(XFilesError::TwitterApi(format!(
-                "Twitter API error {}: {}",
-                status, error_text
-            )));
+    /// Get the pages of a tweet list, passing `meta.next_token` back as
+    /// `token_param` until the last page or `max_pages`
+    async fn get_pages(
+        &self,
+        endpoint: &str,
+        base_url: &str,
+        params: &[(&str, &str)],
+        token_param: &str,
+        max_pages: Option<usize>,
+    ) -> Result<Vec<Tweet>> {
+        let mut tweets = Vec::new();
+        let mut next_token: Option<String> = None;
+        for _ in 0..max_pages.unwrap_or(usize::MAX) {
+            let mut params = params.to_vec();
+            params.push(("tweet.fields", TWEET_FIELDS));
+            if let Some(token) = &next_token {
+                params.push((token_param, token.as_str()));
+            }
+
+            // Note: OAuth library will handle URL encoding
+            let query: Vec<String> = params.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            let url_with_params = format!("{}?{}", base_url, query.join("&"));
+
+            let response = self
+                .send(&self.reads, endpoint, || {
+                    self.client
+                        .get(base_url)
+                        .query(&params)
+                        .header("Authorization", self.generate_oauth_header("GET", &url_with_params))
+                })
+                .await?;
+
+            let api_response: TwitterApiListResponse<TweetData> = response
+                .json()
+                .await
+                .map_err(|e| XFilesError::TwitterApi(format!("Failed to parse response: {}", e)))?;
+
+            tweets.extend(api_response.data.unwrap_or_default().into_iter().map(Tweet::from));
+
+            next_token = api_response.meta.and_then(|m| m.next_token);
+            if next_token.is_none() {
+                break;
+            }
         }
 
-        let api_response: TwitterApiResponse<CreatedTweetData> = response
-            .json()
-            .await
-            .map_err(|e| XFilesError::TwitterApi(format!("Failed to parse response: {}", e)))?;
+        Ok(tweets)
+    }
 
-        let tweet_id = api_response
-            .data
-            .ok_or_else(|| XFilesError::TwitterApi("No tweet data in response".to_string()))?
-            .id;
+    /// Post a new tweet
+    pub async fn post_tweet(&self, content: &str) -> Result<TweetId> {
+        let payload = CreateTweetRequest {
+            text: content.to_string(),
+            reply: None,
+        };
 
-        Ok(tweet_id)
+        self.create_tweet(&payload).await
     }
 
     /// Post a reply to a tweet
     pub async fn post_reply(&self, parent_id: &TweetId, content: &str) -> Result<TweetId> {
-        let url = format!("{}/tweets", TWITTER_API_BASE);
-
-        let auth_header = self.generate_oauth_header("POST", &url);
-
         let payload = CreateTweetRequest {
             text: content.to_string(),
             reply: Some(ReplySettings {
@@ -200,24 +534,22 @@ impl TwitterAdapter {
             }),
         };
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Authorization", auth_header)
-            .header("Content-Type", "application/json")
-            .json(&payload)
-            .send()
-            .await
-            .map_err(|e| XFilesError::TwitterApi(format!("Failed to post reply: {}", e)))?;
+        self.create_tweet(&payload).await
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(XFilesError::TwitterApi(format!(
-                "Twitter API error {}: {}",
-                status, error_text
-            )));
-        }
+    /// Post a tweet and return its ID
+    async fn create_tweet(&self, payload: &CreateTweetRequest) -> Result<TweetId> {
+        let url = format!("{}/tweets", TWITTER_API_BASE);
+
+        let response = self
+            .send(&self.writes, POST_TWEET, || {
+                self.client
+                    .post(&url)
+                    .header("Authorization", self.generate_oauth_header("POST", &url))
+                    .header("Content-Type", "application/json")
+                    .json(payload)
+            })
+            .await?;
 
         let api_response: TwitterApiResponse<CreatedTweetData> = response
             .json()
@@ -233,6 +565,74 @@ impl TwitterAdapter {
     }
 }
 
+/// A limiter holding endpoints to their quotas
+fn with_quotas(
+    limiter: RateLimiter,
+    quotas: &HashMap<String, Vec<(usize, Duration)>>,
+) -> RateLimiter {
+    quotas.iter().fold(limiter, |limiter, (endpoint, quotas)| {
+        quotas.iter().fold(limiter, |limiter, &(max_requests, window)| {
+            limiter.with_quota(endpoint, max_requests, window)
+        })
+    })
+}
+
+/// A failed attempt at a request
+struct Failure {
+    /// Whether trying again may succeed
+    transient: bool,
+    error: XFilesError,
+}
+
+/// The tweets that reply to `id` itself, not to other tweets of its
+/// conversation
+fn direct_replies(tweets: Vec<Tweet>, id: &TweetId) -> Vec<Tweet> {
+    tweets
+        .into_iter()
+        .filter(|t| t.in_reply_to.as_ref() == Some(id))
+        .collect()
+}
+
+/// The tweets below `id`, at any depth, in breadth-first order
+fn thread(tweets: Vec<Tweet>, id: &TweetId) -> Vec<Tweet> {
+    let mut replies: HashMap<TweetId, Vec<Tweet>> = HashMap::new();
+    for tweet in tweets {
+        if let Some(parent) = tweet.in_reply_to.clone() {
+            replies.entry(parent).or_default().push(tweet);
+        }
+    }
+
+    let mut found = Vec::new();
+    let mut queue = VecDeque::from([id.clone()]);
+    while let Some(parent) = queue.pop_front() {
+        for reply in replies.remove(&parent).unwrap_or_default() {
+            queue.push_back(reply.id.clone());
+            found.push(reply);
+        }
+    }
+
+    found
+}
+
+/// Remaining requests and reset time reported by `x-rate-limit-*` headers
+fn rate_limit_headers(headers: &HeaderMap) -> Option<(usize, DateTime<Utc>)> {
+    let value = |name: &str| -> Option<i64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+
+    let remaining = value("x-rate-limit-remaining")?;
+    let reset = DateTime::from_timestamp(value("x-rate-limit-reset")?, 0)?;
+    Some((remaining.max(0) as usize, reset))
+}
+
+/// Time left until `time`, zero if it has passed
+fn until(time: DateTime<Utc>) -> Duration {
+    (time - Utc::now()).to_std().unwrap_or_default()
+}
+
+/// The instant a wall-clock time falls on
+fn instant_at(time: DateTime<Utc>) -> Instant {
+    Instant::now() + until(time)
+}
+
 /// Represents a tweet from the API
 #[derive(Debug, Clone)]
 pub struct Tweet {
@@ -241,6 +641,8 @@ pub struct Tweet {
     pub text: String,
     pub created_at: String,
     pub in_reply_to: Option<TweetId>,
+    /// ID of the tweet that started the conversation
+    pub conversation_id: Option<TweetId>,
 }
 
 // ===== Twitter API v2 Response Types =====
@@ -249,12 +651,116 @@ pub struct Tweet {
 #[derive(Debug, Deserialize)]
 struct TwitterApiResponse<T> {
     data: Option<T>,
+    #[serde(default)]
+    errors: Vec<Problem>,
+}
+
+/// A problem reported by the v2 API
+///
+/// Error responses are problem JSON (`title`, `detail`, `type`), often
+/// with the individual problems listed in `errors`. Entries of `errors`
+/// have the same shape, or the older `message` form.
+#[derive(Debug, Default, Deserialize)]
+struct Problem {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    detail: Option<String>,
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    errors: Vec<Problem>,
+}
+
+impl Problem {
+    /// Parse an error response body, keeping bodies that are not problem
+    /// JSON as the detail
+    fn parse(body: &str) -> Self {
+        serde_json::from_str(body).unwrap_or_else(|_| Problem {
+            detail: Some(body.trim().to_string()).filter(|d| !d.is_empty()),
+            ..Default::default()
+        })
+    }
+
+    /// Most specific description of what went wrong
+    fn detail(&self) -> String {
+        let first = self.errors.first();
+        [
+            first.and_then(|p| p.detail.as_ref()),
+            first.and_then(|p| p.message.as_ref()),
+            self.detail.as_ref(),
+            first.and_then(|p| p.title.as_ref()),
+            self.title.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .find(|d| !d.is_empty())
+        .cloned()
+        .unwrap_or_default()
+    }
+
+    /// Problem type URI, from the first listed problem if there is one
+    fn kind(&self) -> &str {
+        self.errors
+            .first()
+            .and_then(|p| p.kind.as_deref())
+            .or(self.kind.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Classify the problem of a response with the given status
+    ///
+    /// Twitter reports duplicate content, over-long tweets and suspended
+    /// accounts under generic statuses, so the detail is checked first.
+    fn classify(&self, status: StatusCode) -> ApiError {
+        let detail = self.detail();
+        let kind = self.kind();
+        let text = detail.to_lowercase();
+
+        if text.contains("duplicate content") {
+            ApiError::DuplicateContent
+        } else if text.contains("too long") || text.contains("shorter") {
+            ApiError::TooLong(detail)
+        } else if text.contains("suspended") {
+            ApiError::Suspended(detail)
+        } else if status == StatusCode::NOT_FOUND || kind.ends_with("/resource-not-found") {
+            ApiError::TweetNotFound(detail)
+        } else if status == StatusCode::UNAUTHORIZED {
+            ApiError::Unauthorized(detail)
+        } else if status == StatusCode::FORBIDDEN
+            || kind.ends_with("/not-authorized-for-resource")
+            || kind.ends_with("/client-forbidden")
+        {
+            ApiError::Forbidden(detail)
+        } else if status.is_server_error() {
+            ApiError::Server { status: status.as_u16(), detail }
+        } else {
+            ApiError::Other { status: status.as_u16(), detail }
+        }
+    }
 }
 
 /// Twitter API v2 list response wrapper
 #[derive(Debug, Deserialize)]
 struct TwitterApiListResponse<T> {
     data: Option<Vec<T>>,
+    #[serde(default)]
+    meta: Option<ListMeta>,
+}
+
+/// Pagination info of a list response
+#[derive(Debug, Deserialize)]
+struct ListMeta {
+    #[serde(default)]
+    next_token: Option<String>,
+}
+
+/// User data from Twitter API
+#[derive(Debug, Deserialize)]
+struct UserData {
+    id: String,
 }
 
 /// Tweet data from Twitter API
@@ -267,6 +773,8 @@ struct TweetData {
     #[serde(default)]
     created_at: Option<String>,
     #[serde(default)]
+    conversation_id: Option<String>,
+    #[serde(default)]
     referenced_tweets: Option<Vec<ReferencedTweet>>,
 }
 
@@ -295,6 +803,7 @@ impl From<TweetData> for Tweet {
             text: data.text,
             created_at: data.created_at.unwrap_or_default(),
             in_reply_to,
+            conversation_id: data.conversation_id,
         }
     }
 }
@@ -319,6 +828,17 @@ struct ReplySettings {
     in_reply_to_tweet_id: String,
 }
 
+/// Content as tweet text
+///
+/// Tweets can only hold text, so non-UTF-8 content is rejected rather
+/// than silently replaced. Binary content must go through a
+/// [`TextCodec`](crate::util::codec::TextCodec) first.
+fn tweet_text(content: &[u8]) -> Result<&str> {
+    std::str::from_utf8(content).map_err(|e| {
+        XFilesError::InvalidEncoding(format!("Tweet content is not valid UTF-8: {}", e))
+    })
+}
+
 /// Trait for remote storage adapters (allows multiple backends)
 #[async_trait]
 pub trait RemoteAdapter: Send + Sync {
@@ -331,8 +851,38 @@ pub trait RemoteAdapter: Send + Sync {
     /// Store content as reply to parent
     async fn store_reply(&self, parent_id: &TweetId, content: &[u8]) -> Result<TweetId>;
 
-    /// Fetch all replies to a tweet
-    async fn fetch_replies(&self, id: &TweetId) -> Result<Vec<TweetId>>;
+    /// Fetch all replies to a tweet, with their authors
+    async fn fetch_replies(&self, id: &TweetId) -> Result<Vec<Tweet>>;
+
+    /// Fetch every reply below a tweet, at any depth, with their authors
+    ///
+    /// The default asks [`fetch_replies`](Self::fetch_replies) for each
+    /// tweet in turn; adapters that can list a whole thread at once should
+    /// do so instead.
+    async fn fetch_thread(&self, id: &TweetId) -> Result<Vec<Tweet>> {
+        let mut found = Vec::new();
+        let mut queue = VecDeque::from([id.clone()]);
+        while let Some(parent) = queue.pop_front() {
+            for reply in self.fetch_replies(&parent).await? {
+                queue.push_back(reply.id.clone());
+                found.push(reply);
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Author ID of the authenticated account, as it appears in
+    /// [`Tweet::author_id`]
+    async fn account_id(&self) -> Result<String>;
+
+    /// Fetch every tweet of the authenticated account that is still
+    /// available, in any order
+    async fn fetch_timeline(&self) -> Result<Vec<Tweet>>;
+
+    /// Fetch the latest tweets of the authenticated account, including
+    /// ones posted a moment ago
+    async fn fetch_recent(&self) -> Result<Vec<Tweet>>;
 }
 
 #[async_trait]
@@ -343,17 +893,154 @@ impl RemoteAdapter for TwitterAdapter {
     }
 
     async fn store(&self, content: &[u8]) -> Result<TweetId> {
-        let text = String::from_utf8_lossy(content);
-        self.post_tweet(&text).await
+        self.post_tweet(tweet_text(content)?).await
     }
 
     async fn store_reply(&self, parent_id: &TweetId, content: &[u8]) -> Result<TweetId> {
-        let text = String::from_utf8_lossy(content);
-        self.post_reply(parent_id, &text).await
+        self.post_reply(parent_id, tweet_text(content)?).await
+    }
+
+    async fn fetch_replies(&self, id: &TweetId) -> Result<Vec<Tweet>> {
+        self.get_replies(id).await
+    }
+
+    async fn fetch_thread(&self, id: &TweetId) -> Result<Vec<Tweet>> {
+        self.get_thread(id).await
+    }
+
+    async fn account_id(&self) -> Result<String> {
+        self.get_user_id().await
     }
 
-    async fn fetch_replies(&self, id: &TweetId) -> Result<Vec<TweetId>> {
-        let replies = self.get_replies(id).await?;
-        Ok(replies.into_iter().map(|t| t.id).collect())
+    async fn fetch_timeline(&self) -> Result<Vec<Tweet>> {
+        self.get_timeline().await
+    }
+
+    async fn fetch_recent(&self) -> Result<Vec<Tweet>> {
+        self.get_recent_tweets().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_problems() {
+        let classify = |status: u16, body: &str| {
+            Problem::parse(body).classify(StatusCode::from_u16(status).unwrap())
+        };
+
+        assert_eq!(
+            classify(403, r#"{"title":"Forbidden","type":"about:blank","status":403,"detail":"You are not allowed to create a Tweet with duplicate content."}"#),
+            ApiError::DuplicateContent
+        );
+        assert!(matches!(
+            classify(401, r#"{"title":"Unauthorized","type":"about:blank","status":401,"detail":"Unauthorized"}"#),
+            ApiError::Unauthorized(_)
+        ));
+        assert!(matches!(
+            classify(403, r#"{"title":"Forbidden","status":403,"detail":"Your account is suspended and is not permitted to access this feature."}"#),
+            ApiError::Suspended(_)
+        ));
+        assert!(matches!(
+            classify(400, r#"{"errors":[{"message":"Your Tweet text is too long."}],"title":"Invalid Request","detail":"One or more parameters to your request was invalid.","type":"https://api.twitter.com/2/problems/invalid-request"}"#),
+            ApiError::TooLong(_)
+        ));
+        assert_eq!(
+            classify(200, r#"{"errors":[{"value":"1","detail":"Could not find tweet with id: [1].","title":"Not Found Error","resource_type":"tweet","type":"https://api.twitter.com/2/problems/resource-not-found"}]}"#),
+            ApiError::TweetNotFound("Could not find tweet with id: [1].".to_string())
+        );
+        assert!(matches!(
+            classify(403, r#"{"title":"Client Forbidden","type":"https://api.twitter.com/2/problems/client-forbidden","detail":"This request must be made using an approved developer account."}"#),
+            ApiError::Forbidden(_)
+        ));
+
+        let server = classify(503, "Service Unavailable");
+        assert_eq!(server, ApiError::Server { status: 503, detail: "Service Unavailable".to_string() });
+        assert!(server.is_retryable());
+        assert!(!classify(400, "{}").is_retryable());
+    }
+
+    #[test]
+    fn test_direct_replies() {
+        let tweet = |id: &str, in_reply_to: Option<&str>| Tweet {
+            id: id.to_string(),
+            author_id: "1".to_string(),
+            text: String::new(),
+            created_at: String::new(),
+            in_reply_to: in_reply_to.map(str::to_string),
+            conversation_id: Some("root".to_string()),
+        };
+        let tweets = vec![
+            tweet("a", Some("root")),
+            tweet("b", Some("a")),
+            tweet("c", Some("root")),
+            tweet("root", None),
+        ];
+
+        let ids = |replies: Vec<Tweet>| replies.into_iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(ids(direct_replies(tweets.clone(), &"root".to_string())), vec!["a", "c"]);
+        assert_eq!(ids(direct_replies(tweets.clone(), &"a".to_string())), vec!["b"]);
+        assert!(direct_replies(tweets, &"b".to_string()).is_empty());
+    }
+
+    #[test]
+    fn test_thread() {
+        let tweet = |id: &str, in_reply_to: Option<&str>| Tweet {
+            id: id.to_string(),
+            author_id: "1".to_string(),
+            text: String::new(),
+            created_at: String::new(),
+            in_reply_to: in_reply_to.map(str::to_string),
+            conversation_id: Some("root".to_string()),
+        };
+        let tweets = vec![
+            tweet("c", Some("b")),
+            tweet("b", Some("a")),
+            tweet("other", Some("root")),
+            tweet("a", Some("root")),
+            tweet("root", None),
+        ];
+
+        let ids = |found: Vec<Tweet>| found.into_iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(ids(thread(tweets.clone(), &"a".to_string())), vec!["b", "c"]);
+        assert_eq!(ids(thread(tweets, &"root".to_string())), vec!["other", "a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_quotas() {
+        let adapter = TwitterAdapter::builder("key", "secret", "token", "token secret")
+            .endpoint_limit(USERS_ME, 2, LIMIT_WINDOW)
+            .build();
+
+        for _ in 0..SEARCH_RECENT_LIMIT {
+            adapter.reads.acquire_for(SEARCH_RECENT).await;
+        }
+        assert!(!adapter.reads.can_proceed_for(SEARCH_RECENT).await);
+        assert!(adapter.reads.can_proceed_for(TWEET_LOOKUP).await);
+
+        adapter.reads.acquire_for(SEARCH_ALL).await;
+        assert!(!adapter.reads.can_proceed_for(SEARCH_ALL).await);
+
+        adapter.reads.acquire_for(USERS_ME).await;
+        adapter.reads.acquire_for(USERS_ME).await;
+        assert!(!adapter.reads.can_proceed_for(USERS_ME).await);
+    }
+
+    #[test]
+    fn test_rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(rate_limit_headers(&headers), None);
+
+        headers.insert("x-rate-limit-limit", "900".parse().unwrap());
+        headers.insert("x-rate-limit-remaining", "0".parse().unwrap());
+        headers.insert("x-rate-limit-reset", "1767225600".parse().unwrap());
+        let (remaining, reset) = rate_limit_headers(&headers).unwrap();
+        assert_eq!(remaining, 0);
+        assert_eq!(reset.to_rfc3339(), "2026-01-01T00:00:00+00:00");
+
+        headers.insert("x-rate-limit-reset", "soon".parse().unwrap());
+        assert_eq!(rate_limit_headers(&headers), None);
     }
 }
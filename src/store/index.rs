@@ -1,32 +1,256 @@
 //! Graph indexing operations
 
 use crate::dag::commit::{Commit, TweetId};
-use crate::error::Result;
+use crate::error::{Result, XFilesError};
+use crate::store::SqliteStore;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::RwLock;
 
 /// Indexer for maintaining fast graph traversal
+///
+/// Keeps parent and child adjacency lists plus a generation number per
+/// commit (1 for roots, one more than the highest parent otherwise), so a
+/// commit can never be an ancestor of one with a lower or equal generation.
+/// Lowest common ancestor lookups are cached until the next commit is
+/// indexed.
 pub struct GraphIndex {
-    // TODO: Implement efficient index structure
+    /// Parent edges by commit
+    parents: HashMap<TweetId, Vec<TweetId>>,
+    /// Child edges by commit
+    children: HashMap<TweetId, Vec<TweetId>>,
+    /// Generation number by commit
+    generations: HashMap<TweetId, u64>,
+    /// Cached merge bases by commit pair
+    lca_cache: RwLock<HashMap<(TweetId, TweetId), Option<TweetId>>>,
 }
 
 impl GraphIndex {
     /// Create a new graph index
     pub fn new() -> Self {
-        Self {}
+        Self {
+            parents: HashMap::new(),
+            children: HashMap::new(),
+            generations: HashMap::new(),
+            lca_cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Build an index of every commit in a store
+    pub async fn from_store(store: &SqliteStore) -> Result<Self> {
+        let mut index = Self::new();
+        index.rebuild(&store.list_commits().await?)?;
+        Ok(index)
     }
 
     /// Index a new commit
-    pub fn index_commit(&mut self, _commit: &Commit) -> Result<()> {
-        todo!("Implement commit indexing")
+    ///
+    /// Fails without modifying the index if the commit would close a cycle.
+    pub fn index_commit(&mut self, commit: &Commit) -> Result<()> {
+        let id = &commit.id;
+        if commit.parents.contains(id) {
+            return Err(XFilesError::Other(format!("Commit {} is its own parent", id)));
+        }
+
+        let generation = 1 + commit
+            .parents
+            .iter()
+            .filter_map(|p| self.generations.get(p))
+            .max()
+            .copied()
+            .unwrap_or(0);
+
+        // Raise the generations of already indexed descendants
+        let mut updates = HashMap::new();
+        let mut queue: VecDeque<(TweetId, u64)> = self
+            .children
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|child| (child.clone(), generation + 1))
+            .collect();
+        while let Some((current, min_generation)) = queue.pop_front() {
+            if &current == id || commit.parents.contains(&current) {
+                return Err(XFilesError::Other(format!("Commit {} forms a cycle", id)));
+            }
+            let known = updates
+                .get(&current)
+                .or_else(|| self.generations.get(&current))
+                .copied()
+                .unwrap_or(0);
+            if known >= min_generation {
+                continue;
+            }
+            updates.insert(current.clone(), min_generation);
+            for child in self.children.get(&current).into_iter().flatten() {
+                queue.push_back((child.clone(), min_generation + 1));
+            }
+        }
+
+        // Replace any previous edges of this commit
+        if let Some(old_parents) = self.parents.remove(id) {
+            for parent in old_parents {
+                if let Some(siblings) = self.children.get_mut(&parent) {
+                    siblings.retain(|c| c != id);
+                }
+            }
+        }
+        for parent in &commit.parents {
+            let siblings = self.children.entry(parent.clone()).or_default();
+            if !siblings.contains(id) {
+                siblings.push(id.clone());
+            }
+        }
+        self.parents.insert(id.clone(), commit.parents.clone());
+        self.generations.insert(id.clone(), generation);
+        self.generations.extend(updates);
+
+        if let Ok(mut cache) = self.lca_cache.write() {
+            cache.clear();
+        }
+
+        Ok(())
     }
 
     /// Find the path from one commit to another
-    pub fn find_path(&self, _from: &TweetId, _to: &TweetId) -> Result<Vec<TweetId>> {
-        todo!("Implement path finding")
+    ///
+    /// Returns the commits along child edges from `from` to its descendant
+    /// `to`, both included.
+    pub fn find_path(&self, from: &TweetId, to: &TweetId) -> Result<Vec<TweetId>> {
+        let target_generation = self
+            .generation(to)
+            .ok_or_else(|| XFilesError::CommitNotFound(to.clone()))?;
+
+        let mut previous: HashMap<&TweetId, &TweetId> = HashMap::new();
+        let mut queue = VecDeque::from([from]);
+        let mut visited = HashSet::from([from]);
+
+        while let Some(current) = queue.pop_front() {
+            if current == to {
+                let mut path = vec![current.clone()];
+                let mut node = current;
+                while let Some(&prev) = previous.get(node) {
+                    path.push(prev.clone());
+                    node = prev;
+                }
+                path.reverse();
+                return Ok(path);
+            }
+
+            for child in self.children(current) {
+                // Commits at or past the target's generation cannot reach it
+                let reachable = child == to
+                    || self.generation(child).is_some_and(|g| g < target_generation);
+                if reachable && visited.insert(child) {
+                    previous.insert(child, current);
+                    queue.push_back(child);
+                }
+            }
+        }
+
+        Err(XFilesError::CommitNotFound(format!("no path from {} to {}", from, to)))
     }
 
     /// Rebuild the index from stored commits
-    pub fn rebuild(&mut self, _commits: &[Commit]) -> Result<()> {
-        todo!("Implement index rebuild")
+    pub fn rebuild(&mut self, commits: &[Commit]) -> Result<()> {
+        *self = Self::new();
+        for commit in commits {
+            self.index_commit(commit)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a commit is indexed
+    pub fn contains(&self, id: &TweetId) -> bool {
+        self.parents.contains_key(id)
+    }
+
+    /// Get the children of a commit
+    pub fn children(&self, id: &TweetId) -> &[TweetId] {
+        self.children.get(id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// Get the generation number of a commit
+    pub fn generation(&self, id: &TweetId) -> Option<u64> {
+        self.generations.get(id).copied()
+    }
+
+    /// Get a commit and all of its indexed descendants
+    pub fn descendants(&self, start: &TweetId) -> Vec<TweetId> {
+        let mut reachable = vec![start.clone()];
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+
+        while let Some(current) = queue.pop_front() {
+            for child in self.children(current) {
+                if self.contains(child) && visited.insert(child) {
+                    reachable.push(child.clone());
+                    queue.push_back(child);
+                }
+            }
+        }
+
+        reachable
+    }
+
+    /// Get the commits reachable from `start` that have no children
+    pub fn heads(&self, start: &TweetId) -> Vec<TweetId> {
+        self.descendants(start)
+            .into_iter()
+            .filter(|id| self.contains(id) && !self.children(id).iter().any(|c| self.contains(c)))
+            .collect()
+    }
+
+    /// Get a commit and all of its indexed ancestors
+    pub fn ancestors(&self, start: &TweetId) -> HashSet<TweetId> {
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([start.clone()]);
+
+        while let Some(current) = queue.pop_front() {
+            if !self.contains(&current) || !visited.insert(current.clone()) {
+                continue;
+            }
+            for parent in self.parents.get(&current).into_iter().flatten() {
+                queue.push_back(parent.clone());
+            }
+        }
+
+        visited
+    }
+
+    /// Whether `ancestor` is `id` or one of its ancestors
+    pub fn is_ancestor(&self, ancestor: &TweetId, id: &TweetId) -> bool {
+        match (self.generation(ancestor), self.generation(id)) {
+            (Some(a), Some(b)) if a < b => self.ancestors(id).contains(ancestor),
+            _ => ancestor == id && self.contains(id),
+        }
+    }
+
+    /// Find the lowest common ancestor of two commits (merge base)
+    ///
+    /// Picks the common ancestor with the highest generation, which no
+    /// other common ancestor can descend from.
+    pub fn merge_base(&self, a: &TweetId, b: &TweetId) -> Option<TweetId> {
+        let key = if a <= b { (a.clone(), b.clone()) } else { (b.clone(), a.clone()) };
+        if let Some(cached) = self.lca_cache.read().ok().and_then(|c| c.get(&key).cloned()) {
+            return cached;
+        }
+
+        let ancestors_a = self.ancestors(a);
+        let base = self
+            .ancestors(b)
+            .into_iter()
+            .filter(|id| ancestors_a.contains(id))
+            .max_by(|x, y| {
+                let gx = self.generation(x).unwrap_or(0);
+                let gy = self.generation(y).unwrap_or(0);
+                gx.cmp(&gy).then_with(|| y.cmp(x))
+            });
+
+        if let Ok(mut cache) = self.lca_cache.write() {
+            cache.insert(key, base.clone());
+        }
+
+        base
     }
 }
 
@@ -35,3 +259,82 @@ impl Default for GraphIndex {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(id: &str, parents: &[&str]) -> Commit {
+        Commit::new(
+            id.to_string(),
+            parents.iter().map(|p| p.to_string()).collect(),
+            "author".to_string(),
+            String::new(),
+            "text/plain".to_string(),
+            0,
+        )
+    }
+
+    /// r - a - b - m
+    ///      \     /
+    ///       c ---
+    fn diamond() -> Vec<Commit> {
+        vec![
+            commit("r", &[]),
+            commit("a", &["r"]),
+            commit("b", &["a"]),
+            commit("c", &["a"]),
+            commit("m", &["b", "c"]),
+        ]
+    }
+
+    #[test]
+    fn test_generations_out_of_order() {
+        let mut commits = diamond();
+        commits.reverse();
+
+        let mut index = GraphIndex::new();
+        index.rebuild(&commits).unwrap();
+
+        assert_eq!(index.generation(&"r".to_string()), Some(1));
+        assert_eq!(index.generation(&"a".to_string()), Some(2));
+        assert_eq!(index.generation(&"m".to_string()), Some(4));
+        assert_eq!(index.heads(&"r".to_string()), vec!["m".to_string()]);
+    }
+
+    #[test]
+    fn test_find_path() {
+        let mut index = GraphIndex::new();
+        index.rebuild(&diamond()).unwrap();
+
+        let path = index.find_path(&"r".to_string(), &"m".to_string()).unwrap();
+        assert_eq!(path.first(), Some(&"r".to_string()));
+        assert_eq!(path.last(), Some(&"m".to_string()));
+        assert_eq!(path.len(), 4);
+
+        assert!(index.find_path(&"b".to_string(), &"c".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_merge_base() {
+        let mut index = GraphIndex::new();
+        index.rebuild(&diamond()[..4]).unwrap();
+
+        let base = index.merge_base(&"b".to_string(), &"c".to_string());
+        assert_eq!(base, Some("a".to_string()));
+        assert!(index.is_ancestor(&"r".to_string(), &"c".to_string()));
+        assert!(!index.is_ancestor(&"b".to_string(), &"c".to_string()));
+
+        // Cached results are dropped once the graph changes
+        index.index_commit(&commit("m", &["b", "c"])).unwrap();
+        assert_eq!(index.merge_base(&"m".to_string(), &"c".to_string()), Some("c".to_string()));
+    }
+
+    #[test]
+    fn test_rejects_cycles() {
+        let mut index = GraphIndex::new();
+        index.index_commit(&commit("a", &["b"])).unwrap();
+        assert!(index.index_commit(&commit("b", &["a"])).is_err());
+        assert!(index.index_commit(&commit("c", &["c"])).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Versioned schema migrations for the SQLite index
+//!
+//! The schema version is kept in `PRAGMA user_version`. Databases created
+//! before versioning report version 0 and are brought up to date by the
+//! same migrations as new ones, which is why the first migration, creating
+//! the original tables, tolerates tables that already exist. Later
+//! migrations are not idempotent and rely on `user_version` to run exactly
+//! once.
+
+use crate::error::{Result, XFilesError};
+use sqlx::{Row, SqliteConnection, SqlitePool};
+
+/// Schema version written by this build
+pub const SCHEMA_VERSION: u32 = 5;
+
+/// Description of each migration, by version
+const MIGRATIONS: &[(u32, &str)] = &[
+    (1, "commits, chunks and files tables"),
+    (2, "commit_parents edge table"),
+    (3, "text codec of each commit"),
+    (4, "content-addressed blocks table"),
+    (5, "per-file head refs"),
+];
+
+/// Tables of the original, unversioned schema
+///
+/// The JSON `parent_id` column and its index are left out: migration 2
+/// replaces them, and databases created before versioning already have
+/// them.
+const BASELINE: &[&str] = &[
+    r#"
+    CREATE TABLE IF NOT EXISTS commits (
+        tweet_id TEXT PRIMARY KEY,
+        timestamp INTEGER NOT NULL,
+        author TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        mime TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        head BOOLEAN DEFAULT 0
+    )
+    "#,
+    r#"
+    CREATE TABLE IF NOT EXISTS chunks (
+        tweet_id TEXT PRIMARY KEY,
+        parent_commit TEXT NOT NULL,
+        idx INTEGER NOT NULL,
+        size INTEGER NOT NULL,
+        hash TEXT NOT NULL,
+        FOREIGN KEY (parent_commit) REFERENCES commits(tweet_id)
+    )
+    "#,
+    "CREATE INDEX IF NOT EXISTS idx_timestamp ON commits(timestamp)",
+    r#"
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        root_tweet_id TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    )
+    "#,
+];
+
+/// Parent edges, one row per parent in commit order
+const COMMIT_PARENTS: &[&str] = &[
+    r#"
+    CREATE TABLE IF NOT EXISTS commit_parents (
+        child TEXT NOT NULL,
+        parent TEXT NOT NULL,
+        ordinal INTEGER NOT NULL,
+        PRIMARY KEY (child, ordinal),
+        FOREIGN KEY (child) REFERENCES commits(tweet_id) ON DELETE CASCADE
+    )
+    "#,
+    "CREATE INDEX IF NOT EXISTS idx_commit_parents_parent ON commit_parents(parent)",
+];
+
+/// Tweets holding content-defined blocks, by blake3 hash of the block
+const BLOCKS: &[&str] = &[r#"
+    CREATE TABLE IF NOT EXISTS blocks (
+        hash TEXT PRIMARY KEY,
+        tweet_id TEXT NOT NULL,
+        size INTEGER NOT NULL
+    )
+    "#];
+
+/// Head commit of each file, and the head flags older versions never
+/// cleared
+///
+/// Files opened before refs existed get theirs on their next open.
+const REFS: &[&str] = &[
+    r#"
+    CREATE TABLE IF NOT EXISTS refs (
+        path TEXT PRIMARY KEY,
+        head TEXT NOT NULL
+    )
+    "#,
+    r#"
+    UPDATE commits
+    SET head = 0
+    WHERE tweet_id IN (SELECT parent FROM commit_parents)
+    "#,
+];
+
+/// Bring a database up to [`SCHEMA_VERSION`]
+///
+/// All pending migrations run in a single transaction, so a failure leaves
+/// the database at its previous version. Databases written by a newer
+/// version of xfiles are rejected with [`XFilesError::SchemaTooNew`].
+pub async fn migrate(pool: &SqlitePool) -> Result<()> {
+    let mut tx = pool.begin().await?;
+
+    let current = schema_version(&mut tx).await?;
+    if current > SCHEMA_VERSION {
+        return Err(XFilesError::SchemaTooNew {
+            found: current,
+            supported: SCHEMA_VERSION,
+        });
+    }
+
+    for &(version, _) in MIGRATIONS.iter().filter(|(v, _)| *v > current) {
+        apply(&mut tx, version).await?;
+        // PRAGMA values cannot be bound as parameters
+        sqlx::query(&format!("PRAGMA user_version = {}", version))
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Read the schema version of a database
+pub async fn schema_version(conn: &mut SqliteConnection) -> Result<u32> {
+    let row = sqlx::query("PRAGMA user_version").fetch_one(conn).await?;
+    Ok(row.try_get::<i64, _>(0)? as u32)
+}
+
+/// Apply a single migration
+async fn apply(conn: &mut SqliteConnection, version: u32) -> Result<()> {
+    match version {
+        1 => execute_all(conn, BASELINE).await,
+        2 => {
+            execute_all(conn, COMMIT_PARENTS).await?;
+            migrate_parent_column(conn).await
+        }
+        3 => execute_all(conn, &["ALTER TABLE commits ADD COLUMN encoding TEXT"]).await,
+        4 => execute_all(conn, BLOCKS).await,
+        5 => execute_all(conn, REFS).await,
+        _ => Err(XFilesError::Other(format!("Unknown schema migration {}", version))),
+    }
+}
+
+/// Run a list of statements in order
+async fn execute_all(conn: &mut SqliteConnection, statements: &[&str]) -> Result<()> {
+    for statement in statements {
+        sqlx::query(statement).execute(&mut *conn).await?;
+    }
+    Ok(())
+}
+
+/// Move parents out of the JSON `parent_id` column of older databases
+///
+/// Every parent listed in the column becomes a `commit_parents` edge, then
+/// the column and its index are dropped.
+async fn migrate_parent_column(conn: &mut SqliteConnection) -> Result<()> {
+    let row = sqlx::query(
+        "SELECT COUNT(*) AS count FROM pragma_table_info('commits') WHERE name = 'parent_id'",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if row.try_get::<i64, _>("count")? == 0 {
+        return Ok(());
+    }
+
+    sqlx::query(
+        r#"
+        INSERT OR IGNORE INTO commit_parents (child, parent, ordinal)
+        SELECT commits.tweet_id, parents.value, CAST(parents.key AS INTEGER)
+        FROM commits, json_each(commits.parent_id) AS parents
+        WHERE json_valid(commits.parent_id)
+        "#,
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    execute_all(
+        conn,
+        &["DROP INDEX IF EXISTS idx_parent", "ALTER TABLE commits DROP COLUMN parent_id"],
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    async fn pool() -> SqlitePool {
+        SqlitePoolOptions::new().connect("sqlite://:memory:").await.unwrap()
+    }
+
+    async fn version(pool: &SqlitePool) -> u32 {
+        schema_version(&mut pool.acquire().await.unwrap()).await.unwrap()
+    }
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        let versions: Vec<u32> = MIGRATIONS.iter().map(|(v, _)| *v).collect();
+        let expected: Vec<u32> = (1..=SCHEMA_VERSION).collect();
+        assert_eq!(versions, expected);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_fresh_database() {
+        let pool = pool().await;
+        migrate(&pool).await.unwrap();
+        assert_eq!(version(&pool).await, SCHEMA_VERSION);
+
+        // Already up to date
+        migrate(&pool).await.unwrap();
+        assert_eq!(version(&pool).await, SCHEMA_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_clears_stale_head_flags() {
+        let pool = pool().await;
+        let mut conn = pool.acquire().await.unwrap();
+        for version in 1..=4 {
+            apply(&mut conn, version).await.unwrap();
+        }
+        sqlx::query("PRAGMA user_version = 4").execute(&mut *conn).await.unwrap();
+
+        // Every commit written by version 4 kept its head flag
+        for id in ["root", "a", "b"] {
+            sqlx::query(
+                "INSERT INTO commits (tweet_id, timestamp, author, hash, mime, size, head) \
+                 VALUES (?, 0, 'me', '', 'text/plain', 0, 1)",
+            )
+            .bind(id)
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+        }
+        for (child, parent) in [("a", "root"), ("b", "a")] {
+            sqlx::query("INSERT INTO commit_parents (child, parent, ordinal) VALUES (?, ?, 0)")
+                .bind(child)
+                .bind(parent)
+                .execute(&mut *conn)
+                .await
+                .unwrap();
+        }
+        drop(conn);
+
+        migrate(&pool).await.unwrap();
+        assert_eq!(version(&pool).await, SCHEMA_VERSION);
+
+        let heads: Vec<String> = sqlx::query("SELECT tweet_id FROM commits WHERE head = 1")
+            .fetch_all(&pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get("tweet_id"))
+            .collect();
+        assert_eq!(heads, vec!["b"]);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_newer_schema() {
+        let pool = pool().await;
+        sqlx::query(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        match migrate(&pool).await {
+            Err(XFilesError::SchemaTooNew { found, supported }) => {
+                assert_eq!(found, SCHEMA_VERSION + 1);
+                assert_eq!(supported, SCHEMA_VERSION);
+            }
+            other => panic!("expected SchemaTooNew, got {:?}", other),
+        }
+    }
+}
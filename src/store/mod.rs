@@ -6,6 +6,7 @@
 pub mod sqlite;
 pub mod cache;
 pub mod index;
+pub mod migrations;
 
 pub use sqlite::SqliteStore;
 pub use cache::ContentCache;
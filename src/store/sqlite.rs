@@ -1,9 +1,12 @@
 //! SQLite database operations
 
-use crate::dag::commit::{Commit, TweetId};
-use crate::error::Result;
+use crate::dag::commit::{ChunkRef, Commit, TweetId};
+use crate::error::{Result, XFilesError};
+use crate::store::migrations;
+use crate::util::codec::TextCodec;
 use chrono::{DateTime, Utc};
-use sqlx::{Row, SqlitePool, sqlite::SqlitePoolOptions};
+use sqlx::{Row, SqliteConnection, SqlitePool, sqlite::{SqlitePoolOptions, SqliteRow}};
+use std::collections::HashMap;
 
 /// SQLite store for commit graph and metadata
 pub struct SqliteStore {
@@ -22,171 +25,287 @@ impl SqliteStore {
     }
 
     /// Initialize the database schema
+    ///
+    /// Applies any pending migrations. Fails with
+    /// [`XFilesError::SchemaTooNew`](crate::error::XFilesError::SchemaTooNew)
+    /// if the database was written by a newer version of xfiles.
     pub async fn init_schema(&self) -> Result<()> {
-        sqlx::query(
+        migrations::migrate(&self.pool).await
+    }
+
+    /// Get the schema version of the database
+    pub async fn schema_version(&self) -> Result<u32> {
+        migrations::schema_version(&mut *self.pool.acquire().await?).await
+    }
+
+    /// Store a commit in the database
+    ///
+    /// The commit row and its parent edges are written in one transaction.
+    pub async fn store_commit(&self, commit: &Commit) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        insert_commit(&mut tx, commit).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Record a new head commit of a file
+    ///
+    /// The commit, its parent edges and chunk manifest, the head flags and
+    /// the file's ref are written in one transaction, so the ref never
+    /// points at a commit the index does not have.
+    pub async fn advance_head(&self, path: &str, commit: &Commit, chunks: &[ChunkRef]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        insert_commit(&mut tx, commit).await?;
+        insert_chunks(&mut tx, &commit.id, chunks).await?;
+        mark_head(&mut tx, &commit.id).await?;
+        set_ref(&mut tx, path, &commit.id).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Remove a commit, its parent edges and its chunk manifest
+    ///
+    /// Edges from children of the commit are left in place.
+    pub async fn delete_commit(&self, id: &TweetId) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("DELETE FROM commit_parents WHERE child = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM chunks WHERE parent_commit = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM commits WHERE tweet_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Get the parents of a commit, in commit order
+    pub async fn get_parents(&self, id: &TweetId) -> Result<Vec<TweetId>> {
+        let rows = sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS commits (
-                tweet_id TEXT PRIMARY KEY,
-                parent_id TEXT,
-                timestamp INTEGER NOT NULL,
-                author TEXT NOT NULL,
-                hash TEXT NOT NULL,
-                mime TEXT NOT NULL,
-                size INTEGER NOT NULL,
-                head BOOLEAN DEFAULT 0
-            )
+            SELECT parent
+            FROM commit_parents
+            WHERE child = ?
+            ORDER BY ordinal
             "#,
         )
-        .execute(&self.pool)
+        .bind(id)
+        .fetch_all(&self.pool)
         .await?;
 
-        sqlx::query(
+        let mut parents = Vec::new();
+        for row in rows {
+            parents.push(row.try_get("parent")?);
+        }
+
+        Ok(parents)
+    }
+
+    /// Retrieve a commit by ID
+    pub async fn get_commit(&self, id: &TweetId) -> Result<Option<Commit>> {
+        let row = sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS chunks (
-                tweet_id TEXT PRIMARY KEY,
-                parent_commit TEXT NOT NULL,
-                idx INTEGER NOT NULL,
-                size INTEGER NOT NULL,
-                hash TEXT NOT NULL,
-                FOREIGN KEY (parent_commit) REFERENCES commits(tweet_id)
-            )
+            SELECT tweet_id, timestamp, author, hash, mime, size, encoding, head
+            FROM commits
+            WHERE tweet_id = ?
             "#,
         )
-        .execute(&self.pool)
+        .bind(id)
+        .fetch_optional(&self.pool)
         .await?;
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_parent ON commits(parent_id)")
-            .execute(&self.pool)
-            .await?;
+        if let Some(row) = row {
+            let parents = self.get_parents(id).await?;
+            Ok(Some(commit_from_row(&row, parents)?))
+        } else {
+            Ok(None)
+        }
+    }
 
-        sqlx::query("CREATE INDEX IF NOT EXISTS idx_timestamp ON commits(timestamp)")
-            .execute(&self.pool)
-            .await?;
+    /// Get all commits with a specific parent
+    pub async fn get_children(&self, parent_id: &TweetId) -> Result<Vec<Commit>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT DISTINCT commits.tweet_id, commits.timestamp, commits.author,
+                commits.hash, commits.mime, commits.size, commits.encoding, commits.head
+            FROM commit_parents
+            JOIN commits ON commits.tweet_id = commit_parents.child
+            WHERE commit_parents.parent = ?
+            ORDER BY commits.timestamp, commits.tweet_id
+            "#,
+        )
+        .bind(parent_id)
+        .fetch_all(&self.pool)
+        .await?;
 
-        // Create files table for path-to-root mapping
-        sqlx::query(
+        let mut commits = Vec::new();
+        for row in rows {
+            let parents = self.get_parents(&row.try_get("tweet_id")?).await?;
+            commits.push(commit_from_row(&row, parents)?);
+        }
+
+        Ok(commits)
+    }
+
+    /// Get every commit in the store
+    pub async fn list_commits(&self) -> Result<Vec<Commit>> {
+        let rows = sqlx::query(
             r#"
-            CREATE TABLE IF NOT EXISTS files (
-                path TEXT PRIMARY KEY,
-                root_tweet_id TEXT NOT NULL,
-                created_at INTEGER NOT NULL
-            )
+            SELECT tweet_id, timestamp, author, hash, mime, size, encoding, head
+            FROM commits
+            ORDER BY timestamp
             "#,
         )
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await?;
 
+        let edges = sqlx::query("SELECT child, parent FROM commit_parents ORDER BY child, ordinal")
+            .fetch_all(&self.pool)
+            .await?;
+        let mut parents: HashMap<TweetId, Vec<TweetId>> = HashMap::new();
+        for edge in edges {
+            parents.entry(edge.try_get("child")?).or_default().push(edge.try_get("parent")?);
+        }
+
+        let mut commits = Vec::new();
+        for row in rows {
+            let id: TweetId = row.try_get("tweet_id")?;
+            let commit_parents = parents.remove(&id).unwrap_or_default();
+            commits.push(commit_from_row(&row, commit_parents)?);
+        }
+
+        Ok(commits)
+    }
+
+    /// Record the chunk manifest of a commit
+    pub async fn store_chunks(&self, commit_id: &TweetId, chunks: &[ChunkRef]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        insert_chunks(&mut tx, commit_id, chunks).await?;
+        tx.commit().await?;
+
         Ok(())
     }
 
-    /// Store a commit in the database
-    pub async fn store_commit(&self, commit: &Commit) -> Result<()> {
-        // Serialize parents as JSON for storage (supports multiple parents for future merging)
-        let parents_json = serde_json::to_string(&commit.parents)?;
+    /// Get the chunk manifest of a commit, ordered by chunk index
+    pub async fn get_chunks(&self, commit_id: &TweetId) -> Result<Vec<ChunkRef>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT tweet_id, idx, size, hash
+            FROM chunks
+            WHERE parent_commit = ?
+            ORDER BY idx
+            "#,
+        )
+        .bind(commit_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut chunks = Vec::new();
+        for row in rows {
+            chunks.push(ChunkRef {
+                id: row.try_get("tweet_id")?,
+                index: row.try_get::<i64, _>("idx")? as usize,
+                size: row.try_get::<i64, _>("size")? as usize,
+                hash: row.try_get("hash")?,
+            });
+        }
+
+        Ok(chunks)
+    }
 
+    /// Record the tweet holding a content-defined block
+    ///
+    /// The first tweet recorded for a hash is kept.
+    pub async fn store_block(&self, hash: &str, id: &TweetId, size: usize) -> Result<()> {
         sqlx::query(
             r#"
-            INSERT INTO commits (tweet_id, parent_id, timestamp, author, hash, mime, size, head)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
-            ON CONFLICT(tweet_id) DO UPDATE SET
-                parent_id = excluded.parent_id,
-                timestamp = excluded.timestamp,
-                author = excluded.author,
-                hash = excluded.hash,
-                mime = excluded.mime,
-                size = excluded.size,
-                head = excluded.head
+            INSERT OR IGNORE INTO blocks (hash, tweet_id, size)
+            VALUES (?, ?, ?)
             "#,
         )
-        .bind(&commit.id)
-        .bind(parents_json)
-        .bind(commit.timestamp.timestamp())
-        .bind(&commit.author)
-        .bind(&commit.hash)
-        .bind(&commit.mime)
-        .bind(commit.size as i64)
-        .bind(commit.is_head)
+        .bind(hash)
+        .bind(id)
+        .bind(size as i64)
         .execute(&self.pool)
         .await?;
 
         Ok(())
     }
 
-    /// Retrieve a commit by ID
-    pub async fn get_commit(&self, id: &TweetId) -> Result<Option<Commit>> {
+    /// Get the tweet holding the block with the given hash
+    pub async fn get_block(&self, hash: &str) -> Result<Option<TweetId>> {
         let row = sqlx::query(
             r#"
-            SELECT tweet_id, parent_id, timestamp, author, hash, mime, size, head
-            FROM commits
-            WHERE tweet_id = ?
+            SELECT tweet_id
+            FROM blocks
+            WHERE hash = ?
             "#,
         )
-        .bind(id)
+        .bind(hash)
         .fetch_optional(&self.pool)
         .await?;
 
         if let Some(row) = row {
-            let parents_json: String = row.try_get("parent_id")?;
-            let parents: Vec<TweetId> = serde_json::from_str(&parents_json)?;
-            let timestamp_secs: i64 = row.try_get("timestamp")?;
-
-            Ok(Some(Commit {
-                id: row.try_get("tweet_id")?,
-                parents,
-                timestamp: DateTime::from_timestamp(timestamp_secs, 0)
-                    .unwrap_or_else(|| Utc::now()),
-                hash: row.try_get("hash")?,
-                author: row.try_get("author")?,
-                mime: row.try_get("mime")?,
-                size: row.try_get::<i64, _>("size")? as usize,
-                is_head: row.try_get("head")?,
-            }))
+            Ok(Some(row.try_get("tweet_id")?))
         } else {
             Ok(None)
         }
     }
 
-    /// Get all commits with a specific parent
-    pub async fn get_children(&self, parent_id: &TweetId) -> Result<Vec<Commit>> {
+    /// List every recorded block as `(hash, tweet_id)`
+    pub async fn list_blocks(&self) -> Result<Vec<(String, TweetId)>> {
         let rows = sqlx::query(
             r#"
-            SELECT tweet_id, parent_id, timestamp, author, hash, mime, size, head
-            FROM commits
-            WHERE parent_id LIKE ?
+            SELECT hash, tweet_id
+            FROM blocks
+            ORDER BY hash
             "#,
         )
-        .bind(format!("%\"{}\"%%", parent_id))
         .fetch_all(&self.pool)
         .await?;
 
-        let mut commits = Vec::new();
+        let mut blocks = Vec::new();
         for row in rows {
-            let parents_json: String = row.try_get("parent_id")?;
-            let parents: Vec<TweetId> = serde_json::from_str(&parents_json)?;
-            let timestamp_secs: i64 = row.try_get("timestamp")?;
-
-            commits.push(Commit {
-                id: row.try_get("tweet_id")?,
-                parents,
-                timestamp: DateTime::from_timestamp(timestamp_secs, 0)
-                    .unwrap_or_else(|| Utc::now()),
-                hash: row.try_get("hash")?,
-                author: row.try_get("author")?,
-                mime: row.try_get("mime")?,
-                size: row.try_get::<i64, _>("size")? as usize,
-                is_head: row.try_get("head")?,
-            });
+            blocks.push((row.try_get("hash")?, row.try_get("tweet_id")?));
         }
 
-        Ok(commits)
+        Ok(blocks)
+    }
+
+    /// Forget the tweet holding a block
+    pub async fn delete_block(&self, hash: &str) -> Result<()> {
+        sqlx::query("DELETE FROM blocks WHERE hash = ?")
+            .bind(hash)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
     }
 
     /// Mark a commit as head
+    ///
+    /// Its parents stop being heads. Other heads are left alone, so forks
+    /// keep one head per branch.
     pub async fn set_head(&self, id: &TweetId) -> Result<()> {
+        mark_head(&mut *self.pool.acquire().await?, id).await
+    }
+
+    /// Clear the head flag of a commit
+    pub async fn clear_head(&self, id: &TweetId) -> Result<()> {
         sqlx::query(
             r#"
             UPDATE commits
-            SET head = 1
+            SET head = 0
             WHERE tweet_id = ?
             "#,
         )
@@ -201,7 +320,7 @@ impl SqliteStore {
     pub async fn get_heads(&self) -> Result<Vec<Commit>> {
         let rows = sqlx::query(
             r#"
-            SELECT tweet_id, parent_id, timestamp, author, hash, mime, size, head
+            SELECT tweet_id, timestamp, author, hash, mime, size, encoding, head
             FROM commits
             WHERE head = 1
             "#,
@@ -211,21 +330,8 @@ impl SqliteStore {
 
         let mut commits = Vec::new();
         for row in rows {
-            let parents_json: String = row.try_get("parent_id")?;
-            let parents: Vec<TweetId> = serde_json::from_str(&parents_json)?;
-            let timestamp_secs: i64 = row.try_get("timestamp")?;
-
-            commits.push(Commit {
-                id: row.try_get("tweet_id")?,
-                parents,
-                timestamp: DateTime::from_timestamp(timestamp_secs, 0)
-                    .unwrap_or_else(|| Utc::now()),
-                hash: row.try_get("hash")?,
-                author: row.try_get("author")?,
-                mime: row.try_get("mime")?,
-                size: row.try_get::<i64, _>("size")? as usize,
-                is_head: row.try_get("head")?,
-            });
+            let parents = self.get_parents(&row.try_get("tweet_id")?).await?;
+            commits.push(commit_from_row(&row, parents)?);
         }
 
         Ok(commits)
@@ -270,6 +376,63 @@ impl SqliteStore {
         }
     }
 
+    /// Get the head commit recorded for a file path
+    pub async fn get_file_head(&self, path: &str) -> Result<Option<TweetId>> {
+        let row = sqlx::query(
+            r#"
+            SELECT head
+            FROM refs
+            WHERE path = ?
+            "#,
+        )
+        .bind(path)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(row) = row {
+            Ok(Some(row.try_get("head")?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Record the head commit of a file path
+    pub async fn set_file_head(&self, path: &str, head: &TweetId) -> Result<()> {
+        set_ref(&mut *self.pool.acquire().await?, path, head).await
+    }
+
+    /// Forget the head commit recorded for a file path
+    ///
+    /// The next open resolves the head from the remote again.
+    pub async fn remove_file_head(&self, path: &str) -> Result<()> {
+        sqlx::query("DELETE FROM refs WHERE path = ?")
+            .bind(path)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// List every recorded file head as `(path, head)`
+    pub async fn list_file_heads(&self) -> Result<Vec<(String, TweetId)>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT path, head
+            FROM refs
+            ORDER BY path
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut heads = Vec::new();
+        for row in rows {
+            heads.push((row.try_get("path")?, row.try_get("head")?));
+        }
+
+        Ok(heads)
+    }
+
     /// List all registered file paths
     pub async fn list_files(&self) -> Result<Vec<String>> {
         let rows = sqlx::query(
@@ -290,6 +453,21 @@ impl SqliteStore {
         Ok(paths)
     }
 
+    /// Unregister a file path and its head ref
+    ///
+    /// The file's commits are left in the index.
+    pub async fn remove_file(&self, path: &str) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for statement in ["DELETE FROM refs WHERE path = ?", "DELETE FROM files WHERE path = ?"] {
+            sqlx::query(statement).bind(path).execute(&mut *tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     /// Check if a file exists
     pub async fn file_exists(&self, path: &str) -> Result<bool> {
         let row = sqlx::query(
@@ -307,3 +485,251 @@ impl SqliteStore {
         Ok(count > 0)
     }
 }
+
+/// Write a commit row and its parent edges
+async fn insert_commit(conn: &mut SqliteConnection, commit: &Commit) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO commits (tweet_id, timestamp, author, hash, mime, size, encoding, head)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(tweet_id) DO UPDATE SET
+            timestamp = excluded.timestamp,
+            author = excluded.author,
+            hash = excluded.hash,
+            mime = excluded.mime,
+            size = excluded.size,
+            encoding = excluded.encoding,
+            head = excluded.head
+        "#,
+    )
+    .bind(&commit.id)
+    .bind(commit.timestamp.timestamp())
+    .bind(&commit.author)
+    .bind(&commit.hash)
+    .bind(&commit.mime)
+    .bind(commit.size as i64)
+    .bind(commit.encoding.map(TextCodec::name))
+    .bind(commit.is_head)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("DELETE FROM commit_parents WHERE child = ?")
+        .bind(&commit.id)
+        .execute(&mut *conn)
+        .await?;
+
+    for (ordinal, parent) in commit.parents.iter().enumerate() {
+        sqlx::query("INSERT INTO commit_parents (child, parent, ordinal) VALUES (?, ?, ?)")
+            .bind(&commit.id)
+            .bind(parent)
+            .bind(ordinal as i64)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Write the chunk manifest of a commit
+async fn insert_chunks(conn: &mut SqliteConnection, commit_id: &TweetId, chunks: &[ChunkRef]) -> Result<()> {
+    for chunk in chunks {
+        sqlx::query(
+            r#"
+            INSERT INTO chunks (tweet_id, parent_commit, idx, size, hash)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(tweet_id) DO UPDATE SET
+                parent_commit = excluded.parent_commit,
+                idx = excluded.idx,
+                size = excluded.size,
+                hash = excluded.hash
+            "#,
+        )
+        .bind(&chunk.id)
+        .bind(commit_id)
+        .bind(chunk.index as i64)
+        .bind(chunk.size as i64)
+        .bind(&chunk.hash)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Flag a commit as head and clear the flag on its parents
+async fn mark_head(conn: &mut SqliteConnection, id: &TweetId) -> Result<()> {
+    sqlx::query(
+        r#"
+        UPDATE commits
+        SET head = 0
+        WHERE tweet_id IN (SELECT parent FROM commit_parents WHERE child = ?)
+        "#,
+    )
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE commits
+        SET head = 1
+        WHERE tweet_id = ?
+        "#,
+    )
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Point a file's ref at a head commit
+async fn set_ref(conn: &mut SqliteConnection, path: &str, head: &TweetId) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO refs (path, head)
+        VALUES (?, ?)
+        ON CONFLICT(path) DO UPDATE SET
+            head = excluded.head
+        "#,
+    )
+    .bind(path)
+    .bind(head)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Build a commit from a `commits` row and its parents
+fn commit_from_row(row: &SqliteRow, parents: Vec<TweetId>) -> Result<Commit> {
+    let timestamp_secs: i64 = row.try_get("timestamp")?;
+
+    Ok(Commit {
+        id: row.try_get("tweet_id")?,
+        parents,
+        timestamp: DateTime::from_timestamp(timestamp_secs, 0).unwrap_or_else(Utc::now),
+        hash: row.try_get("hash")?,
+        author: row.try_get("author")?,
+        mime: row.try_get("mime")?,
+        size: row.try_get::<i64, _>("size")? as usize,
+        encoding: match row.try_get::<Option<String>, _>("encoding")? {
+            Some(name) => Some(TextCodec::from_name(&name).ok_or_else(|| {
+                XFilesError::InvalidEncoding(format!("Unknown text codec {}", name))
+            })?),
+            None => None,
+        },
+        is_head: row.try_get("head")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn store() -> SqliteStore {
+        let store = SqliteStore::new("sqlite://:memory:").await.unwrap();
+        store.init_schema().await.unwrap();
+        store
+    }
+
+    fn commit(id: &str, parents: &[&str]) -> Commit {
+        Commit::new(
+            id.to_string(),
+            parents.iter().map(|p| p.to_string()).collect(),
+            "author".to_string(),
+            String::new(),
+            "text/plain".to_string(),
+            0,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_children_by_exact_parent() {
+        let store = store().await;
+        store.store_commit(&commit("1", &[])).await.unwrap();
+        store.store_commit(&commit("11", &[])).await.unwrap();
+        store.store_commit(&commit("2", &["11"])).await.unwrap();
+        store.store_commit(&commit("3", &["1"])).await.unwrap();
+
+        let children = store.get_children(&"1".to_string()).await.unwrap();
+        let ids: Vec<_> = children.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, vec!["3"]);
+    }
+
+    #[tokio::test]
+    async fn test_merge_parent_order() {
+        let store = store().await;
+        for c in [commit("r", &[]), commit("b", &["r"]), commit("a", &["r"])] {
+            store.store_commit(&c).await.unwrap();
+        }
+        store.store_commit(&commit("m", &["b", "a"])).await.unwrap();
+
+        let merge = store.get_commit(&"m".to_string()).await.unwrap().unwrap();
+        assert_eq!(merge.parents, vec!["b".to_string(), "a".to_string()]);
+        assert_eq!(store.get_children(&"a".to_string()).await.unwrap()[0].id, "m");
+        assert_eq!(store.get_children(&"b".to_string()).await.unwrap()[0].id, "m");
+
+        // Re-storing a commit replaces its edges
+        store.store_commit(&commit("m", &["a"])).await.unwrap();
+        assert!(store.get_children(&"b".to_string()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_blocks_keep_first_tweet() {
+        let store = store().await;
+        assert_eq!(store.get_block("h").await.unwrap(), None);
+
+        store.store_block("h", &"10".to_string(), 128).await.unwrap();
+        store.store_block("h", &"20".to_string(), 128).await.unwrap();
+        assert_eq!(store.get_block("h").await.unwrap(), Some("10".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_parent_column() {
+        let store = SqliteStore::new("sqlite://:memory:").await.unwrap();
+        sqlx::query(
+            r#"
+            CREATE TABLE commits (
+                tweet_id TEXT PRIMARY KEY,
+                parent_id TEXT,
+                timestamp INTEGER NOT NULL,
+                author TEXT NOT NULL,
+                hash TEXT NOT NULL,
+                mime TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                head BOOLEAN DEFAULT 0
+            )
+            "#,
+        )
+        .execute(&store.pool)
+        .await
+        .unwrap();
+        sqlx::query("CREATE INDEX idx_parent ON commits(parent_id)")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO commits (tweet_id, parent_id, timestamp, author, hash, mime, size)
+            VALUES ('r', '[]', 0, 'a', '', 'text/plain', 0),
+                   ('x', '["r"]', 1, 'a', '', 'text/plain', 0),
+                   ('y', '["r"]', 2, 'a', '', 'text/plain', 0),
+                   ('m', '["y","x"]', 3, 'a', '', 'text/plain', 0)
+            "#,
+        )
+        .execute(&store.pool)
+        .await
+        .unwrap();
+
+        store.init_schema().await.unwrap();
+
+        let merge = store.get_commit(&"m".to_string()).await.unwrap().unwrap();
+        assert_eq!(merge.parents, vec!["y".to_string(), "x".to_string()]);
+        assert_eq!(store.get_children(&"r".to_string()).await.unwrap().len(), 2);
+
+        // Running the schema setup again is a no-op
+        store.init_schema().await.unwrap();
+        assert_eq!(store.list_commits().await.unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,272 @@
+//! Text codecs for posting binary content as tweet text
+//!
+//! Tweets can only hold text, so content that is not valid UTF-8 (or not
+//! text at all) is encoded with one of these codecs before posting. All
+//! alphabets avoid characters Twitter rewrites or links: `&`, `<` and `>`
+//! are HTML-escaped, `@` starts a mention and `.` can turn a run of
+//! characters into a t.co link.
+
+use crate::error::{Result, XFilesError};
+use crate::util::encoding::{decode_base64, encode_base64};
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+/// A codec turning arbitrary bytes into tweet-safe text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TextCodec {
+    /// URL-safe base64, 6 bits per ASCII character
+    Base64,
+    /// Base85 over printable ASCII, 6.4 bits per character
+    Base85,
+    /// 11 bits per character, using letters Twitter counts as a single
+    /// character each
+    Base2048,
+}
+
+impl TextCodec {
+    /// Name recorded in commit metadata
+    pub fn name(self) -> &'static str {
+        match self {
+            TextCodec::Base64 => "base64",
+            TextCodec::Base85 => "base85",
+            TextCodec::Base2048 => "base2048",
+        }
+    }
+
+    /// Look up a codec by its recorded name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "base64" => Some(TextCodec::Base64),
+            "base85" => Some(TextCodec::Base85),
+            "base2048" => Some(TextCodec::Base2048),
+            _ => None,
+        }
+    }
+
+    /// Encode bytes as text
+    pub fn encode(self, data: &[u8]) -> String {
+        match self {
+            TextCodec::Base64 => encode_base64(data),
+            TextCodec::Base85 => encode_base85(data),
+            TextCodec::Base2048 => encode_base2048(data),
+        }
+    }
+
+    /// Decode text produced by [`TextCodec::encode`]
+    pub fn decode(self, text: &str) -> Result<Vec<u8>> {
+        match self {
+            TextCodec::Base64 => decode_base64(text),
+            TextCodec::Base85 => decode_base85(text),
+            TextCodec::Base2048 => decode_base2048(text),
+        }
+    }
+}
+
+/// Base85 alphabet: printable ASCII without `"&'.<>@\` and backtick
+const BASE85_ALPHABET: &[u8; 85] =
+    b"!#$%()*+,-/0123456789:;=?ABCDEFGHIJKLMNOPQRSTUVWXYZ[]^_abcdefghijklmnopqrstuvwxyz{|}~";
+
+/// Encode bytes as base85
+///
+/// Every 4 bytes become 5 characters; a final group of `n` bytes becomes
+/// `n + 1` characters.
+fn encode_base85(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(4) * 5);
+
+    for group in data.chunks(4) {
+        let mut padded = [0u8; 4];
+        padded[..group.len()].copy_from_slice(group);
+        let mut value = u32::from_be_bytes(padded);
+
+        let mut digits = [0u8; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = BASE85_ALPHABET[(value % 85) as usize];
+            value /= 85;
+        }
+        for &digit in &digits[..group.len() + 1] {
+            encoded.push(digit as char);
+        }
+    }
+
+    encoded
+}
+
+/// Decode base85 text
+fn decode_base85(text: &str) -> Result<Vec<u8>> {
+    let invalid = || XFilesError::InvalidEncoding("Invalid base85 text".to_string());
+    if text.len() % 5 == 1 {
+        return Err(invalid());
+    }
+
+    let mut decoded = Vec::with_capacity(text.len() / 5 * 4 + 3);
+    for group in text.as_bytes().chunks(5) {
+        // Pad short groups with the highest digit so truncation rounds back
+        let mut value: u64 = 0;
+        for i in 0..5 {
+            let digit = match group.get(i) {
+                Some(c) => BASE85_ALPHABET.iter().position(|a| a == c).ok_or_else(invalid)?,
+                None => 84,
+            };
+            value = value * 85 + digit as u64;
+        }
+        let value = u32::try_from(value).map_err(|_| invalid())?;
+        decoded.extend_from_slice(&value.to_be_bytes()[..group.len() - 1]);
+    }
+
+    Ok(decoded)
+}
+
+/// Code point ranges of the base2048 alphabet, in ascending order
+///
+/// These are the first 2048 letters (general category `Lu`, `Ll` or `Lo`)
+/// below U+1100 that are unchanged by NFC normalization, alone or next to
+/// each other. Twitter counts everything below U+1100 as one character.
+const BASE2048_RANGES: &[(u32, u32)] = &[
+    (0x0041, 0x005A), (0x0061, 0x007A), (0x00AA, 0x00AA), (0x00B5, 0x00B5), (0x00BA, 0x00BA),
+    (0x00C0, 0x00D6), (0x00D8, 0x00F6), (0x00F8, 0x01C4), (0x01C6, 0x01C7), (0x01C9, 0x01CA),
+    (0x01CC, 0x01F1), (0x01F3, 0x02AF), (0x0370, 0x0373), (0x0376, 0x0377), (0x037B, 0x037D),
+    (0x037F, 0x037F), (0x0386, 0x0386), (0x0388, 0x038A), (0x038C, 0x038C), (0x038E, 0x03A1),
+    (0x03A3, 0x03F5), (0x03F7, 0x0481), (0x048A, 0x052F), (0x0531, 0x0556), (0x0560, 0x0588),
+    (0x05D0, 0x05EA), (0x05EF, 0x05F2), (0x0620, 0x063F), (0x0641, 0x064A), (0x066E, 0x066F),
+    (0x0671, 0x06D3), (0x06D5, 0x06D5), (0x06EE, 0x06EF), (0x06FA, 0x06FC), (0x06FF, 0x06FF),
+    (0x0710, 0x0710), (0x0712, 0x072F), (0x074D, 0x07A5), (0x07B1, 0x07B1), (0x07CA, 0x07EA),
+    (0x0800, 0x0815), (0x0840, 0x0858), (0x0860, 0x086A), (0x0870, 0x0887), (0x0889, 0x088E),
+    (0x08A0, 0x08C8), (0x0904, 0x0939), (0x093D, 0x093D), (0x0950, 0x0950), (0x0960, 0x0961),
+    (0x0972, 0x0980), (0x0985, 0x098C), (0x098F, 0x0990), (0x0993, 0x09A8), (0x09AA, 0x09B0),
+    (0x09B2, 0x09B2), (0x09B6, 0x09B9), (0x09BD, 0x09BD), (0x09CE, 0x09CE), (0x09E0, 0x09E1),
+    (0x09F0, 0x09F1), (0x09FC, 0x09FC), (0x0A05, 0x0A0A), (0x0A0F, 0x0A10), (0x0A13, 0x0A28),
+    (0x0A2A, 0x0A30), (0x0A32, 0x0A32), (0x0A35, 0x0A35), (0x0A38, 0x0A39), (0x0A5C, 0x0A5C),
+    (0x0A72, 0x0A74), (0x0A85, 0x0A8D), (0x0A8F, 0x0A91), (0x0A93, 0x0AA8), (0x0AAA, 0x0AB0),
+    (0x0AB2, 0x0AB3), (0x0AB5, 0x0AB9), (0x0ABD, 0x0ABD), (0x0AD0, 0x0AD0), (0x0AE0, 0x0AE1),
+    (0x0AF9, 0x0AF9), (0x0B05, 0x0B0C), (0x0B0F, 0x0B10), (0x0B13, 0x0B28), (0x0B2A, 0x0B30),
+    (0x0B32, 0x0B33), (0x0B35, 0x0B39), (0x0B3D, 0x0B3D), (0x0B5F, 0x0B61), (0x0B71, 0x0B71),
+    (0x0B83, 0x0B83), (0x0B85, 0x0B8A), (0x0B8E, 0x0B90), (0x0B92, 0x0B95), (0x0B99, 0x0B9A),
+    (0x0B9C, 0x0B9C), (0x0B9E, 0x0B9F), (0x0BA3, 0x0BA4), (0x0BA8, 0x0BAA), (0x0BAE, 0x0BB9),
+    (0x0BD0, 0x0BD0), (0x0C05, 0x0C0C), (0x0C0E, 0x0C10), (0x0C12, 0x0C28), (0x0C2A, 0x0C39),
+    (0x0C3D, 0x0C3D), (0x0C58, 0x0C5A), (0x0C5D, 0x0C5D), (0x0C60, 0x0C61), (0x0C80, 0x0C80),
+    (0x0C85, 0x0C8C), (0x0C8E, 0x0C90), (0x0C92, 0x0CA8), (0x0CAA, 0x0CB3), (0x0CB5, 0x0CB9),
+    (0x0CBD, 0x0CBD), (0x0CDD, 0x0CDE), (0x0CE0, 0x0CE1), (0x0CF1, 0x0CF2), (0x0D04, 0x0D0C),
+    (0x0D0E, 0x0D10), (0x0D12, 0x0D3A), (0x0D3D, 0x0D3D), (0x0D4E, 0x0D4E), (0x0D54, 0x0D56),
+    (0x0D5F, 0x0D61), (0x0D7A, 0x0D7F), (0x0D85, 0x0D96), (0x0D9A, 0x0DB1), (0x0DB3, 0x0DB6),
+];
+
+/// Characters carrying the last 1 to 3 bits of base2048 text
+const BASE2048_TAIL: &[u8; 8] = b"01234567";
+
+/// The base2048 alphabet, indexed by 11-bit value
+fn base2048_alphabet() -> &'static [char] {
+    static ALPHABET: OnceLock<Vec<char>> = OnceLock::new();
+    ALPHABET.get_or_init(|| {
+        BASE2048_RANGES
+            .iter()
+            .flat_map(|&(start, end)| start..=end)
+            .filter_map(char::from_u32)
+            .collect()
+    })
+}
+
+/// Encode bytes as base2048
+///
+/// Each character holds 11 bits. Leftover bits are written as one more
+/// alphabet character when there are 4 to 10 of them, or as a tail
+/// character when there are 1 to 3, so the decoder can tell how many
+/// bytes the text holds.
+fn encode_base2048(data: &[u8]) -> String {
+    let alphabet = base2048_alphabet();
+    let mut encoded = String::with_capacity(data.len() * 2);
+    let mut bits: u32 = 0;
+    let mut count = 0;
+
+    for &byte in data {
+        bits = (bits << 8) | byte as u32;
+        count += 8;
+        if count >= 11 {
+            count -= 11;
+            encoded.push(alphabet[((bits >> count) & 0x7ff) as usize]);
+        }
+    }
+
+    if count > 3 {
+        encoded.push(alphabet[((bits << (11 - count)) & 0x7ff) as usize]);
+    } else if count > 0 {
+        encoded.push(BASE2048_TAIL[((bits << (3 - count)) & 0x7) as usize] as char);
+    }
+
+    encoded
+}
+
+/// Decode base2048 text
+fn decode_base2048(text: &str) -> Result<Vec<u8>> {
+    let invalid = |c: char| XFilesError::InvalidEncoding(format!("Invalid base2048 character {:?}", c));
+    let alphabet = base2048_alphabet();
+    let mut decoded = Vec::with_capacity(text.len() * 11 / 16);
+    let mut bits: u32 = 0;
+    let mut count = 0;
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let (value, width) = match alphabet.binary_search(&c) {
+            Ok(value) => (value as u32, 11),
+            Err(_) => match BASE2048_TAIL.iter().position(|&t| t as char == c) {
+                Some(value) if chars.peek().is_none() => (value as u32, 3),
+                _ => return Err(invalid(c)),
+            },
+        };
+
+        bits = (bits << width) | value;
+        count += width;
+        while count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+        bits &= (1 << count) - 1;
+    }
+
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODECS: [TextCodec; 3] = [TextCodec::Base64, TextCodec::Base85, TextCodec::Base2048];
+
+    #[test]
+    fn test_roundtrip() {
+        for codec in CODECS {
+            for len in 0..40 {
+                let data: Vec<u8> = (0..len).map(|i| (i * 151 + 7) as u8).collect();
+                let text = codec.encode(&data);
+                assert_eq!(codec.decode(&text).unwrap(), data, "{:?} with {} bytes", codec, len);
+            }
+            assert_eq!(TextCodec::from_name(codec.name()), Some(codec));
+        }
+    }
+
+    #[test]
+    fn test_twitter_safe_output() {
+        let data: Vec<u8> = (0..=255).collect();
+        for codec in CODECS {
+            let text = codec.encode(&data);
+            assert!(!text.contains(['&', '<', '>', '@', '.', ' ', '\n']), "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn test_base2048_alphabet() {
+        let alphabet = base2048_alphabet();
+        assert_eq!(alphabet.len(), 2048);
+        assert!(alphabet.windows(2).all(|w| w[0] < w[1]));
+        assert!(alphabet.iter().all(|&c| (c as u32) < 0x1100 && c.is_alphabetic()));
+
+        // 11 bits per character
+        assert_eq!(TextCodec::Base2048.encode(&[0xff; 11]).chars().count(), 8);
+    }
+
+    #[test]
+    fn test_rejects_invalid_text() {
+        assert!(TextCodec::Base85.decode("ab\"c").is_err());
+        assert!(TextCodec::Base2048.decode("a0b").is_err());
+        assert!(TextCodec::Base2048.decode("a!").is_err());
+    }
+}
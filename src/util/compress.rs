@@ -0,0 +1,122 @@
+//! Payload compression
+//!
+//! Compressed payloads are binary, so they are always posted through a
+//! [`TextCodec`](crate::util::codec::TextCodec). The algorithm is recorded
+//! in the envelope (`z=<name>`) and undone on read.
+
+use crate::error::{Result, XFilesError};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// A compression algorithm for commit payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Compression {
+    /// Zstandard at a high level
+    Zstd,
+    /// Raw deflate (RFC 1951)
+    Deflate,
+    /// Brotli, which does best on short text thanks to its built-in dictionary
+    Brotli,
+}
+
+impl Compression {
+    /// Name recorded in the envelope
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Deflate => "deflate",
+            Compression::Brotli => "brotli",
+        }
+    }
+
+    /// Look up an algorithm by its recorded name
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zstd" => Some(Compression::Zstd),
+            "deflate" => Some(Compression::Deflate),
+            "brotli" => Some(Compression::Brotli),
+            _ => None,
+        }
+    }
+
+    /// Compress a payload
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Zstd => Ok(zstd::encode_all(data, 19)?),
+            Compression::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compression::Brotli => {
+                let mut out = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut out, 4096, 11, 22);
+                    encoder.write_all(data)?;
+                }
+                Ok(out)
+            }
+        }
+    }
+
+    /// Decompress a payload of at most `limit` bytes
+    ///
+    /// Output beyond `limit` is treated as corrupt data, so a hostile tweet
+    /// cannot expand into an arbitrarily large buffer.
+    pub fn decompress(self, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+        let invalid = |e: std::io::Error| {
+            XFilesError::InvalidEncoding(format!("Invalid {} data: {}", self.name(), e))
+        };
+
+        let reader: Box<dyn Read + '_> = match self {
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(data).map_err(invalid)?),
+            Compression::Deflate => Box::new(flate2::read::DeflateDecoder::new(data)),
+            Compression::Brotli => Box::new(brotli::Decompressor::new(data, 4096)),
+        };
+
+        let mut out = Vec::new();
+        reader.take(limit as u64 + 1).read_to_end(&mut out).map_err(invalid)?;
+        if out.len() > limit {
+            return Err(XFilesError::InvalidEncoding(format!(
+                "{} data expands beyond {} bytes",
+                self.name(),
+                limit
+            )));
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Compression; 3] = [Compression::Zstd, Compression::Deflate, Compression::Brotli];
+
+    #[test]
+    fn test_round_trip() {
+        let data = br#"{"goal":"explore","steps":["a","b","c"],"mood":"curious"}"#.repeat(20);
+        for compression in ALL {
+            let compressed = compression.compress(&data).unwrap();
+            assert!(compressed.len() < data.len() / 4, "{}", compression.name());
+            assert_eq!(compression.decompress(&compressed, data.len()).unwrap(), data);
+            assert_eq!(Compression::from_name(compression.name()), Some(compression));
+
+            // Empty payloads survive too
+            let empty = compression.compress(b"").unwrap();
+            assert!(compression.decompress(&empty, 0).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        let data = vec![b'a'; 10_000];
+        for compression in ALL {
+            let compressed = compression.compress(&data).unwrap();
+            assert!(compression.decompress(&compressed, 9_999).is_err());
+            assert!(compression.decompress(b"not compressed at all", 100).is_err());
+        }
+    }
+}
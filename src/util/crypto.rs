@@ -0,0 +1,193 @@
+//! End-to-end encryption of commit payloads
+//!
+//! Every file gets its own keys, derived from a master key and the file
+//! path with blake3's key derivation: one for XChaCha20-Poly1305 and one
+//! for keyed content hashes. Encrypted commits are hashed with the keyed
+//! hash, so key holders can still verify them while nobody else can
+//! confirm a guess of the content.
+//!
+//! Master keys carry an ID that is recorded in the envelope (`k=<id>`), so
+//! commits written before a key rotation stay readable.
+
+use crate::dag::commit::Hash;
+use crate::error::{Result, XFilesError};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::collections::BTreeMap;
+
+/// Size of master and derived keys, in bytes
+pub const KEY_SIZE: usize = 32;
+
+/// Size of the random nonce prepended to each ciphertext
+const NONCE_SIZE: usize = 24;
+
+/// Key derivation context of per-file encryption keys
+const CIPHER_CONTEXT: &str = "xfiles 2026-10 per-file XChaCha20-Poly1305 key";
+
+/// Key derivation context of per-file hash keys
+const HASH_CONTEXT: &str = "xfiles 2026-10 per-file keyed content hash";
+
+/// Master keys by ID, with the one new commits are encrypted under
+#[derive(Clone)]
+pub struct Keyring {
+    keys: BTreeMap<u32, [u8; KEY_SIZE]>,
+    current: u32,
+}
+
+impl Keyring {
+    /// Create a keyring holding a single master key
+    pub fn new(id: u32, master: [u8; KEY_SIZE]) -> Self {
+        Self {
+            keys: BTreeMap::from([(id, master)]),
+            current: id,
+        }
+    }
+
+    /// Add an older master key, kept for reading commits written with it
+    pub fn add(&mut self, id: u32, master: [u8; KEY_SIZE]) {
+        self.keys.insert(id, master);
+    }
+
+    /// Add a master key and encrypt new commits with it from now on
+    pub fn rotate(&mut self, id: u32, master: [u8; KEY_SIZE]) {
+        self.keys.insert(id, master);
+        self.current = id;
+    }
+
+    /// ID of the master key new commits are encrypted under
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    /// Keys of a file under the current master key
+    pub fn current_key(&self, path: &str) -> FileKey {
+        self.file_key(self.current, path)
+            .expect("the current master key is always in the keyring")
+    }
+
+    /// Keys of a file under master key `id`
+    pub fn file_key(&self, id: u32, path: &str) -> Result<FileKey> {
+        let master = self
+            .keys
+            .get(&id)
+            .ok_or_else(|| XFilesError::Decryption(format!("Unknown master key {}", id)))?;
+
+        let mut material = master.to_vec();
+        material.extend_from_slice(path.as_bytes());
+        Ok(FileKey {
+            id,
+            cipher: blake3::derive_key(CIPHER_CONTEXT, &material),
+            hash: blake3::derive_key(HASH_CONTEXT, &material),
+        })
+    }
+}
+
+impl std::fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print key material
+        f.debug_struct("Keyring")
+            .field("ids", &self.keys.keys().collect::<Vec<_>>())
+            .field("current", &self.current)
+            .finish()
+    }
+}
+
+/// Keys of one file under one master key
+pub struct FileKey {
+    id: u32,
+    cipher: [u8; KEY_SIZE],
+    hash: [u8; KEY_SIZE],
+}
+
+impl FileKey {
+    /// ID of the master key these keys derive from
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Keyed blake3 hash of content, as hex
+    pub fn hash(&self, content: &[u8]) -> Hash {
+        blake3::keyed_hash(&self.hash, content).to_hex().to_string()
+    }
+
+    /// Encrypt a payload, authenticating `aad` along with it
+    ///
+    /// Returns the random nonce followed by the ciphertext and tag.
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let cipher = XChaCha20Poly1305::new(&self.cipher.into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad })
+            .map_err(|_| XFilesError::Other("Encryption failed".to_string()))?;
+
+        let mut out = nonce.to_vec();
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    /// Decrypt a payload produced by [`FileKey::encrypt`]
+    pub fn decrypt(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if data.len() < NONCE_SIZE {
+            return Err(XFilesError::Decryption("Ciphertext is truncated".to_string()));
+        }
+
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        XChaCha20Poly1305::new(&self.cipher.into())
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| {
+                XFilesError::Decryption(format!(
+                    "Authentication failed with master key {}",
+                    self.id
+                ))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let keyring = Keyring::new(1, [7; KEY_SIZE]);
+        let key = keyring.current_key("notes/private.md");
+
+        let sealed = key.encrypt(b"secret plan", b"aad").unwrap();
+        assert_eq!(key.decrypt(&sealed, b"aad").unwrap(), b"secret plan");
+
+        // Tampering or the wrong context is detected
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(key.decrypt(&tampered, b"aad"), Err(XFilesError::Decryption(_))));
+        assert!(matches!(key.decrypt(&sealed, b"other"), Err(XFilesError::Decryption(_))));
+        assert!(key.decrypt(&sealed[..10], b"aad").is_err());
+    }
+
+    #[test]
+    fn test_keys_are_per_path_and_master() {
+        let mut keyring = Keyring::new(1, [7; KEY_SIZE]);
+        let a = keyring.current_key("a.txt");
+        let b = keyring.current_key("b.txt");
+        assert_ne!(a.hash(b"x"), b.hash(b"x"));
+        assert_ne!(a.hash(b"x"), crate::util::hash::compute_hash(b"x"));
+
+        let sealed = a.encrypt(b"x", b"").unwrap();
+        assert!(b.decrypt(&sealed, b"").is_err());
+
+        // Rotation keeps old keys readable
+        keyring.rotate(2, [9; KEY_SIZE]);
+        assert_eq!(keyring.current(), 2);
+        let old = keyring.file_key(1, "a.txt").unwrap();
+        assert_eq!(old.decrypt(&sealed, b"").unwrap(), b"x");
+        assert_ne!(keyring.current_key("a.txt").hash(b"x"), a.hash(b"x"));
+        assert!(keyring.file_key(3, "a.txt").is_err());
+    }
+
+    #[test]
+    fn test_debug_hides_keys() {
+        let keyring = Keyring::new(1, [0xAB; KEY_SIZE]);
+        let debug = format!("{:?}", keyring);
+        assert!(!debug.contains("171"));
+        assert!(debug.contains("current: 1"));
+    }
+}
@@ -1,10 +1,35 @@
 //! Content encoding and compression utilities
 
-use crate::error::Result;
+use crate::error::{Result, XFilesError};
+use crate::util::codec::TextCodec;
+use crate::util::compress::Compression;
+use crate::util::sign::SIGNATURE_SIZE;
 use serde::{Deserialize, Serialize};
 
+/// Version of the on-wire envelope written by this build
+pub const ENVELOPE_VERSION: u8 = 1;
+
+/// Magic prefix of an envelope header, followed by the format version
+const ENVELOPE_MAGIC: &str = "xf";
+
 /// Metadata header for encoded content
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Every tweet of a commit starts with this header on its own line:
+///
+/// ```text
+/// xf1 <index>/<count> <size> <hash> [z=<algorithm>] [k=<key>] [e=<codec>] [b=1] [p=<id>,...] [s=<signature>] [n=<nonce>] <mime>
+/// ```
+///
+/// The hash is the blake3 hash of the full content, written as unpadded
+/// URL-safe base64. `z` names the [`Compression`] applied to the payload,
+/// `k` the master key it is encrypted under (the hash is then keyed, see
+/// [`crate::util::crypto`]) and `e` the [`TextCodec`] the chunk body is
+/// encoded with. `b` marks a payload that lists block tweets instead of holding the
+/// content itself. Extra parents and the commit's Ed25519 signature (see
+/// [`crate::util::sign`]) are only written on the first chunk. `n` only
+/// tells a tweet apart from an identical recent one, which Twitter would
+/// reject as duplicate content (see [`with_nonce`]).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ContentHeader {
     /// MIME type
     pub mime: String,
@@ -12,68 +37,487 @@ pub struct ContentHeader {
     pub size: usize,
     /// Content hash
     pub hash: String,
-    /// Whether content is compressed
-    pub compressed: bool,
+    /// Compression applied to the payload, if any
+    pub compression: Option<Compression>,
+    /// ID of the master key the payload is encrypted under, if any
+    pub key: Option<u32>,
     /// Encoding version
     pub version: u8,
+    /// Position of this chunk in the commit payload
+    pub index: usize,
+    /// Number of chunks in the commit payload
+    pub count: usize,
+    /// Text codec of the chunk bodies, `None` for plain text
+    pub encoding: Option<TextCodec>,
+    /// Whether the payload is a block list (see [`BLOCK_MIME`])
+    pub blocks: bool,
+    /// Parents besides the tweet being replied to (merge commits)
+    pub parents: Vec<String>,
+    /// Signature of the commit by one of the file's writers
+    pub signature: Option<Vec<u8>>,
+    /// Marker telling the tweet apart from an identical one
+    pub nonce: Option<u32>,
 }
 
-/// Encode content with metadata header
-pub fn encode_with_header(content: &[u8], mime: &str) -> Result<Vec<u8>> {
-    let hash = crate::util::hash::compute_hash(content);
-
-    let header = ContentHeader {
-        mime: mime.to_string(),
-        size: content.len(),
-        hash,
-        compressed: false,
-        version: 1,
-    };
+impl ContentHeader {
+    /// Create a header for single-chunk content
+    pub fn new(mime: &str, size: usize, hash: String) -> Self {
+        Self {
+            mime: mime.to_string(),
+            size,
+            hash,
+            compression: None,
+            key: None,
+            version: ENVELOPE_VERSION,
+            index: 0,
+            count: 1,
+            encoding: None,
+            blocks: false,
+            parents: Vec::new(),
+            signature: None,
+            nonce: None,
+        }
+    }
+
+    /// Header for chunk `index` of `count`
+    pub fn for_chunk(&self, index: usize, count: usize) -> Self {
+        Self {
+            index,
+            count,
+            parents: if index == 0 { self.parents.clone() } else { Vec::new() },
+            signature: if index == 0 { self.signature.clone() } else { None },
+            ..self.clone()
+        }
+    }
+
+    /// Render the header line, including its trailing newline
+    pub fn encode(&self) -> Result<String> {
+        let digest = blake3::Hash::from_hex(&self.hash)
+            .map_err(|e| XFilesError::InvalidEncoding(format!("Invalid content hash: {}", e)))?;
+
+        let mut line = format!(
+            "{}{} {}/{} {} {}",
+            ENVELOPE_MAGIC,
+            self.version,
+            self.index,
+            self.count,
+            self.size,
+            encode_base64(digest.as_bytes())
+        );
+        if let Some(compression) = self.compression {
+            line.push_str(" z=");
+            line.push_str(compression.name());
+        }
+        if let Some(key) = self.key {
+            line.push_str(&format!(" k={}", key));
+        }
+        if let Some(codec) = self.encoding {
+            line.push_str(" e=");
+            line.push_str(codec.name());
+        }
+        if self.blocks {
+            line.push_str(" b=1");
+        }
+        if !self.parents.is_empty() {
+            line.push_str(" p=");
+            line.push_str(&self.parents.join(","));
+        }
+        if let Some(signature) = &self.signature {
+            line.push_str(" s=");
+            line.push_str(&encode_base64(signature));
+        }
+        if let Some(nonce) = self.nonce {
+            line.push_str(&format!(" n={}", nonce));
+        }
+        line.push(' ');
+        line.push_str(&self.mime);
+        line.push('\n');
+
+        Ok(line)
+    }
 
-    let header_json = serde_json::to_string(&header)?;
-    let separator = b"\n---\n";
+    /// Parse a header line (without its newline)
+    ///
+    /// Returns `None` if the line is not an envelope header at all, and an
+    /// error if it is one but is malformed or from a newer format version.
+    pub fn decode(line: &str) -> Result<Option<Self>> {
+        let mut tokens = line.split(' ');
 
-    let mut encoded = Vec::new();
-    encoded.extend_from_slice(header_json.as_bytes());
-    encoded.extend_from_slice(separator);
-    encoded.extend_from_slice(content);
+        let Some(version) = tokens
+            .next()
+            .and_then(|t| t.strip_prefix(ENVELOPE_MAGIC))
+            .and_then(|v| v.parse::<u8>().ok())
+        else {
+            return Ok(None);
+        };
+        let Some((index, count)) = tokens
+            .next()
+            .and_then(|t| t.split_once('/'))
+            .and_then(|(i, c)| Some((i.parse::<usize>().ok()?, c.parse::<usize>().ok()?)))
+        else {
+            return Ok(None);
+        };
+
+        let invalid = |what: &str| XFilesError::InvalidEncoding(format!("Invalid envelope {}", what));
+        if version == 0 || version > ENVELOPE_VERSION {
+            return Err(XFilesError::InvalidEncoding(format!(
+                "Unsupported envelope version {}",
+                version
+            )));
+        }
+        if index >= count {
+            return Err(invalid("chunk position"));
+        }
+
+        let size = tokens
+            .next()
+            .and_then(|t| t.parse().ok())
+            .ok_or_else(|| invalid("size"))?;
+        let hash = tokens
+            .next()
+            .and_then(|t| decode_base64(t).ok())
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .map(|bytes| blake3::Hash::from_bytes(bytes).to_hex().to_string())
+            .ok_or_else(|| invalid("hash"))?;
+
+        let mut header = Self {
+            mime: String::new(),
+            size,
+            hash,
+            compression: None,
+            key: None,
+            version,
+            index,
+            count,
+            encoding: None,
+            blocks: false,
+            parents: Vec::new(),
+            signature: None,
+            nonce: None,
+        };
+
+        // Optional `k=v` fields come before the MIME type
+        let mut rest: Vec<&str> = tokens.collect();
+        while let Some(token) = rest.first() {
+            match token.split_once('=') {
+                Some(("z", value)) => {
+                    header.compression =
+                        Some(Compression::from_name(value).ok_or_else(|| invalid("compression"))?)
+                }
+                Some(("e", value)) => {
+                    header.encoding =
+                        Some(TextCodec::from_name(value).ok_or_else(|| invalid("codec"))?)
+                }
+                Some(("k", value)) => {
+                    header.key = Some(value.parse().map_err(|_| invalid("key ID"))?)
+                }
+                Some(("b", value)) => header.blocks = value == "1",
+                Some(("p", value)) => {
+                    header.parents = value.split(',').map(str::to_string).collect()
+                }
+                Some(("s", value)) => {
+                    header.signature = Some(
+                        decode_base64(value)
+                            .ok()
+                            .filter(|s| s.len() == SIGNATURE_SIZE)
+                            .ok_or_else(|| invalid("signature"))?,
+                    )
+                }
+                Some(("n", value)) => {
+                    header.nonce = Some(value.parse().map_err(|_| invalid("nonce"))?)
+                }
+                Some((key, _)) if key.len() == 1 => {}
+                _ => break,
+            }
+            rest.remove(0);
+        }
+
+        header.mime = rest.join(" ");
+        if header.mime.is_empty() {
+            return Err(invalid("MIME type"));
+        }
+
+        Ok(Some(header))
+    }
+}
+
+/// Whether a MIME type describes human-readable text
+pub fn is_text_mime(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence,
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/toml"
+                | "application/yaml"
+        )
+}
 
+/// MIME type of delta commits, which store a patch against their parent
+pub const DELTA_MIME: &str = "application/x-xfiles-delta";
+
+/// MIME type of a delta commit over content of type `content_mime`
+pub fn delta_mime(content_mime: &str) -> String {
+    format!("{}; type={}", DELTA_MIME, content_mime)
+}
+
+/// Content MIME type of a delta commit, or `None` if `mime` is not a delta
+pub fn parse_delta_mime(mime: &str) -> Option<&str> {
+    let (essence, params) = mime.split_once(';').unwrap_or((mime, ""));
+    if essence.trim() != DELTA_MIME {
+        return None;
+    }
+
+    params
+        .split(';')
+        .find_map(|param| param.trim().strip_prefix("type="))
+        .or(Some("application/octet-stream"))
+}
+
+/// MIME type of the tweets holding content-defined blocks
+///
+/// A commit posted as blocks has a payload listing the tweet ID of each of
+/// its blocks, one per line, and is marked with `b=1` in its envelope.
+pub const BLOCK_MIME: &str = "application/x-xfiles-block";
+
+/// Encode content with metadata header
+///
+/// The content is run through the header's text codec, if it names one.
+pub fn encode_with_header(content: &[u8], header: &ContentHeader) -> Result<Vec<u8>> {
+    let mut encoded = header.encode()?.into_bytes();
+    match header.encoding {
+        Some(codec) => encoded.extend_from_slice(codec.encode(content).as_bytes()),
+        None => encoded.extend_from_slice(content),
+    }
     Ok(encoded)
 }
 
 /// Decode content and extract header
-pub fn decode_with_header(encoded: &[u8]) -> Result<(ContentHeader, Vec<u8>)> {
-    let separator = b"\n---\n";
+///
+/// Content without an envelope header (as written before the envelope
+/// existed) is returned unchanged with no header.
+pub fn decode_with_header(encoded: &[u8]) -> Result<(Option<ContentHeader>, Vec<u8>)> {
+    let (header, body) = split_header(encoded)?;
+    match header.as_ref().and_then(|h| h.encoding) {
+        Some(codec) => {
+            let text = std::str::from_utf8(&body).map_err(|_| {
+                XFilesError::InvalidEncoding(format!("{} body is not text", codec.name()))
+            })?;
+            Ok((header, codec.decode(text)?))
+        }
+        None => Ok((header, body)),
+    }
+}
+
+/// Largest nonce written to tell a tweet apart from identical ones
+///
+/// Chunks leave room for it in their header.
+pub const MAX_NONCE: u32 = 9;
+
+/// Re-render a tweet with `nonce` in its envelope header
+///
+/// The body is left untouched, so chunk contents and the commit hash stay
+/// the same. Fails for tweets without an envelope.
+pub fn with_nonce(tweet: &[u8], nonce: u32) -> Result<Vec<u8>> {
+    let end = tweet.iter().position(|&b| b == b'\n').unwrap_or(tweet.len());
+    let mut header = std::str::from_utf8(&tweet[..end])
+        .ok()
+        .map(ContentHeader::decode)
+        .transpose()?
+        .flatten()
+        .ok_or_else(|| XFilesError::InvalidEncoding("Tweet has no envelope".to_string()))?;
+
+    header.nonce = Some(nonce);
+    let mut marked = header.encode()?.into_bytes();
+    marked.extend_from_slice(tweet.get(end + 1..).unwrap_or_default());
+    Ok(marked)
+}
 
-    if let Some(pos) = encoded
-        .windows(separator.len())
-        .position(|window| window == separator)
-    {
-        let header_bytes = &encoded[..pos];
-        let content = &encoded[pos + separator.len()..];
+/// Separate the envelope header line from the body
+fn split_header(encoded: &[u8]) -> Result<(Option<ContentHeader>, Vec<u8>)> {
+    if !encoded.starts_with(ENVELOPE_MAGIC.as_bytes()) {
+        return Ok((None, encoded.to_vec()));
+    }
 
-        let header: ContentHeader = serde_json::from_slice(header_bytes)?;
+    let Some(end) = encoded.iter().position(|&b| b == b'\n') else {
+        return Ok((None, encoded.to_vec()));
+    };
+    let Ok(line) = std::str::from_utf8(&encoded[..end]) else {
+        return Ok((None, encoded.to_vec()));
+    };
 
-        Ok((header, content.to_vec()))
-    } else {
-        Err(crate::error::XFilesError::InvalidEncoding(
-            "Missing header separator".to_string(),
-        ))
+    match ContentHeader::decode(line)? {
+        Some(header) => Ok((Some(header), encoded[end + 1..].to_vec())),
+        None => Ok((None, encoded.to_vec())),
     }
 }
 
+/// URL-safe base64 alphabet
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Encode bytes as unpadded URL-safe base64
+pub fn encode_base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for group in data.chunks(3) {
+        let bits = group.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32) << (8 * (3 - group.len()));
+        for i in 0..=group.len() {
+            let sextet = (bits >> (18 - 6 * i)) & 0x3f;
+            encoded.push(BASE64_ALPHABET[sextet as usize] as char);
+        }
+    }
+
+    encoded
+}
+
+/// Decode unpadded URL-safe base64
+pub fn decode_base64(encoded: &str) -> Result<Vec<u8>> {
+    let invalid = || XFilesError::InvalidEncoding(format!("Invalid base64: {}", encoded));
+    if encoded.len() % 4 == 1 {
+        return Err(invalid());
+    }
+
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    for group in encoded.as_bytes().chunks(4) {
+        let mut bits = 0u32;
+        for &c in group {
+            let value = BASE64_ALPHABET.iter().position(|&a| a == c).ok_or_else(invalid)?;
+            bits = (bits << 6) | value as u32;
+        }
+        bits <<= 6 * (4 - group.len());
+        for i in 0..group.len() - 1 {
+            decoded.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+
+    Ok(decoded)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::hash::compute_hash;
 
     #[test]
     fn test_encode_decode() {
         let content = b"test content";
-        let encoded = encode_with_header(content, "text/plain").unwrap();
-        let (header, decoded) = decode_with_header(&encoded).unwrap();
+        let header = ContentHeader::new("text/plain", content.len(), compute_hash(content));
+        let encoded = encode_with_header(content, &header).unwrap();
+        let (decoded_header, decoded) = decode_with_header(&encoded).unwrap();
+
+        let decoded_header = decoded_header.unwrap();
+        assert_eq!(decoded_header.mime, "text/plain");
+        assert_eq!(decoded_header.size, content.len());
+        assert_eq!(decoded_header, header);
+        assert_eq!(decoded, content);
+    }
+
+    #[test]
+    fn test_header_fields() {
+        let mut header = ContentHeader::new(&delta_mime("text/plain"), 10, compute_hash(b"x"));
+        header.compression = Some(Compression::Brotli);
+        header.key = Some(3);
+        header.encoding = Some(TextCodec::Base85);
+        header.blocks = true;
+        header.parents = vec!["123".to_string(), "456".to_string()];
+        header.signature = Some(vec![9; SIGNATURE_SIZE]);
+        header.nonce = Some(2);
+
+        let first = header.for_chunk(0, 12);
+        let line = first.encode().unwrap();
+        assert!(line.starts_with("xf1 0/12 10 "));
+        assert_eq!(ContentHeader::decode(line.trim_end()).unwrap(), Some(first));
+
+        // Extra parents and the signature are only carried by the first chunk
+        let last = header.for_chunk(11, 12);
+        assert!(last.parents.is_empty());
+        assert!(last.signature.is_none());
+        assert_eq!(ContentHeader::decode(last.encode().unwrap().trim_end()).unwrap(), Some(last));
+    }
+
+    #[test]
+    fn test_with_nonce() {
+        let content = b"ok";
+        let header = ContentHeader::new("text/plain", content.len(), compute_hash(content));
+        let tweet = encode_with_header(content, &header).unwrap();
+
+        let marked = with_nonce(&tweet, 3).unwrap();
+        assert_ne!(marked, tweet);
+        let (decoded, body) = decode_with_header(&marked).unwrap();
+        assert_eq!(decoded.as_ref().and_then(|h| h.nonce), Some(3));
+        assert_eq!(decoded.unwrap().hash, header.hash);
+        assert_eq!(body, content);
 
-        assert_eq!(header.mime, "text/plain");
-        assert_eq!(header.size, content.len());
+        // Marking again replaces the nonce
+        assert_eq!(with_nonce(&marked, 3).unwrap(), marked);
+        assert!(with_nonce(b"no envelope", 1).is_err());
+    }
+
+    #[test]
+    fn test_encoded_body() {
+        let content = [0u8, 159, 146, 150, 255];
+        let mut header = ContentHeader::new("image/png", content.len(), compute_hash(&content));
+        header.encoding = Some(TextCodec::Base2048);
+
+        let encoded = encode_with_header(&content, &header).unwrap();
+        assert!(std::str::from_utf8(&encoded).is_ok());
+        let (decoded_header, decoded) = decode_with_header(&encoded).unwrap();
+        assert_eq!(decoded_header, Some(header));
         assert_eq!(decoded, content);
     }
+
+    #[test]
+    fn test_decode_legacy_content() {
+        for raw in [&b"plain old tweet"[..], b"xfiles rocks\nsecond line", b"xf1 is not a header\n"] {
+            let (header, content) = decode_with_header(raw).unwrap();
+            assert!(header.is_none());
+            assert_eq!(content, raw);
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_headers() {
+        let hash = encode_base64(blake3::hash(b"x").as_bytes());
+        let newer = format!("xf9 0/1 1 {} text/plain\nx", hash);
+        assert!(decode_with_header(newer.as_bytes()).is_err());
+
+        let bad_hash = "xf1 0/1 1 not-a-hash text/plain\nx";
+        assert!(decode_with_header(bad_hash.as_bytes()).is_err());
+
+        let bad_index = format!("xf1 3/2 1 {} text/plain\nx", hash);
+        assert!(decode_with_header(bad_index.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_base64_roundtrip() {
+        for len in 0..10 {
+            let data: Vec<u8> = (0..len).map(|i| (i * 37 + 250) as u8).collect();
+            assert_eq!(decode_base64(&encode_base64(&data)).unwrap(), data);
+        }
+        assert_eq!(encode_base64(b"foob"), "Zm9vYg");
+        assert!(decode_base64("Z").is_err());
+    }
+
+    #[test]
+    fn test_is_text_mime() {
+        assert!(is_text_mime("text/plain"));
+        assert!(is_text_mime("text/markdown; charset=utf-8"));
+        assert!(is_text_mime("application/json"));
+        assert!(is_text_mime("application/ld+json"));
+        assert!(!is_text_mime("application/octet-stream"));
+        assert!(!is_text_mime("image/png"));
+    }
+
+    #[test]
+    fn test_delta_mime() {
+        let mime = delta_mime("text/plain");
+        assert_eq!(parse_delta_mime(&mime), Some("text/plain"));
+        assert_eq!(parse_delta_mime("text/plain"), None);
+    }
 }
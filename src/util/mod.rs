@@ -2,6 +2,10 @@
 
 pub mod hash;
 pub mod encoding;
+pub mod codec;
+pub mod compress;
+pub mod crypto;
+pub mod sign;
 pub mod time;
 
 pub use hash::compute_hash;
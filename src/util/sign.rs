@@ -0,0 +1,185 @@
+//! Ed25519 commit signatures
+//!
+//! Anyone can reply to a root tweet, so a reply alone does not prove who
+//! wrote a commit. A file's root tweet publishes the public keys of its
+//! writers, and each commit carries an Ed25519 signature in its envelope
+//! (`s=<signature>`) made with one of them.
+//!
+//! The signature covers the tweet the commit replies to, its extra
+//! parents, and its size, hash and MIME type. The hash covers the content,
+//! so a commit whose signature verifies and whose content matches its hash
+//! is exactly what a writer posted, where they posted it.
+
+use crate::dag::commit::TweetId;
+use crate::util::encoding::{ContentHeader, decode_base64, encode_base64};
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey};
+
+/// Size of secret and public keys, in bytes
+pub const KEY_SIZE: usize = 32;
+
+/// Size of a signature, in bytes
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// Prefix of the root tweet line listing the writers' public keys
+pub const WRITERS_PREFIX: &str = "🔑 ";
+
+/// Domain separation for signed commit messages
+const SIGNING_CONTEXT: &str = "xfiles signed commit v1";
+
+/// Signs commits, and names the writers published on new root tweets
+#[derive(Clone)]
+pub struct Signer {
+    key: SigningKey,
+    writers: Vec<[u8; KEY_SIZE]>,
+}
+
+impl Signer {
+    /// Create a signer from an Ed25519 secret key
+    pub fn new(secret: [u8; KEY_SIZE]) -> Self {
+        let key = SigningKey::from_bytes(&secret);
+        Self {
+            writers: vec![key.verifying_key().to_bytes()],
+            key,
+        }
+    }
+
+    /// Also publish another writer's public key on new root tweets
+    ///
+    /// Each key takes 44 characters of the root tweet, so a file can list
+    /// about five writers.
+    pub fn add_writer(&mut self, public: [u8; KEY_SIZE]) {
+        if !self.writers.contains(&public) {
+            self.writers.push(public);
+        }
+    }
+
+    /// Public key of this signer
+    pub fn public_key(&self) -> [u8; KEY_SIZE] {
+        self.key.verifying_key().to_bytes()
+    }
+
+    /// Public keys published on new root tweets, this signer's first
+    pub fn writers(&self) -> &[[u8; KEY_SIZE]] {
+        &self.writers
+    }
+
+    /// Sign the envelope of a commit posted as a reply to `reply_to`
+    pub fn sign(&self, reply_to: &TweetId, header: &ContentHeader) -> Vec<u8> {
+        self.key.sign(&message(reply_to, header)).to_bytes().to_vec()
+    }
+}
+
+impl std::fmt::Debug for Signer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the secret key
+        f.debug_struct("Signer")
+            .field("public_key", &encode_base64(&self.public_key()))
+            .field("writers", &self.writers.len())
+            .finish()
+    }
+}
+
+/// Whether a commit envelope is signed by one of `writers`
+///
+/// Unsigned envelopes never verify.
+pub fn verify(writers: &[[u8; KEY_SIZE]], reply_to: &TweetId, header: &ContentHeader) -> bool {
+    let Some(signature) = header
+        .signature
+        .as_deref()
+        .and_then(|s| Signature::from_slice(s).ok())
+    else {
+        return false;
+    };
+
+    let message = message(reply_to, header);
+    writers
+        .iter()
+        .filter_map(|key| VerifyingKey::from_bytes(key).ok())
+        .any(|key| key.verify_strict(&message, &signature).is_ok())
+}
+
+/// Render the root tweet line publishing `writers`
+pub fn encode_writers(writers: &[[u8; KEY_SIZE]]) -> String {
+    let keys: Vec<String> = writers.iter().map(|key| encode_base64(key)).collect();
+    format!("{}{}", WRITERS_PREFIX, keys.join(" "))
+}
+
+/// Parse a root tweet line publishing writer keys
+///
+/// Returns `None` if the line does not list writers. Malformed keys are
+/// skipped, as they could never verify a signature anyway.
+pub fn decode_writers(line: &str) -> Option<Vec<[u8; KEY_SIZE]>> {
+    let keys = line.strip_prefix(WRITERS_PREFIX)?;
+    Some(
+        keys.split(' ')
+            .filter_map(|key| decode_base64(key).ok())
+            .filter_map(|key| <[u8; KEY_SIZE]>::try_from(key).ok())
+            .collect(),
+    )
+}
+
+/// The message signed for a commit
+fn message(reply_to: &TweetId, header: &ContentHeader) -> Vec<u8> {
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        SIGNING_CONTEXT,
+        reply_to,
+        header.parents.join(","),
+        header.size,
+        header.hash,
+        header.mime
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::hash::compute_hash;
+
+    fn header() -> ContentHeader {
+        ContentHeader::new("text/plain", 5, compute_hash(b"hello"))
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = Signer::new([7; KEY_SIZE]);
+        let mut header = header();
+        header.signature = Some(signer.sign(&"100".to_string(), &header));
+        assert_eq!(header.signature.as_ref().unwrap().len(), SIGNATURE_SIZE);
+
+        assert!(verify(signer.writers(), &"100".to_string(), &header));
+
+        // Replayed elsewhere, altered, or checked against other keys
+        assert!(!verify(signer.writers(), &"101".to_string(), &header));
+        let mut altered = header.clone();
+        altered.hash = compute_hash(b"world");
+        assert!(!verify(signer.writers(), &"100".to_string(), &altered));
+        let other = Signer::new([8; KEY_SIZE]);
+        assert!(!verify(other.writers(), &"100".to_string(), &header));
+
+        // Unsigned envelopes never verify
+        assert!(!verify(signer.writers(), &"100".to_string(), &self::header()));
+    }
+
+    #[test]
+    fn test_writers_line() {
+        let mut signer = Signer::new([1; KEY_SIZE]);
+        signer.add_writer(Signer::new([2; KEY_SIZE]).public_key());
+        signer.add_writer(signer.public_key());
+        assert_eq!(signer.writers().len(), 2);
+
+        let line = encode_writers(signer.writers());
+        assert!(line.starts_with(WRITERS_PREFIX));
+        assert_eq!(decode_writers(&line).unwrap(), signer.writers());
+        assert_eq!(decode_writers("🔑 not-a-key").unwrap(), Vec::<[u8; KEY_SIZE]>::new());
+        assert!(decode_writers("notes.md").is_none());
+    }
+
+    #[test]
+    fn test_debug_hides_secret() {
+        let debug = format!("{:?}", Signer::new([0xAB; KEY_SIZE]));
+        assert!(!debug.contains("171"));
+        assert!(debug.contains("public_key"));
+    }
+}
@@ -183,3 +183,930 @@ async fn test_open_nonexistent_file_fails() {
     let result = fs.open("nonexistent.txt", OpenMode::ReadOnly).await;
     assert!(result.is_err());
 }
+
+#[tokio::test]
+async fn test_chunked_read_after_cache_miss() {
+    use xfiles::store::{ContentCache, SqliteStore};
+
+    let adapter = Arc::new(MockAdapter::new());
+    let store = SqliteStore::new("sqlite://:memory:").await.unwrap();
+    store.init_schema().await.unwrap();
+    let store = Arc::new(store);
+
+    let root = adapter.store(b"root").await.unwrap();
+    let mut writer = XFile::new(
+        "chunked.txt".to_string(),
+        root,
+        store.clone(),
+        adapter.clone(),
+        Arc::new(ContentCache::new()),
+        "testuser".to_string(),
+    );
+
+    let large_content: Vec<u8> = (0..2048).map(|i| b'a' + (i % 26) as u8).collect();
+    writer.write(&large_content).await.unwrap();
+
+    // A fresh cache forces the content to be reassembled from the manifest
+    let reader = XFile::new(
+        "chunked.txt".to_string(),
+        writer.head().clone(),
+        store.clone(),
+        adapter.clone(),
+        Arc::new(ContentCache::new()),
+        "testuser".to_string(),
+    );
+    let read_content = reader.read().await.unwrap();
+    assert_eq!(read_content, large_content);
+
+    let manifest = store.get_chunks(writer.head()).await.unwrap();
+    assert!(manifest.len() >= large_content.len().div_ceil(TWEET_MAX_SIZE));
+    assert!(manifest.iter().all(|chunk| chunk.size <= TWEET_MAX_SIZE));
+    assert_eq!(manifest[0].id, *writer.head());
+}
+
+#[tokio::test]
+async fn test_read_at_revision() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter, Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut file = fs.open("beliefs.txt", OpenMode::Create).await.unwrap();
+    file.write(b"Belief 1").await.unwrap();
+    let first = file.head().clone();
+    file.write(b"Belief 2").await.unwrap();
+    file.write(b"Belief 3").await.unwrap();
+
+    assert_eq!(fs.read_at("beliefs.txt", Revision::Back(0)).await.unwrap(), b"Belief 3");
+    assert_eq!(fs.read_at("beliefs.txt", Revision::Back(1)).await.unwrap(), b"Belief 2");
+    assert_eq!(
+        fs.read_at("beliefs.txt", Revision::Commit(first)).await.unwrap(),
+        b"Belief 1"
+    );
+    assert_eq!(
+        fs.read_at("beliefs.txt", Revision::At(chrono::Utc::now())).await.unwrap(),
+        b"Belief 3"
+    );
+    assert_eq!(file.read_at(&Revision::Back(2)).await.unwrap(), b"Belief 1");
+
+    // Before the file existed / beyond the root
+    let past = chrono::Utc::now() - chrono::Duration::days(1);
+    assert!(fs.read_at("beliefs.txt", Revision::At(past)).await.is_err());
+    assert!(fs.read_at("beliefs.txt", Revision::Back(10)).await.is_err());
+}
+
+#[tokio::test]
+async fn test_diff_revisions() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter, Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut file = fs.open("notes.md", OpenMode::Create).await.unwrap();
+    file.write(b"goal: explore\nmood: curious\n").await.unwrap();
+    file.write(b"goal: explore\nmood: focused\n").await.unwrap();
+
+    let ops = fs.diff("notes.md", Revision::Back(1), Revision::Back(0)).await.unwrap();
+    let old = fs.read_at("notes.md", Revision::Back(1)).await.unwrap();
+    let new = file.read().await.unwrap();
+    assert_eq!(xfiles::dag::diff::apply_diff(&old, &ops).unwrap(), new);
+
+    let rendered = xfiles::dag::diff::unified_diff(&ops, "a/notes.md", "b/notes.md", 3);
+    assert!(rendered.contains("-mood: curious\n+mood: focused\n"));
+}
+
+#[tokio::test]
+async fn test_delta_writes() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    fs.set_write_options(WriteOptions {
+        snapshot_interval: Some(2),
+        ..Default::default()
+    });
+
+    let mut file = fs.open("agent.log", OpenMode::Create).await.unwrap();
+    let mut log = String::new();
+    let mut versions = Vec::new();
+    for i in 0..6 {
+        log.push_str(&format!("[{}] agent step completed with a fairly long status line\n", i));
+        file.write(log.as_bytes()).await.unwrap();
+        versions.push(log.clone());
+    }
+
+    // snapshot, delta, delta, snapshot, delta, delta
+    let history = fs.history("agent.log").await.unwrap();
+    let kinds: Vec<bool> = history
+        .iter()
+        .skip(1)
+        .map(|c| c.mime.starts_with("application/x-xfiles-delta"))
+        .collect();
+    assert_eq!(kinds, vec![false, true, true, false, true, true]);
+    assert!(history.iter().skip(1).all(|c| c.size > 0));
+
+    // Rebuild every revision by replaying from the closest snapshot
+    for (back, expected) in versions.iter().rev().enumerate() {
+        fs.clear_cache();
+        let content = fs.read_at("agent.log", Revision::Back(back)).await.unwrap();
+        assert_eq!(content, expected.as_bytes());
+    }
+}
+
+#[tokio::test]
+async fn test_merge_forked_file() {
+    use xfiles::fs::merge::ThreeWayMerge;
+
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter, Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut file = fs.open("plan.txt", OpenMode::Create).await.unwrap();
+    file.write(b"step 1\nstep 2\nstep 3\n").await.unwrap();
+    drop(file);
+
+    // Two writers start from the same head and fork the file
+    let mut left = fs.open("plan.txt", OpenMode::ReadWrite).await.unwrap();
+    let mut right = fs.open("plan.txt", OpenMode::ReadWrite).await.unwrap();
+    left.write(b"step 1 (done)\nstep 2\nstep 3\n").await.unwrap();
+    right.write(b"step 1\nstep 2\nstep 3 (done)\n").await.unwrap();
+
+    let head = fs.merge("plan.txt", &ThreeWayMerge).await.unwrap();
+
+    let history = fs.history("plan.txt").await.unwrap();
+    let merge_commit = history.iter().find(|c| c.id == head).unwrap();
+    assert_eq!(merge_commit.parents.len(), 2);
+    assert!(merge_commit.parents.contains(left.head()));
+    assert!(merge_commit.parents.contains(right.head()));
+
+    let merged = fs.open("plan.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(merged.head(), &head);
+    assert_eq!(merged.read().await.unwrap(), b"step 1 (done)\nstep 2\nstep 3 (done)\n");
+}
+
+#[tokio::test]
+async fn test_recover_index_from_timeline() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut notes = fs.open("notes.txt", OpenMode::Create).await.unwrap();
+    notes.write(b"first").await.unwrap();
+    notes.write(b"second").await.unwrap();
+
+    let large = "x".repeat(700);
+    let mut log = fs.open("logs/agent.log", OpenMode::Create).await.unwrap();
+    log.write(large.as_bytes()).await.unwrap();
+
+    // Unrelated tweets on the timeline are ignored
+    adapter.store(b"just a regular tweet").await.unwrap();
+
+    // A fresh index knows nothing until recovery runs
+    let mut fresh = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    assert!(fresh.list("/").await.unwrap().is_empty());
+
+    let report = fresh.recover().await.unwrap();
+    assert_eq!(report.files, vec!["logs/agent.log", "notes.txt"]);
+    assert_eq!(report.commits, 5);
+    assert!(report.unresolved.is_empty());
+
+    let notes = fresh.open("notes.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(notes.read().await.unwrap(), b"second");
+    assert_eq!(fresh.history("notes.txt").await.unwrap().len(), 3);
+
+    let log = fresh.open("logs/agent.log", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(log.read().await.unwrap(), large.as_bytes());
+
+    // Running it again finds nothing new
+    let report = fresh.recover().await.unwrap();
+    assert_eq!(report.commits, 0);
+}
+
+#[tokio::test]
+async fn test_recover_reports_missing_chunks() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut file = fs.open("big.txt", OpenMode::Create).await.unwrap();
+    file.write("y".repeat(700).as_bytes()).await.unwrap();
+    let commit_id = file.head().clone();
+
+    // Delete the last chunk of the commit
+    let last_chunk = adapter.get_replies(&adapter.get_replies(&commit_id)[0])[0].clone();
+    assert!(adapter.delete_tweet(&last_chunk));
+
+    let fresh = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    let report = fresh.recover().await.unwrap();
+
+    assert_eq!(report.files, vec!["big.txt"]);
+    let unresolved: Vec<_> = report.unresolved.iter().map(|u| u.id.clone()).collect();
+    assert!(unresolved.contains(&commit_id));
+}
+
+#[tokio::test]
+async fn test_commit_envelope_on_wire() {
+    use xfiles::store::{ContentCache, SqliteStore};
+    use xfiles::util::encoding::decode_with_header;
+    use xfiles::util::hash::compute_hash;
+
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let content: Vec<u8> = (0..1000).map(|i| b'a' + (i % 26) as u8).collect();
+    let mut file = fs.open("wire.txt", OpenMode::Create).await.unwrap();
+    file.write(&content).await.unwrap();
+
+    // Every chunk tweet describes the commit it belongs to
+    let head_tweet = adapter.get_tweet(file.head()).unwrap();
+    let (header, _) = decode_with_header(head_tweet.text.as_bytes()).unwrap();
+    let header = header.unwrap();
+    assert_eq!(header.hash, compute_hash(&content));
+    assert_eq!(header.mime, "text/plain");
+    assert_eq!(header.size, content.len());
+    assert_eq!(header.index, 0);
+    assert!(header.count > 1);
+
+    // A reader without the commit in its index follows the envelopes
+    let store = SqliteStore::new("sqlite://:memory:").await.unwrap();
+    store.init_schema().await.unwrap();
+    let reader = XFile::new(
+        "wire.txt".to_string(),
+        file.head().clone(),
+        Arc::new(store),
+        adapter.clone(),
+        Arc::new(ContentCache::new()),
+        "testuser".to_string(),
+    );
+    assert_eq!(reader.read().await.unwrap(), content);
+}
+
+#[tokio::test]
+async fn test_recover_envelope_metadata() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    fs.set_write_options(WriteOptions {
+        mime: "text/markdown".to_string(),
+        snapshot_interval: Some(4),
+        ..Default::default()
+    });
+
+    let base = "# Notes\n".to_string() + &"line\n".repeat(40);
+    let mut file = fs.open("notes.md", OpenMode::Create).await.unwrap();
+    file.write(base.as_bytes()).await.unwrap();
+    file.write((base.clone() + "more\n").as_bytes()).await.unwrap();
+
+    // Fork and merge so a commit has two parents
+    let mut left = fs.open("notes.md", OpenMode::ReadWrite).await.unwrap();
+    let mut right = fs.open("notes.md", OpenMode::ReadWrite).await.unwrap();
+    left.write(("# Left\n".to_string() + &base[8..] + "more\n").as_bytes()).await.unwrap();
+    right.write((base.clone() + "more\nright\n").as_bytes()).await.unwrap();
+    fs.merge("notes.md", &xfiles::fs::merge::ThreeWayMerge).await.unwrap();
+
+    let original = fs.history("notes.md").await.unwrap();
+    let expected = fs.open("notes.md", OpenMode::ReadOnly).await.unwrap().read().await.unwrap();
+
+    // Replies written before the envelope existed are still readable
+    let head = fs.open("notes.md", OpenMode::ReadOnly).await.unwrap().head().clone();
+    adapter.store_reply(&head, b"legacy raw reply").await.unwrap();
+
+    let mut fresh = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    let report = fresh.recover().await.unwrap();
+    assert!(report.unresolved.is_empty(), "{:?}", report.unresolved);
+
+    let recovered = fresh.history("notes.md").await.unwrap();
+    assert_eq!(recovered.len(), original.len() + 1);
+    for commit in &original {
+        let copy = recovered.iter().find(|c| c.id == commit.id).unwrap();
+        assert_eq!(copy.parents, commit.parents);
+        assert_eq!(copy.hash, commit.hash);
+        assert_eq!(copy.mime, commit.mime);
+        assert_eq!(copy.size, commit.size);
+    }
+
+    let file = fresh.open("notes.md", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), b"legacy raw reply");
+    let merged = fresh.read_at("notes.md", Revision::Commit(head)).await.unwrap();
+    assert_eq!(merged, expected);
+}
+
+#[tokio::test]
+async fn test_binary_content_codecs() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let binary: Vec<u8> = (0..2000u32).map(|i| (i * 7919 % 256) as u8).collect();
+    for codec in [TextCodec::Base64, TextCodec::Base85, TextCodec::Base2048] {
+        fs.set_write_options(WriteOptions {
+            mime: "application/octet-stream".to_string(),
+            codec,
+            ..Default::default()
+        });
+        let path = format!("blob-{}.bin", codec.name());
+        let mut file = fs.open(&path, OpenMode::Create).await.unwrap();
+        file.write(&binary).await.unwrap();
+
+        fs.clear_cache();
+        let file = fs.open(&path, OpenMode::ReadOnly).await.unwrap();
+        assert_eq!(file.read().await.unwrap(), binary);
+
+        let history = fs.history(&path).await.unwrap();
+        assert_eq!(history.last().unwrap().encoding, Some(codec));
+    }
+
+    // Text is posted as is, unless it is not valid UTF-8
+    fs.set_write_options(WriteOptions::default());
+    let mut file = fs.open("text.txt", OpenMode::Create).await.unwrap();
+    file.write(b"plain text").await.unwrap();
+    file.write([0x66, 0x6f, 0xff, 0x6f]).await.unwrap();
+
+    let history = fs.history("text.txt").await.unwrap();
+    assert_eq!(history[1].encoding, None);
+    assert_eq!(history[2].encoding, Some(TextCodec::Base2048));
+
+    fs.clear_cache();
+    let file = fs.open("text.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), [0x66, 0x6f, 0xff, 0x6f]);
+
+    // Every posted tweet is valid text that the real adapter accepts
+    for tweet in adapter.fetch_timeline().await.unwrap() {
+        let posted = adapter.fetch(&tweet.id).await.unwrap();
+        assert!(std::str::from_utf8(&posted).is_ok());
+    }
+}
+
+#[tokio::test]
+async fn test_weighted_chunking() {
+    use xfiles::fs::chunk::weighted_length;
+
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    // Double-weight characters and multi-code-point graphemes
+    let content = "日本語のテキスト 👨‍👩‍👧 🇸🇪 cafe\u{301}\n".repeat(40);
+    let mut file = fs.open("unicode.txt", OpenMode::Create).await.unwrap();
+    file.write(content.as_bytes()).await.unwrap();
+
+    for tweet in adapter.fetch_timeline().await.unwrap() {
+        assert!(weighted_length(&tweet.text) <= TWEET_MAX_SIZE);
+    }
+
+    fs.clear_cache();
+    let file = fs.open("unicode.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), content.as_bytes());
+}
+
+#[tokio::test]
+async fn test_content_defined_dedup() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    fs.set_write_options(WriteOptions {
+        chunking: Chunking::ContentDefined,
+        ..Default::default()
+    });
+
+    let mut file = fs.open("agent.log", OpenMode::Create).await.unwrap();
+    let mut log = String::new();
+    for i in 0..200 {
+        log.push_str(&format!("[{:03}] step finished, observed value {}\n", i, i * 7919 % 1000));
+    }
+    file.write(log.as_bytes()).await.unwrap();
+
+    // Appending only posts the blocks at the end, plus the block list
+    let before = adapter.fetch_timeline().await.unwrap().len();
+    log.push_str("[200] one more line\n");
+    file.write(log.as_bytes()).await.unwrap();
+    let posted = adapter.fetch_timeline().await.unwrap().len() - before;
+    let full = xfiles::fs::chunk::chunk_content(log.as_bytes()).unwrap().len();
+    assert!(posted < full / 4, "posted {} tweets, a full copy takes {}", posted, full);
+
+    fs.clear_cache();
+    let file = fs.open("agent.log", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), log.as_bytes());
+
+    // Recovery finds the blocks, and later writes keep sharing them
+    let mut fresh = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    let report = fresh.recover().await.unwrap();
+    assert!(report.unresolved.is_empty(), "{:?}", report.unresolved);
+    assert_eq!(fresh.history("agent.log").await.unwrap().len(), 3);
+
+    fresh.set_write_options(WriteOptions {
+        chunking: Chunking::ContentDefined,
+        ..Default::default()
+    });
+    let mut file = fresh.open("agent.log", OpenMode::ReadWrite).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), log.as_bytes());
+    let before = adapter.fetch_timeline().await.unwrap().len();
+    log.push_str("[201] and another\n");
+    file.write(log.as_bytes()).await.unwrap();
+    assert!(adapter.fetch_timeline().await.unwrap().len() - before < full / 4);
+}
+
+#[tokio::test]
+async fn test_compressed_writes() {
+    use xfiles::util::encoding::decode_with_header;
+
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let state: String = (0..40)
+        .map(|i| format!(r#"{{"step":{},"goal":"explore","status":"done"}}"#, i))
+        .collect::<Vec<_>>()
+        .join(",");
+    let state = format!("[{}]", state);
+
+    // Uncompressed baseline
+    fs.set_write_options(WriteOptions {
+        mime: "application/json".to_string(),
+        ..Default::default()
+    });
+    let before = adapter.fetch_timeline().await.unwrap().len();
+    let mut file = fs.open("plain.json", OpenMode::Create).await.unwrap();
+    file.write(state.as_bytes()).await.unwrap();
+    let plain = adapter.fetch_timeline().await.unwrap().len() - before;
+
+    for compression in [Compression::Zstd, Compression::Deflate, Compression::Brotli] {
+        fs.set_write_options(WriteOptions {
+            mime: "application/json".to_string(),
+            compression: Some(compression),
+            ..Default::default()
+        });
+        let path = format!("state-{}.json", compression.name());
+        let before = adapter.fetch_timeline().await.unwrap().len();
+        let mut file = fs.open(&path, OpenMode::Create).await.unwrap();
+        file.write(state.as_bytes()).await.unwrap();
+        let posted = adapter.fetch_timeline().await.unwrap().len() - before;
+        assert!(posted < plain, "{} took {} tweets, plain {}", compression.name(), posted, plain);
+
+        let tweet = adapter.get_tweet(file.head()).unwrap();
+        let (header, _) = decode_with_header(tweet.text.as_bytes()).unwrap();
+        assert_eq!(header.unwrap().compression, Some(compression));
+
+        // Small writes are not worth compressing
+        file.write(b"[]").await.unwrap();
+        let tweet = adapter.get_tweet(file.head()).unwrap();
+        let (header, _) = decode_with_header(tweet.text.as_bytes()).unwrap();
+        assert_eq!(header.unwrap().compression, None);
+
+        fs.clear_cache();
+        let state_at = fs.read_at(&path, Revision::Back(1)).await.unwrap();
+        assert_eq!(state_at, state.as_bytes());
+    }
+
+    // Recovery decompresses to check content hashes
+    let fresh = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    let report = fresh.recover().await.unwrap();
+    assert!(report.unresolved.is_empty(), "{:?}", report.unresolved);
+    let state_at = fresh.read_at("state-zstd.json", Revision::Back(1)).await.unwrap();
+    assert_eq!(state_at, state.as_bytes());
+}
+
+#[tokio::test]
+async fn test_encrypted_files() {
+    use xfiles::error::XFilesError;
+    use xfiles::util::encoding::decode_with_header;
+    use xfiles::util::hash::compute_hash;
+
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    fs.set_keyring(Keyring::new(1, [1; 32]));
+    fs.set_write_options(WriteOptions {
+        encrypt: true,
+        snapshot_interval: Some(3),
+        ..Default::default()
+    });
+
+    let secret = "the launch code is 0000\n".repeat(20);
+    let mut file = fs.open("private.txt", OpenMode::Create).await.unwrap();
+    file.write(secret.as_bytes()).await.unwrap();
+    file.write((secret.clone() + "and a delta\n").as_bytes()).await.unwrap();
+
+    // Nothing on the wire gives the content away, not even its hash
+    for tweet in adapter.fetch_timeline().await.unwrap() {
+        assert!(!tweet.text.contains("launch"));
+    }
+    let (header, _) = decode_with_header(adapter.get_tweet(file.head()).unwrap().text.as_bytes()).unwrap();
+    assert_eq!(header.unwrap().key, Some(1));
+    let history = fs.history("private.txt").await.unwrap();
+    assert_ne!(history[1].hash, compute_hash(secret.as_bytes()));
+
+    fs.clear_cache();
+    let first = fs.read_at("private.txt", Revision::Back(1)).await.unwrap();
+    assert_eq!(first, secret.as_bytes());
+
+    // Without the right keys, reads fail with a decryption error
+    let mut other = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    other.recover().await.unwrap();
+    let result = other.open("private.txt", OpenMode::ReadOnly).await.unwrap().read().await;
+    assert!(matches!(result, Err(XFilesError::Decryption(_))));
+    other.set_keyring(Keyring::new(1, [2; 32]));
+    let result = other.open("private.txt", OpenMode::ReadOnly).await.unwrap().read().await;
+    assert!(matches!(result, Err(XFilesError::Decryption(_))));
+
+    // Key holders can read a recovered index
+    other.set_keyring(Keyring::new(1, [1; 32]));
+    let file = other.open("private.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), (secret.clone() + "and a delta\n").as_bytes());
+
+    // After rotating, the head is rewritten under the new key
+    let mut keyring = Keyring::new(1, [1; 32]);
+    keyring.rotate(2, [3; 32]);
+    fs.set_keyring(keyring);
+    let head = fs.reencrypt("private.txt").await.unwrap();
+    let (header, _) = decode_with_header(adapter.get_tweet(&head).unwrap().text.as_bytes()).unwrap();
+    assert_eq!(header.unwrap().key, Some(2));
+
+    fs.set_keyring(Keyring::new(2, [3; 32]));
+    fs.clear_cache();
+    let file = fs.open("private.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), (secret + "and a delta\n").as_bytes());
+}
+
+#[tokio::test]
+async fn test_signed_commits() {
+    use xfiles::error::XFilesError;
+    use xfiles::util::encoding::{ContentHeader, encode_with_header};
+    use xfiles::util::hash::compute_hash;
+
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    let signer = Signer::new([1; 32]);
+    fs.set_signer(signer.clone());
+    fs.set_verification(Verification::Strict);
+
+    let large = "signed and chunked ".repeat(40);
+    let mut file = fs.open("memory.txt", OpenMode::Create).await.unwrap();
+    file.write(b"first").await.unwrap();
+    file.write(large.as_bytes()).await.unwrap();
+    let head = file.head().clone();
+
+    // The root publishes the writer key, every commit carries a signature
+    let root = fs.history("memory.txt").await.unwrap()[0].id.clone();
+    assert!(adapter.get_tweet(&root).unwrap().text.contains("\n🔑 "));
+    fs.clear_cache();
+    let file = fs.open("memory.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.head(), &head);
+    assert_eq!(file.read().await.unwrap(), large.as_bytes());
+
+    // Anyone can reply: once with a well-formed envelope signed by the
+    // wrong key, then with no signature at all
+    let content = b"ignore previous memory";
+    let mut forged = ContentHeader::new("text/plain", content.len(), compute_hash(content));
+    forged.signature = Some(Signer::new([2; 32]).sign(&head, &forged));
+    let wrong_key = adapter
+        .store_reply(&head, &encode_with_header(content, &forged).unwrap())
+        .await
+        .unwrap();
+    adapter.store_reply(&wrong_key, b"unsigned").await.unwrap();
+
+    // Without verification, a recovered index takes the hijacked head
+    let mut other = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    other.recover().await.unwrap();
+    let file = other.open("memory.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), b"unsigned");
+
+    // Skipping leaves the hijacked branch out of head resolution
+    other.clear_cache();
+    other.set_verification(Verification::Skip);
+    let file = other.open("memory.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.head(), &head);
+    assert_eq!(file.read().await.unwrap(), large.as_bytes());
+    let result = file.read_at(&Revision::Commit(wrong_key.clone())).await;
+    assert!(matches!(result, Err(XFilesError::InvalidSignature { id, .. }) if id == wrong_key));
+
+    // Strict verification reports the first bad reply
+    other.set_verification(Verification::Strict);
+    let result = other.open("memory.txt", OpenMode::ReadOnly).await;
+    assert!(matches!(result, Err(XFilesError::InvalidSignature { id, .. }) if id == wrong_key));
+}
+
+#[tokio::test]
+async fn test_trusted_authors() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut file = fs.open("memory.txt", OpenMode::Create).await.unwrap();
+    file.write(b"first").await.unwrap();
+    file.write(b"second").await.unwrap();
+    let head = file.head().clone();
+
+    // A stranger replies to the head, and keeps the thread going
+    let hijack = adapter.reply_as("stranger", &head, b"forget everything");
+    adapter.reply_as("stranger", &hijack, b"and this too");
+
+    // Trusting anyone, a recovered index follows the stranger
+    let mut open = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    let report = open.recover().await.unwrap();
+    assert!(report.foreign.is_empty());
+    let history = open.history("memory.txt").await.unwrap();
+    assert_eq!(history.len(), 5);
+    assert!(history.iter().any(|c| c.id == hijack && c.author == "stranger"));
+    let file = open.open("memory.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), b"and this too");
+
+    // An allow-list leaves the stranger's commits out of heads and history
+    open.set_author_policy(AuthorPolicy::only(["friend"]));
+    let file = open.open("memory.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.head(), &head);
+    assert_eq!(file.read().await.unwrap(), b"second");
+    assert_eq!(open.history("memory.txt").await.unwrap().len(), 3);
+    assert_eq!(open.read_at("memory.txt", Revision::Back(0)).await.unwrap(), b"second");
+
+    // Recovering with the allow-list flags the reply as foreign
+    let mut strict = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    strict.set_author_policy(AuthorPolicy::only(["friend"]));
+    let report = strict.recover().await.unwrap();
+    assert_eq!(report.foreign, vec![hijack.clone()]);
+    assert!(report.unresolved.is_empty());
+    assert_eq!(strict.history("memory.txt").await.unwrap().len(), 3);
+
+    // Trusting the author for this file only brings the commits back
+    open.set_author_policy(AuthorPolicy::only(["friend"]).with_file("memory.txt", ["stranger"]));
+    let file = open.open("memory.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), b"and this too");
+}
+
+#[tokio::test]
+async fn test_verify_file() {
+    use xfiles::error::XFilesError;
+    use xfiles::fs::verify::Damage;
+
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    fs.set_write_options(WriteOptions {
+        snapshot_interval: Some(5),
+        ..Default::default()
+    });
+
+    let large = "every chunk is checked\n".repeat(30);
+    let mut file = fs.open("log.txt", OpenMode::Create).await.unwrap();
+    file.write(b"small").await.unwrap();
+    let small = file.head().clone();
+    file.write(large.as_bytes()).await.unwrap();
+    let chunked = file.head().clone();
+    file.write((large.clone() + "one more line\n").as_bytes()).await.unwrap();
+    let delta = file.head().clone();
+
+    let report = fs.verify("log.txt").await.unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.checked, 4);
+
+    // Edit the second chunk of the large commit
+    let second = adapter.get_replies(&chunked)
+        .into_iter()
+        .find(|id| id != &delta)
+        .unwrap();
+    assert!(adapter.edit_tweet(&second, b"edited"));
+
+    // Reads past the cache catch the edit with a typed error
+    fs.clear_cache();
+    let result = fs.read_at("log.txt", Revision::Commit(chunked.clone())).await;
+    assert!(matches!(result, Err(XFilesError::HashMismatch { .. })));
+
+    // Verification reports the edit, the delta built on it, and a deletion
+    assert!(adapter.delete_tweet(&small));
+    let report = fs.verify("log.txt").await.unwrap();
+    assert_eq!(report.checked, 4);
+    let damage: Vec<_> = report.damaged.iter().map(|d| (d.commit.clone(), d.damage.clone())).collect();
+    assert!(matches!(&damage[0], (id, Damage::Missing { tweet, .. }) if id == &small && tweet == &small));
+    assert_eq!(damage[1], (chunked.clone(), Damage::Edited { tweet: second }));
+    assert_eq!(damage[2], (delta, Damage::BrokenBase { base: chunked }));
+}
+
+#[tokio::test]
+async fn test_fsck_and_repair() {
+    use xfiles::fs::fsck::Problem;
+
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut file = fs.open("kept.txt", OpenMode::Create).await.unwrap();
+    file.write(b"first").await.unwrap();
+    let first = file.head().clone();
+    file.write(b"second").await.unwrap();
+    let second = file.head().clone();
+
+    let mut lost = fs.open("lost.txt", OpenMode::Create).await.unwrap();
+    let lost_root = lost.head().clone();
+    lost.write(b"gone with its root").await.unwrap();
+    let lost_commit = lost.head().clone();
+
+    let report = fs.fsck().await.unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.files, 2);
+    assert_eq!(report.commits, 5);
+
+    assert!(adapter.delete_tweet(&lost_root));
+    assert!(adapter.delete_tweet(&first));
+
+    // Checking alone changes nothing
+    let report = fs.fsck().await.unwrap();
+    assert!(report.findings.iter().all(|f| !f.repaired));
+    assert!(report.findings.iter().any(|f| matches!(
+        &f.problem,
+        Problem::DeletedRoot { path, root, .. } if path == "lost.txt" && root == &lost_root
+    )));
+    assert!(report.findings.iter().any(|f| matches!(
+        &f.problem,
+        Problem::DeletedTweet { commit, tweet, .. } if commit == &first && tweet == &first
+    )));
+    assert_eq!(fs.fsck().await.unwrap().findings, report.findings);
+
+    // Repair drops the lost file and clears the flags, but keeps the
+    // deleted commit the rest of the history hangs from
+    let report = fs.repair().await.unwrap();
+    assert!(report.findings.iter().any(|f| f.repaired && matches!(
+        &f.problem,
+        Problem::OrphanCommit { commit } if commit == &lost_commit
+    )));
+    let left: Vec<_> = report.unrepaired().cloned().collect();
+    assert!(matches!(&left[..], [Problem::DeletedTweet { commit, .. }] if commit == &first));
+
+    assert!(!fs.exists("lost.txt").await.unwrap());
+    assert_eq!(fs.history("kept.txt").await.unwrap().len(), 3);
+    let content = fs.read_at("kept.txt", Revision::Commit(second)).await.unwrap();
+    assert_eq!(content, b"second");
+
+    let report = fs.fsck().await.unwrap();
+    assert_eq!(report.files, 1);
+    assert_eq!(report.commits, 3);
+    assert_eq!(report.findings.len(), 1);
+}
+
+#[tokio::test]
+async fn test_heads_resolve_locally() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut file = fs.open("notes.txt", OpenMode::Create).await.unwrap();
+    file.write(b"first").await.unwrap();
+    file.write(b"second").await.unwrap();
+    let second = file.head().clone();
+
+    // A reply the index does not know about yet
+    let reply = adapter.reply_as("friend", &second, b"from a friend");
+    let file = fs.open("notes.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.head(), &second);
+    assert_eq!(fs.refresh("notes.txt").await.unwrap(), second);
+
+    // Recovery indexes it, and the next open resolves the head again
+    let report = fs.recover().await.unwrap();
+    assert_eq!(report.commits, 1);
+    let file = fs.open("notes.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.head(), &reply);
+    assert_eq!(file.read().await.unwrap(), b"from a friend");
+
+    // Only the new head carries a head flag
+    let report = fs.fsck().await.unwrap();
+    assert!(report.is_ok(), "{:?}", report.findings);
+    assert_eq!(report.commits, 4);
+}
+
+#[tokio::test]
+async fn test_duplicate_content_retried_with_nonce() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    // Identical consecutive writes post identical tweets
+    let mut file = fs.open("status.txt", OpenMode::Create).await.unwrap();
+    let first_root = file.head().clone();
+    file.write(b"ok").await.unwrap();
+    let first = file.head().clone();
+    file.write(b"ok").await.unwrap();
+    let second = file.head().clone();
+    assert_ne!(first, second);
+
+    let tweet = adapter.get_tweet(&second).unwrap();
+    assert!(tweet.text.lines().next().unwrap().contains(" n=1 "));
+
+    let file = fs.open("status.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), b"ok");
+    assert_eq!(fs.read_at("status.txt", Revision::Commit(first)).await.unwrap(), b"ok");
+    assert!(fs.verify("status.txt").await.unwrap().is_ok());
+
+    // Another index re-creating the file later posts an identical root
+    assert!(adapter.backdate_tweet(&first_root, chrono::Duration::minutes(1)));
+    let mut other = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    let mut file = other.open("status.txt", OpenMode::Create).await.unwrap();
+    let root = adapter.get_tweet(file.head()).unwrap();
+    assert_eq!(root.text, "📁 status.txt\nn=1");
+    file.write(b"ok").await.unwrap();
+    let file = other.open("status.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), b"ok");
+
+    // Recovery still reads the marked root as one of the file
+    let mut recovered = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    let report = recovered.recover().await.unwrap();
+    assert!(report.files.contains(&"status.txt".to_string()));
+    let root_id = root.id.clone();
+    assert!(report.unresolved.iter().any(|u| u.id == root_id && u.reason.contains("already has root")));
+    let file = recovered.open("status.txt", OpenMode::ReadOnly).await.unwrap();
+    assert_eq!(file.read().await.unwrap(), b"ok");
+    assert!(fs.fsck().await.unwrap().is_ok());
+    assert!(other.fsck().await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn test_duplicate_of_lost_response_reused() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut file = fs.open("notes.txt", OpenMode::Create).await.unwrap();
+    let root = file.head().clone();
+
+    // The post goes through, but its retry is told it is a duplicate
+    adapter.lose_next_response();
+    file.write(b"hello").await.unwrap();
+    let replies = adapter.get_replies(&root);
+    assert_eq!(replies, vec![file.head().clone()]);
+    assert!(!adapter.get_tweet(file.head()).unwrap().text.contains(" n="));
+    assert_eq!(file.read().await.unwrap(), b"hello");
+
+    // The same goes for a root
+    adapter.lose_next_response();
+    let file = fs.open("other.txt", OpenMode::Create).await.unwrap();
+    let root = adapter.get_tweet(file.head()).unwrap();
+    assert_eq!(root.text, "📁 other.txt");
+    let timeline = adapter.fetch_timeline().await.unwrap();
+    assert_eq!(timeline.iter().filter(|t| t.text.starts_with("📁 other.txt")).count(), 1);
+
+    // Recovery finds a single line of history, not a fork
+    let fresh = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+    let report = fresh.recover().await.unwrap();
+    assert!(report.unresolved.is_empty(), "{:?}", report.unresolved);
+    assert_eq!(fresh.history("notes.txt").await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_head_resolution_lists_thread_once() {
+    let adapter = Arc::new(MockAdapter::new());
+    let mut fs = XFS::with_adapter("testuser", adapter.clone(), Some(":memory:"))
+        .await
+        .unwrap();
+
+    let mut file = fs.open("log.txt", OpenMode::Create).await.unwrap();
+    for i in 0..20 {
+        file.write(format!("entry {}", i).as_bytes()).await.unwrap();
+    }
+    let head = file.head().clone();
+
+    // One listing of the thread, not one per commit
+    let before = adapter.requests();
+    assert_eq!(fs.refresh("log.txt").await.unwrap(), head);
+    assert!(adapter.requests() - before <= 2, "{} requests", adapter.requests() - before);
+}